@@ -0,0 +1,345 @@
+use crate::calc::{Number, Span};
+use std::fmt;
+
+/// An operator before its operand.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum UnaryOp {
+    /// `-n`
+    Neg,
+    /// `+n`
+    Pos,
+    /// `!b`, logical negation.
+    Not,
+    /// `~n`, the bitwise complement.
+    BitNot,
+}
+
+/// An operator after its operand.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PostfixOp {
+    /// `n!`
+    Factorial,
+    /// `n!!`
+    DoubleFactorial,
+    /// `n%`, i.e. `n / 100`
+    Percent,
+}
+
+/// An operator between two operands.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BinaryOp {
+    /// `a + b`
+    Add,
+    /// `a - b`
+    Sub,
+    /// `a * b`
+    Mul,
+    /// `a / b`
+    Div,
+    /// `a % b`, with the sign of `a`.
+    Mod,
+    /// `a ^ b`
+    Pow,
+
+    /// `a == b`
+    Eq,
+    /// `a != b`
+    Ne,
+    /// `a < b`
+    Lt,
+    /// `a <= b`
+    Le,
+    /// `a > b`
+    Gt,
+    /// `a >= b`
+    Ge,
+
+    /// `a && b`, evaluating `b` only if `a` is true.
+    And,
+    /// `a || b`, evaluating `b` only if `a` is false.
+    Or,
+
+    /// `a & b`
+    BitAnd,
+    /// `a | b`
+    BitOr,
+    /// `a xor b`
+    BitXor,
+    /// `a << b`
+    Shl,
+    /// `>>`, keeping the sign.
+    Shr,
+    /// `>>>`, shifting in zeros within the word size.
+    LogicalShr,
+}
+
+impl BinaryOp {
+    fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::Or => 3,
+            BinaryOp::And => 4,
+            BinaryOp::Eq
+            | BinaryOp::Ne
+            | BinaryOp::Lt
+            | BinaryOp::Le
+            | BinaryOp::Gt
+            | BinaryOp::Ge => 6,
+            BinaryOp::BitOr => 7,
+            BinaryOp::BitXor => 8,
+            BinaryOp::BitAnd => 9,
+            BinaryOp::Shl | BinaryOp::Shr | BinaryOp::LogicalShr => 10,
+            BinaryOp::Add | BinaryOp::Sub => 11,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => 12,
+            BinaryOp::Pow => 13,
+        }
+    }
+
+    /// `&`, `|`, `xor` and the shifts, which take integers.
+    pub fn is_bitwise(self) -> bool {
+        (7..=10).contains(&self.precedence())
+    }
+
+    fn is_right_assoc(self) -> bool {
+        self == BinaryOp::Pow
+    }
+
+    /// Comparisons cannot be chained, `1 < 2 < 3` is a syntax error.
+    fn is_non_assoc(self) -> bool {
+        self.precedence() == 6
+    }
+}
+
+impl fmt::Display for UnaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UnaryOp::Neg => write!(f, "-"),
+            UnaryOp::Pos => write!(f, "+"),
+            UnaryOp::Not => write!(f, "!"),
+            UnaryOp::BitNot => write!(f, "~"),
+        }
+    }
+}
+
+impl fmt::Display for PostfixOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PostfixOp::Factorial => write!(f, "!"),
+            PostfixOp::DoubleFactorial => write!(f, "!!"),
+            PostfixOp::Percent => write!(f, "%"),
+        }
+    }
+}
+
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BinaryOp::Add => write!(f, "+"),
+            BinaryOp::Sub => write!(f, "-"),
+            BinaryOp::Mul => write!(f, "*"),
+            BinaryOp::Div => write!(f, "/"),
+            BinaryOp::Mod => write!(f, "%"),
+            BinaryOp::Pow => write!(f, "^"),
+            BinaryOp::Eq => write!(f, "=="),
+            BinaryOp::Ne => write!(f, "!="),
+            BinaryOp::Lt => write!(f, "<"),
+            BinaryOp::Le => write!(f, "<="),
+            BinaryOp::Gt => write!(f, ">"),
+            BinaryOp::Ge => write!(f, ">="),
+            BinaryOp::And => write!(f, "&&"),
+            BinaryOp::Or => write!(f, "||"),
+            BinaryOp::BitAnd => write!(f, "&"),
+            BinaryOp::BitOr => write!(f, "|"),
+            BinaryOp::BitXor => write!(f, "xor"),
+            BinaryOp::Shl => write!(f, "<<"),
+            BinaryOp::Shr => write!(f, ">>"),
+            BinaryOp::LogicalShr => write!(f, ">>>"),
+        }
+    }
+}
+
+/// Expression tree produced by `Parser` and evaluated by `Calculator`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expr {
+    /// What the expression is.
+    pub kind: ExprKind,
+    /// Part of the input the expression was parsed from.
+    pub span: Span,
+}
+
+/// The kinds of expression, with their operands.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExprKind {
+    /// A number as written.
+    Literal(Number),
+    /// A variable or constant.
+    Var(String),
+    /// `op a`
+    Unary(UnaryOp, Box<Expr>),
+    /// `a op`
+    Postfix(PostfixOp, Box<Expr>),
+    /// `a op b`
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    /// `f(a, b)`, a builtin or user-defined function.
+    Call(String, Vec<Expr>),
+    /// `[a, b, c]`, a vector, or a matrix if the elements are vectors.
+    Vector(Vec<Expr>),
+    /// `v[i]` or `m[i, j]`, counting from 0.
+    Index(Box<Expr>, Vec<Expr>),
+    /// `a to b` or `a in b`, `a` in the unit `b`.
+    Convert(Box<Expr>, Box<Expr>),
+    /// `cond ? a : b`, also written `if(cond, a, b)`.
+    Cond(Box<Expr>, Box<Expr>, Box<Expr>),
+    /// `x = a`
+    Assign(String, Box<Expr>),
+}
+
+impl Expr {
+    /// An expression parsed from `span`.
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Expr { kind, span }
+    }
+
+    /// Binding strength of the outermost node, used to decide where the
+    /// pretty-printer needs parentheses.
+    fn precedence(&self) -> u8 {
+        match &self.kind {
+            ExprKind::Assign(..) => 0,
+            ExprKind::Cond(..) => 1,
+            ExprKind::Convert(..) => 2,
+            ExprKind::Binary(op, ..) => op.precedence(),
+            ExprKind::Unary(UnaryOp::Not, _) => 5,
+            ExprKind::Unary(..) => 14,
+            ExprKind::Postfix(..) => 15,
+            ExprKind::Literal(_)
+            | ExprKind::Var(_)
+            | ExprKind::Call(..)
+            | ExprKind::Vector(_)
+            | ExprKind::Index(..) => 16,
+        }
+    }
+
+    fn fmt_operand(&self, f: &mut fmt::Formatter, parens: bool) -> fmt::Result {
+        if parens {
+            write!(f, "({})", self)
+        } else {
+            write!(f, "{}", self)
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            ExprKind::Literal(v) => write!(f, "{}", v),
+            ExprKind::Var(name) => write!(f, "{}", name),
+            ExprKind::Unary(op, e) => {
+                write!(f, "{}", op)?;
+                e.fmt_operand(f, e.precedence() < self.precedence())
+            }
+            ExprKind::Postfix(op, e) => {
+                // Parenthesize nested postfix operators too, `(3!)!` is not `3!!`.
+                e.fmt_operand(f, e.precedence() <= self.precedence())?;
+                write!(f, "{}", op)
+            }
+            ExprKind::Binary(op, a, b) => {
+                let prec = op.precedence();
+                let (left, mut right) = if op.is_right_assoc() {
+                    (a.precedence() <= prec, b.precedence() < prec)
+                } else if op.is_non_assoc() {
+                    (a.precedence() <= prec, b.precedence() <= prec)
+                } else {
+                    (a.precedence() < prec, b.precedence() <= prec)
+                };
+                // A sign after "%" would make it a percent sign instead.
+                if *op == BinaryOp::Mod && b.to_string().starts_with(&['-', '+'][..]) {
+                    right = true;
+                }
+                a.fmt_operand(f, left)?;
+                write!(f, " {} ", op)?;
+                b.fmt_operand(f, right)
+            }
+            ExprKind::Call(name, args) => {
+                write!(f, "{}(", name)?;
+                fmt_list(f, args)?;
+                write!(f, ")")
+            }
+            ExprKind::Vector(elements) => {
+                write!(f, "[")?;
+                fmt_list(f, elements)?;
+                write!(f, "]")
+            }
+            ExprKind::Index(e, indices) => {
+                e.fmt_operand(f, e.precedence() < self.precedence())?;
+                write!(f, "[")?;
+                fmt_list(f, indices)?;
+                write!(f, "]")
+            }
+            ExprKind::Convert(a, unit) => {
+                a.fmt_operand(f, a.precedence() < self.precedence())?;
+                write!(f, " to ")?;
+                // The unit is parsed like a product.
+                unit.fmt_operand(f, unit.precedence() < BinaryOp::Mul.precedence())
+            }
+            ExprKind::Cond(c, a, b) => {
+                c.fmt_operand(f, c.precedence() <= self.precedence())?;
+                write!(f, " ? {} : ", a)?;
+                b.fmt_operand(f, b.precedence() < self.precedence())
+            }
+            ExprKind::Assign(name, e) => write!(f, "{} = {}", name, e),
+        }
+    }
+}
+
+/// Writes `exprs` separated by commas.
+fn fmt_list(f: &mut fmt::Formatter, exprs: &[Expr]) -> fmt::Result {
+    for (i, e) in exprs.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", e)?;
+    }
+    Ok(())
+}
+
+/// `name(params) = body`, a user-defined function.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FunctionDef {
+    /// The name it is called by.
+    pub name: String,
+    /// The names of its parameters, in order.
+    pub params: Vec<String>,
+    /// The expression it evaluates, over its parameters.
+    pub body: Expr,
+    /// Part of the input the definition was parsed from.
+    pub span: Span,
+}
+
+impl fmt::Display for FunctionDef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}({}) = {}",
+            self.name,
+            self.params.join(", "),
+            self.body
+        )
+    }
+}
+
+/// A complete line of input.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Stmt {
+    /// An expression, possibly an assignment, whose value is the result.
+    Expr(Expr),
+    /// The definition of a function, whose result is the definition.
+    Function(FunctionDef),
+}
+
+impl fmt::Display for Stmt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stmt::Expr(e) => write!(f, "{}", e),
+            Stmt::Function(def) => write!(f, "{}", def),
+        }
+    }
+}
@@ -0,0 +1,896 @@
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_rational::BigRational;
+use num_traits::{One, Signed, ToPrimitive, Zero};
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::fmt;
+use std::thread::LocalKey;
+
+/// Bits carried beyond the requested number of decimal digits, so rounding
+/// errors in the last bits never show up in printed results.
+const GUARD_BITS: u32 = 10;
+
+/// Extra bits transcendental functions work with before rounding.
+const WORK_BITS: u32 = 32;
+
+/// `exp` gives up on arguments of this many bits or more, the result would
+/// be too large to ever print.
+const MAX_EXP_ARG_BITS: i64 = 20;
+
+/// Trigonometric functions give up on arguments of this many bits or more,
+/// reducing them would need a ridiculously precise `PI`.
+const MAX_TRIG_ARG_BITS: i64 = 1 << 14;
+
+/// Largest `n` for which `gamma(n)` is computed as an exact factorial.
+const MAX_EXACT_GAMMA: u64 = 10_000;
+
+/// `mant * 2^exp`, rounded to `prec` significant bits.
+///
+/// Results of operations are rounded to the larger precision of their
+/// operands. There is no infinity or NaN; operations without a finite result
+/// return `None` and callers fall back to `f64`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BigFloat {
+    /// Odd, or zero together with `exp`.
+    mant: BigInt,
+    exp: i64,
+    prec: u32,
+}
+
+thread_local! {
+    static PI: RefCell<(u32, BigInt)> = RefCell::new((0, BigInt::zero()));
+    static LN2: RefCell<(u32, BigInt)> = RefCell::new((0, BigInt::zero()));
+    static BERNOULLI: RefCell<Vec<BigRational>> = const { RefCell::new(Vec::new()) };
+}
+
+impl BigFloat {
+    /// Precision in bits needed to print `digits` significant digits.
+    pub(crate) fn bits_for_digits(digits: u32) -> u32 {
+        (digits as f64 * std::f64::consts::LOG2_10).ceil() as u32 + GUARD_BITS
+    }
+
+    /// Significant decimal digits shown when printed.
+    pub fn digits(&self) -> u32 {
+        let bits = self.prec.saturating_sub(GUARD_BITS) as f64;
+        ((bits * std::f64::consts::LOG10_2).floor() as u32).max(1)
+    }
+
+    /// Precision in bits.
+    pub fn prec(&self) -> u32 {
+        self.prec
+    }
+
+    fn new(mut mant: BigInt, mut exp: i64, prec: u32) -> Self {
+        let bits = mant.bits();
+        if bits > prec as u64 {
+            // Round to nearest, ties away from zero.
+            let shift = bits - prec as u64;
+            let rounded = (mant.abs() + (BigInt::one() << (shift - 1))) >> shift;
+            mant = if mant.is_negative() {
+                -rounded
+            } else {
+                rounded
+            };
+            exp += shift as i64;
+        }
+        match mant.trailing_zeros() {
+            Some(zeros) => {
+                mant >>= zeros;
+                exp += zeros as i64;
+            }
+            None => exp = 0,
+        }
+        BigFloat { mant, exp, prec }
+    }
+
+    /// The same value with a different precision, rounded if it is lower.
+    pub(crate) fn with_prec(&self, prec: u32) -> Self {
+        BigFloat::new(self.mant.clone(), self.exp, prec)
+    }
+
+    pub(crate) fn from_int(i: &BigInt, prec: u32) -> Self {
+        BigFloat::new(i.clone(), 0, prec)
+    }
+
+    pub(crate) fn from_i64(i: i64, prec: u32) -> Self {
+        BigFloat::new(BigInt::from(i), 0, prec)
+    }
+
+    pub(crate) fn from_ratio(r: &BigRational, prec: u32) -> Self {
+        BigFloat::div_ints(r.numer(), r.denom(), 0, prec)
+    }
+
+    /// Exact value of `f`, `None` for infinities and NaN.
+    pub(crate) fn from_f64(f: f64, prec: u32) -> Option<Self> {
+        if !f.is_finite() {
+            return None;
+        }
+        let (mant, exp, sign) = num_traits::Float::integer_decode(f);
+        Some(BigFloat::new(
+            BigInt::from(mant) * sign,
+            exp as i64,
+            prec.max(53),
+        ))
+    }
+
+    /// `n / d * 2^exp`.
+    fn div_ints(n: &BigInt, d: &BigInt, exp: i64, prec: u32) -> Self {
+        let shift = (prec as i64 + 2 + d.bits() as i64 - n.bits() as i64).max(0);
+        BigFloat::new((n << shift as u64) / d, exp - shift, prec)
+    }
+
+    /// Nearest `f64`, infinite if the value is out of range.
+    pub fn to_f64(&self) -> f64 {
+        if self.is_zero() {
+            return 0.0;
+        }
+        let r = self.with_prec(53);
+        let v = r.mant.to_f64().unwrap_or(f64::NAN);
+        let top = r.exp + r.mant.bits() as i64;
+        if top > 1100 {
+            return v.signum() * f64::INFINITY;
+        } else if top < -1100 {
+            return v.signum() * 0.0;
+        }
+        // Two steps so neither factor underflows on its own.
+        let half = r.exp / 2;
+        v * 2_f64.powi(half as i32) * 2_f64.powi((r.exp - half) as i32)
+    }
+
+    pub(crate) fn is_zero(&self) -> bool {
+        self.mant.is_zero()
+    }
+
+    pub(crate) fn is_negative(&self) -> bool {
+        self.mant.is_negative()
+    }
+
+    pub(crate) fn is_integer(&self) -> bool {
+        self.exp >= 0 || self.is_zero()
+    }
+
+    /// The integer value, if it is one and not absurdly large.
+    pub(crate) fn to_integer(&self) -> Option<BigInt> {
+        if self.is_integer() && self.exp < 1 << 24 {
+            Some(&self.mant << self.exp as u64)
+        } else {
+            None
+        }
+    }
+
+    /// `|self| < 2^top`, and `|self| >= 2^(top - 1)` unless it is zero.
+    fn top(&self) -> i64 {
+        self.exp + self.mant.bits() as i64
+    }
+
+    pub(crate) fn neg(&self) -> Self {
+        BigFloat {
+            mant: -&self.mant,
+            exp: self.exp,
+            prec: self.prec,
+        }
+    }
+
+    pub(crate) fn abs(&self) -> Self {
+        if self.is_negative() {
+            self.neg()
+        } else {
+            self.clone()
+        }
+    }
+
+    /// `self * 2^k`, exact.
+    pub(crate) fn mul_pow2(&self, k: i64) -> Self {
+        BigFloat::new(self.mant.clone(), self.exp + k, self.prec)
+    }
+
+    pub(crate) fn add(&self, other: &Self) -> Self {
+        let prec = self.prec.max(other.prec);
+        if other.is_zero() {
+            return self.with_prec(prec);
+        } else if self.is_zero() {
+            return other.with_prec(prec);
+        }
+        // Far smaller operands cannot change the rounded result.
+        if self.top() - other.top() > prec as i64 + 2 {
+            return self.with_prec(prec);
+        } else if other.top() - self.top() > prec as i64 + 2 {
+            return other.with_prec(prec);
+        }
+        let exp = self.exp.min(other.exp);
+        let a = &self.mant << (self.exp - exp) as u64;
+        let b = &other.mant << (other.exp - exp) as u64;
+        BigFloat::new(a + b, exp, prec)
+    }
+
+    pub(crate) fn sub(&self, other: &Self) -> Self {
+        self.add(&other.neg())
+    }
+
+    pub(crate) fn mul(&self, other: &Self) -> Self {
+        let prec = self.prec.max(other.prec);
+        BigFloat::new(&self.mant * &other.mant, self.exp + other.exp, prec)
+    }
+
+    pub(crate) fn div(&self, other: &Self) -> Option<Self> {
+        if other.is_zero() {
+            return None;
+        }
+        let prec = self.prec.max(other.prec);
+        Some(BigFloat::div_ints(
+            &self.mant,
+            &other.mant,
+            self.exp - other.exp,
+            prec,
+        ))
+    }
+
+    pub(crate) fn compare(&self, other: &Self) -> Ordering {
+        let sign = |x: &BigFloat| x.mant.sign();
+        if sign(self) != sign(other) || self.is_zero() {
+            return sign(self).cmp(&sign(other));
+        }
+        let by_magnitude = if self.top() != other.top() {
+            self.top().cmp(&other.top())
+        } else {
+            let exp = self.exp.min(other.exp);
+            let a = self.mant.abs() << (self.exp - exp) as u64;
+            let b = other.mant.abs() << (other.exp - exp) as u64;
+            a.cmp(&b)
+        };
+        if self.is_negative() {
+            by_magnitude.reverse()
+        } else {
+            by_magnitude
+        }
+    }
+
+    /// Rounds to an integer with `round`, which gets the mantissa and the
+    /// power of two it is to be divided by.
+    fn to_int_with(&self, round: fn(&BigInt, &BigInt) -> BigInt) -> Self {
+        if self.is_integer() {
+            return self.clone();
+        }
+        let denom = BigInt::one() << (-self.exp) as u64;
+        BigFloat::new(round(&self.mant, &denom), 0, self.prec)
+    }
+
+    pub(crate) fn floor(&self) -> Self {
+        self.to_int_with(|m, d| m.div_floor(d))
+    }
+
+    pub(crate) fn ceil(&self) -> Self {
+        self.to_int_with(|m, d| -(-m).div_floor(d))
+    }
+
+    pub(crate) fn trunc(&self) -> Self {
+        self.to_int_with(|m, d| m / d)
+    }
+
+    /// Rounds half-way cases away from zero, like `f64::round`.
+    pub(crate) fn round(&self) -> Self {
+        self.to_int_with(|m, d| {
+            let r: BigInt = (m.abs() + (d >> 1u32)) / d;
+            if m.is_negative() {
+                -r
+            } else {
+                r
+            }
+        })
+    }
+
+    /// The value scaled by `2^wp` as an integer.
+    fn to_fixed(&self, wp: u32) -> BigInt {
+        let shift = self.exp + wp as i64;
+        if shift >= 0 {
+            &self.mant << shift as u64
+        } else {
+            &self.mant >> (-shift) as u64
+        }
+    }
+
+    fn from_fixed(f: BigInt, wp: u32, prec: u32) -> Self {
+        BigFloat::new(f, -(wp as i64), prec)
+    }
+
+    pub(crate) fn pi(prec: u32) -> Self {
+        let wp = prec + WORK_BITS;
+        BigFloat::from_fixed(pi_fixed(wp), wp, prec)
+    }
+
+    pub(crate) fn e(prec: u32) -> Self {
+        BigFloat::from_i64(1, prec + WORK_BITS)
+            .exp()
+            .map(|e| e.with_prec(prec))
+            .unwrap_or_else(|| BigFloat::from_i64(0, prec))
+    }
+
+    pub(crate) fn sqrt(&self) -> Option<Self> {
+        if self.is_negative() {
+            return None;
+        }
+        Some(self.nth_root(2))
+    }
+
+    /// The positive `n`th root of a non-negative value.
+    fn nth_root(&self, n: u32) -> Self {
+        if self.is_zero() {
+            return self.clone();
+        }
+        let n64 = n as i64;
+        let mut shift = (n64 * (self.prec as i64 + 2) - self.mant.bits() as i64).max(0);
+        shift += (self.exp - shift).rem_euclid(n64);
+        let root = (&self.mant << shift as u64).nth_root(n);
+        BigFloat::new(root, (self.exp - shift) / n64, self.prec)
+    }
+
+    pub(crate) fn cbrt(&self) -> Self {
+        let root = self.abs().nth_root(3);
+        if self.is_negative() {
+            root.neg()
+        } else {
+            root
+        }
+    }
+
+    /// `self^(1/n)`, for non-negative `self` only.
+    pub(crate) fn root(&self, n: &Self) -> Option<Self> {
+        let prec = self.prec.max(n.prec);
+        if self.is_negative() {
+            return None;
+        }
+        match n.to_integer().and_then(|n| n.to_u32()) {
+            Some(n) if n > 0 => Some(self.with_prec(prec).nth_root(n)),
+            _ => self.pow(&BigFloat::from_i64(1, prec).div(n)?),
+        }
+    }
+
+    pub(crate) fn exp(&self) -> Option<Self> {
+        let prec = self.prec;
+        if self.is_zero() {
+            return Some(BigFloat::from_i64(1, prec));
+        }
+        let top = self.top();
+        if top > MAX_EXP_ARG_BITS {
+            return None;
+        }
+
+        // exp(x) = exp(r / 2^s)^(2^s) * 2^n where x = n * ln(2) + r.
+        let halvings = ((prec as f64).sqrt() as u32).max(4);
+        let wp = prec + WORK_BITS + top.max(0) as u32 + halvings;
+        let one = BigInt::one() << wp;
+        let x = self.to_fixed(wp);
+        let ln2 = ln2_fixed(wp);
+        let n = (&x * 2u32 + &ln2).div_floor(&(&ln2 * 2u32));
+        let r = (x - &n * &ln2) >> halvings;
+
+        let mut sum = one.clone();
+        let mut term = one;
+        let mut k = 1u32;
+        loop {
+            term = fmul(&term, &r, wp) / k;
+            if term.is_zero() {
+                break;
+            }
+            sum += &term;
+            k += 1;
+        }
+        for _ in 0..halvings {
+            sum = fmul(&sum, &sum, wp);
+        }
+        Some(BigFloat::new(sum, n.to_i64()? - wp as i64, prec))
+    }
+
+    pub(crate) fn ln(&self) -> Option<Self> {
+        if !self.mant.is_positive() {
+            return None;
+        }
+        let prec = self.prec;
+
+        // x = y * 2^k with y = mant / 2^ybits in [1/sqrt(2), sqrt(2)).
+        let mut ybits = self.mant.bits();
+        let mut k = self.top();
+        if (&self.mant * &self.mant) << 1 < BigInt::one() << (2 * ybits) {
+            ybits -= 1;
+            k -= 1;
+        }
+        // Close to 1 the result is tiny, so work with enough bits to keep
+        // the relative error small.
+        let d = &self.mant - (BigInt::one() << ybits);
+        let extra = if d.is_zero() { 0 } else { ybits - d.bits() };
+        let wp = prec + WORK_BITS + extra as u32 + (64 - k.unsigned_abs().leading_zeros());
+        let one = BigInt::one() << wp;
+        let y = if wp as u64 >= ybits {
+            &self.mant << (wp as u64 - ybits)
+        } else {
+            &self.mant >> (ybits - wp as u64)
+        };
+
+        // ln(y) = 2 atanh((y - 1) / (y + 1))
+        let t = ((&y - &one) << wp) / (&y + &one);
+        let t2 = fmul(&t, &t, wp);
+        let mut sum = t.clone();
+        let mut term = t;
+        let mut j = 1u32;
+        loop {
+            term = fmul(&term, &t2, wp);
+            if term.is_zero() {
+                break;
+            }
+            sum += &term / (2 * j + 1);
+            j += 1;
+        }
+        let r = (sum << 1) + ln2_fixed(wp) * k;
+        Some(BigFloat::from_fixed(r, wp, prec))
+    }
+
+    /// Logarithm to an arbitrary `base`.
+    pub(crate) fn log(&self, base: &Self) -> Option<Self> {
+        let prec = self.prec.max(base.prec);
+        let wp = prec + WORK_BITS;
+        let r = self.with_prec(wp).ln()?.div(&base.with_prec(wp).ln()?)?;
+        Some(r.with_prec(prec))
+    }
+
+    /// `self^y`. Negative bases only have real powers for integer `y`.
+    pub(crate) fn pow(&self, y: &Self) -> Option<Self> {
+        let prec = self.prec.max(y.prec);
+        if y.is_zero() {
+            return Some(BigFloat::from_i64(1, prec));
+        } else if self.is_zero() {
+            return if y.is_negative() {
+                None
+            } else {
+                Some(self.with_prec(prec))
+            };
+        }
+
+        if let Some(n) = y.to_integer().and_then(|n| n.to_i64()) {
+            if n.unsigned_abs() <= u32::MAX as u64 {
+                let wp = prec + WORK_BITS + 64 - n.unsigned_abs().leading_zeros();
+                let mut base = self.with_prec(wp);
+                let mut acc = BigFloat::from_i64(1, wp);
+                let mut e = n.unsigned_abs();
+                while e > 0 {
+                    if e & 1 == 1 {
+                        acc = acc.mul(&base);
+                    }
+                    base = base.mul(&base);
+                    e >>= 1;
+                }
+                if n < 0 {
+                    acc = BigFloat::from_i64(1, wp).div(&acc)?;
+                }
+                return Some(acc.with_prec(prec));
+            }
+        }
+
+        if self.is_negative() {
+            return None;
+        }
+        let wp = prec + WORK_BITS + MAX_EXP_ARG_BITS as u32;
+        let l = self.with_prec(wp).ln()?.mul(&y.with_prec(wp));
+        Some(l.exp()?.with_prec(prec))
+    }
+
+    fn sin_cos(&self) -> Option<(Self, Self)> {
+        let prec = self.prec;
+        if self.is_zero() {
+            return Some((self.clone(), BigFloat::from_i64(1, prec)));
+        }
+        let top = self.top();
+        if top > MAX_TRIG_ARG_BITS {
+            return None;
+        }
+
+        // Reduce to r = x - q * pi/2 with |r| <= pi/4.
+        let wp = prec + WORK_BITS + top.unsigned_abs() as u32;
+        let one = BigInt::one() << wp;
+        let x = self.to_fixed(wp);
+        let half_pi = pi_fixed(wp) >> 1u32;
+        let q = (&x * 2u32 + &half_pi).div_floor(&(&half_pi * 2u32));
+        let r = x - &q * &half_pi;
+        let r2 = fmul(&r, &r, wp);
+
+        let mut sin = r.clone();
+        let mut term = r;
+        let mut k = 1u32;
+        loop {
+            term = -fmul(&term, &r2, wp) / (2 * k * (2 * k + 1));
+            if term.is_zero() {
+                break;
+            }
+            sin += &term;
+            k += 1;
+        }
+        let mut cos = one.clone();
+        let mut term = one;
+        let mut k = 1u32;
+        loop {
+            term = -fmul(&term, &r2, wp) / ((2 * k - 1) * (2 * k));
+            if term.is_zero() {
+                break;
+            }
+            cos += &term;
+            k += 1;
+        }
+
+        let (sin, cos) = match q.mod_floor(&BigInt::from(4)).to_u8() {
+            Some(0) => (sin, cos),
+            Some(1) => (cos, -sin),
+            Some(2) => (-sin, -cos),
+            _ => (-cos, sin),
+        };
+        Some((
+            BigFloat::from_fixed(sin, wp, prec),
+            BigFloat::from_fixed(cos, wp, prec),
+        ))
+    }
+
+    pub(crate) fn sin(&self) -> Option<Self> {
+        self.sin_cos().map(|(s, _)| s)
+    }
+
+    pub(crate) fn cos(&self) -> Option<Self> {
+        self.sin_cos().map(|(_, c)| c)
+    }
+
+    pub(crate) fn tan(&self) -> Option<Self> {
+        let prec = self.prec;
+        let (s, c) = self.with_prec(prec + WORK_BITS).sin_cos()?;
+        Some(s.div(&c)?.with_prec(prec))
+    }
+
+    pub(crate) fn atan(&self) -> Self {
+        let prec = self.prec;
+        if self.is_zero() {
+            return self.clone();
+        }
+        let wp = prec + WORK_BITS + (-self.top()).max(0) as u32;
+        let one = BigInt::one() << wp;
+        let x = self.abs();
+
+        // atan(x) = pi/2 - atan(1/x)
+        let inverted = x.compare(&BigFloat::from_i64(1, prec)) == Ordering::Greater;
+        let r = if inverted {
+            let inv = (&one << wp) / x.to_fixed(wp);
+            (pi_fixed(wp) >> 1) - atan_fixed(inv, wp)
+        } else {
+            atan_fixed(x.to_fixed(wp), wp)
+        };
+        let r = if self.is_negative() { -r } else { r };
+        BigFloat::from_fixed(r, wp, prec)
+    }
+
+    /// Angle of the point `(x, self)`, like `f64::atan2`.
+    pub(crate) fn atan2(&self, x: &Self) -> Self {
+        let prec = self.prec.max(x.prec);
+        let wp = prec + WORK_BITS;
+        let half_pi = || BigFloat::from_fixed(pi_fixed(wp) >> 1, wp, prec);
+        if x.is_zero() {
+            return if self.is_zero() {
+                BigFloat::from_i64(0, prec)
+            } else if self.is_negative() {
+                half_pi().neg()
+            } else {
+                half_pi()
+            };
+        }
+        let a = match self.with_prec(wp).div(&x.with_prec(wp)) {
+            Some(q) => q.atan(),
+            None => return BigFloat::from_i64(0, prec),
+        };
+        let a = if x.is_negative() {
+            let pi = BigFloat::pi(wp);
+            if self.is_negative() {
+                a.sub(&pi)
+            } else {
+                a.add(&pi)
+            }
+        } else {
+            a
+        };
+        a.with_prec(prec)
+    }
+
+    /// `sqrt(1 - x^2)`, `None` if `|x| > 1`.
+    fn cos_of_asin(&self) -> Option<Self> {
+        let one = BigFloat::from_i64(1, self.prec);
+        one.sub(&self.mul(self)).sqrt()
+    }
+
+    pub(crate) fn asin(&self) -> Option<Self> {
+        let prec = self.prec;
+        let x = self.with_prec(prec + WORK_BITS);
+        Some(x.atan2(&x.cos_of_asin()?).with_prec(prec))
+    }
+
+    pub(crate) fn acos(&self) -> Option<Self> {
+        let prec = self.prec;
+        let x = self.with_prec(prec + WORK_BITS);
+        Some(x.cos_of_asin()?.atan2(&x).with_prec(prec))
+    }
+
+    /// `exp(x)` and `exp(-x)` with enough extra precision that their sum
+    /// and difference are accurate too.
+    fn exp_pair(&self) -> Option<(Self, Self)> {
+        let wp = self.prec + WORK_BITS + (-self.top()).max(0) as u32;
+        let e = self.with_prec(wp).exp()?;
+        let inv = BigFloat::from_i64(1, wp).div(&e)?;
+        Some((e, inv))
+    }
+
+    pub(crate) fn sinh(&self) -> Option<Self> {
+        let (e, inv) = self.exp_pair()?;
+        Some(e.sub(&inv).mul_pow2(-1).with_prec(self.prec))
+    }
+
+    pub(crate) fn cosh(&self) -> Option<Self> {
+        let (e, inv) = self.exp_pair()?;
+        Some(e.add(&inv).mul_pow2(-1).with_prec(self.prec))
+    }
+
+    pub(crate) fn tanh(&self) -> Option<Self> {
+        // Beyond this tanh(x) rounds to 1.
+        if self.top() > (u32::BITS - self.prec.leading_zeros()) as i64 {
+            let one = BigFloat::from_i64(1, self.prec);
+            return Some(if self.is_negative() { one.neg() } else { one });
+        }
+        let (e, inv) = self.exp_pair()?;
+        Some(e.sub(&inv).div(&e.add(&inv))?.with_prec(self.prec))
+    }
+
+    pub(crate) fn gamma(&self) -> Option<Self> {
+        let prec = self.prec;
+        if self.is_integer() {
+            let n = self.to_integer()?;
+            if !n.is_positive() {
+                return None;
+            }
+            if let Some(n) = n.to_u64().filter(|&n| n <= MAX_EXACT_GAMMA) {
+                let f: BigInt = (2..n).map(BigInt::from).product();
+                return Some(BigFloat::from_int(&f, prec));
+            }
+        }
+
+        // The result's relative error is the absolute error of ln(gamma),
+        // which grows with x.
+        let wp = prec + WORK_BITS + 2 * self.top().max(0) as u32;
+        let x = self.with_prec(wp);
+        let one = BigFloat::from_i64(1, wp);
+        let half = one.mul_pow2(-1);
+
+        if x.compare(&half) == Ordering::Less {
+            // Reflection: gamma(x) = pi / (sin(pi x) gamma(1 - x))
+            let pi = BigFloat::pi(wp);
+            let s = pi.mul(&x).sin()?;
+            let g = one.sub(&x).gamma()?;
+            return Some(pi.div(&s.mul(&g))?.with_prec(prec));
+        }
+
+        // Shift x up to z = x + n, where Stirling's series converges quickly,
+        // then divide by x (x + 1) ... (x + n - 1).
+        let shift = (wp as i64 - x.floor().to_integer()?.to_i64().unwrap_or(i64::MAX)).max(0);
+        let mut product = one.clone();
+        let mut z = x;
+        for _ in 0..shift {
+            product = product.mul(&z);
+            z = z.add(&one);
+        }
+
+        // ln(gamma(z)) ~ (z - 1/2) ln(z) - z + ln(2 pi)/2 + sum(B_2k / (2k (2k - 1) z^(2k - 1)))
+        let two_pi = BigFloat::pi(wp).mul_pow2(1);
+        let mut lg = z
+            .sub(&half)
+            .mul(&z.ln()?)
+            .sub(&z)
+            .add(&two_pi.ln()?.mul_pow2(-1));
+        let z2 = z.mul(&z);
+        let mut zpow = z.clone();
+        for k in 1..=wp as usize {
+            let b = bernoulli_even(k);
+            let c = BigRational::new(
+                b.numer().clone(),
+                b.denom() * BigInt::from(2 * k * (2 * k - 1)),
+            );
+            let term = BigFloat::from_ratio(&c, wp).div(&zpow)?;
+            lg = lg.add(&term);
+            if term.is_zero() || term.top() < -(wp as i64) {
+                break;
+            }
+            zpow = zpow.mul(&z2);
+        }
+        Some(lg.exp()?.div(&product)?.with_prec(prec))
+    }
+
+    /// Formats the value with `digits` significant digits, in scientific
+    /// notation if it is very large or very small.
+    fn fmt_digits(&self, f: &mut fmt::Formatter, digits: u32) -> fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+        if self.is_negative() {
+            write!(f, "-")?;
+        }
+
+        // |x| = n / d, scaled by 10^(digits - 1 - k) where 10^k <= |x|.
+        let (n, d) = if self.exp >= 0 {
+            (self.mant.abs() << self.exp as u64, BigInt::one())
+        } else {
+            (self.mant.abs(), BigInt::one() << (-self.exp) as u64)
+        };
+        let ten = BigInt::from(10);
+        let mut k = ((self.top() - 1) as f64 * std::f64::consts::LOG10_2).floor() as i64;
+        let digits_s = loop {
+            let scale = digits as i64 - 1 - k;
+            let (n, d) = if scale >= 0 {
+                (&n * num_traits::pow(ten.clone(), scale as usize), d.clone())
+            } else {
+                (
+                    n.clone(),
+                    &d * num_traits::pow(ten.clone(), (-scale) as usize),
+                )
+            };
+            let rounded = (n * 2u32 + &d) / (d * 2u32);
+            let s = rounded.to_string();
+            if s.len() > digits as usize {
+                k += 1;
+            } else if s.len() < digits as usize {
+                k -= 1;
+            } else {
+                break s;
+            }
+        };
+        let s = digits_s.trim_end_matches('0');
+
+        if k >= digits as i64 || k < -7 {
+            write!(f, "{}", &s[..1])?;
+            if s.len() > 1 {
+                write!(f, ".{}", &s[1..])?;
+            }
+            write!(f, "e{}", k)
+        } else if k < 0 {
+            write!(f, "0.{}{}", "0".repeat((-k - 1) as usize), s)
+        } else if s.len() as i64 <= k + 1 {
+            write!(f, "{}{}", s, "0".repeat((k + 1) as usize - s.len()))
+        } else {
+            let (int, frac) = s.split_at(k as usize + 1);
+            write!(f, "{}.{}", int, frac)
+        }
+    }
+}
+
+impl fmt::Display for BigFloat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_digits(f, self.digits())
+    }
+}
+
+/// `a * b` in fixed point with `wp` fraction bits, truncated toward zero so
+/// that series terms reach zero from either side.
+fn fmul(a: &BigInt, b: &BigInt, wp: u32) -> BigInt {
+    let p = a * b;
+    if p.is_negative() {
+        -((-p) >> wp)
+    } else {
+        p >> wp
+    }
+}
+
+/// A fixed point constant with at least `wp` fraction bits, computed once
+/// per thread at the highest precision asked for so far.
+fn cached(
+    cache: &'static LocalKey<RefCell<(u32, BigInt)>>,
+    wp: u32,
+    f: fn(u32) -> BigInt,
+) -> BigInt {
+    cache.with(|c| {
+        let mut c = c.borrow_mut();
+        if c.0 < wp {
+            *c = (wp, f(wp));
+        }
+        &c.1 >> (c.0 - wp)
+    })
+}
+
+/// Machin's formula, pi = 16 atan(1/5) - 4 atan(1/239).
+fn pi_fixed(wp: u32) -> BigInt {
+    cached(&PI, wp, |wp| {
+        let w = wp + 8;
+        (atan_inv(5, w) * 16 - atan_inv(239, w) * 4) >> 8
+    })
+}
+
+/// ln(2) = 2 atanh(1/3).
+fn ln2_fixed(wp: u32) -> BigInt {
+    cached(&LN2, wp, |wp| (atanh_inv(3, wp + 8) * 2) >> 8)
+}
+
+/// `atan(1/n)` in fixed point.
+fn atan_inv(n: u64, wp: u32) -> BigInt {
+    inverse_series(n, wp, true)
+}
+
+/// `atanh(1/n)` in fixed point.
+fn atanh_inv(n: u64, wp: u32) -> BigInt {
+    inverse_series(n, wp, false)
+}
+
+fn inverse_series(n: u64, wp: u32, alternating: bool) -> BigInt {
+    let n2 = BigInt::from(n * n);
+    let mut term = (BigInt::one() << wp) / n;
+    let mut sum = term.clone();
+    let mut k = 1u64;
+    loop {
+        term /= &n2;
+        if term.is_zero() {
+            break;
+        }
+        let t = &term / (2 * k + 1);
+        if alternating && k % 2 == 1 {
+            sum -= t;
+        } else {
+            sum += t;
+        }
+        k += 1;
+    }
+    sum
+}
+
+/// `atan(x)` in fixed point for `0 <= x <= 1`.
+fn atan_fixed(mut x: BigInt, wp: u32) -> BigInt {
+    // atan(x) = 2 atan(x / (1 + sqrt(1 + x^2))), until the series is fast.
+    const HALVINGS: u32 = 8;
+    let one = BigInt::one() << wp;
+    for _ in 0..HALVINGS {
+        let s = ((&one + fmul(&x, &x, wp)) << wp).sqrt();
+        x = (x << wp) / (&one + s);
+    }
+    let x2 = fmul(&x, &x, wp);
+    let mut sum = x.clone();
+    let mut term = x;
+    let mut k = 1u32;
+    loop {
+        term = -fmul(&term, &x2, wp);
+        if term.is_zero() {
+            break;
+        }
+        sum += &term / (2 * k + 1);
+        k += 1;
+    }
+    sum << HALVINGS
+}
+
+/// The Bernoulli number `B_2k`, for `k >= 1`.
+fn bernoulli_even(k: usize) -> BigRational {
+    BERNOULLI.with(|b| {
+        let mut b = b.borrow_mut();
+        if b.len() < k {
+            // Tangent numbers T_1..T_n (Brent and Harvey), then
+            // B_2j = (-1)^(j - 1) 2j T_j / (2^2j (2^2j - 1)).
+            let n = k.max(2 * b.len());
+            let mut t = vec![BigInt::one(); n + 1];
+            for j in 2..=n {
+                t[j] = &t[j - 1] * (j - 1);
+            }
+            for i in 2..=n {
+                for j in i..=n {
+                    t[j] = &t[j - 1] * (j - i) + &t[j] * (j - i + 2);
+                }
+            }
+            *b = (1..=n)
+                .map(|j| {
+                    let p = BigInt::one() << (2 * j);
+                    let r = BigRational::new(&t[j] * (2 * j), &p * (&p - 1u32));
+                    if j % 2 == 0 {
+                        -r
+                    } else {
+                        r
+                    }
+                })
+                .collect();
+        }
+        b[k - 1].clone()
+    })
+}
@@ -0,0 +1,119 @@
+use crate::calc::{BinaryOp, Number};
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_traits::{One, Signed, ToPrimitive, Zero};
+
+/// Largest shift accepted by `<<`, in bits of the result.
+const MAX_SHIFT: u64 = 1 << 20;
+
+// Integers without a word size behave as two's complement with infinitely
+// many sign bits, so `~0` is -1 and `-1 >> 1` stays -1. With a word size
+// they are the low `bits` bits, which `wrap` reads as a signed integer.
+
+/// `n` as an unsigned integer of `bits` bits.
+pub fn unsigned(n: &BigInt, bits: u32) -> BigInt {
+    n.mod_floor(&(BigInt::one() << bits as usize))
+}
+
+/// The low `bits` bits of `n` as a two's complement signed integer.
+pub fn wrap(n: &BigInt, bits: u32) -> BigInt {
+    let u = unsigned(n, bits);
+    if u.bit(u64::from(bits) - 1) {
+        u - (BigInt::one() << bits as usize)
+    } else {
+        u
+    }
+}
+
+/// Whether `n` fits in `bits` bits as a signed integer, or also as an
+/// unsigned one with `unsigned`, so that the literal `0xff` fits in a byte
+/// as -1.
+pub fn fits(n: &BigInt, bits: u32, unsigned: bool) -> bool {
+    let min = -(BigInt::one() << (bits as usize - 1));
+    let max = BigInt::one() << (bits as usize - usize::from(!unsigned));
+    &min <= n && n < &max
+}
+
+/// A bitwise operator applied to two integers, NaN where the operands are
+/// not integers or the result is undefined.
+pub fn binary(op: BinaryOp, a: &Number, b: &Number, word: Option<u32>) -> Number {
+    let (a, b) = match (a, b) {
+        (Number::Int(a), Number::Int(b)) => (a, b),
+        _ => return Number::Float(f64::NAN),
+    };
+    let r = match op {
+        BinaryOp::BitAnd => Some(a & b),
+        BinaryOp::BitOr => Some(a | b),
+        BinaryOp::BitXor => Some(a ^ b),
+        BinaryOp::Shl => shift(a, b, false),
+        BinaryOp::Shr => shift(a, b, true),
+        BinaryOp::LogicalShr => match (word, b.to_u64()) {
+            (Some(bits), Some(k)) => Some(unsigned(a, bits) >> k),
+            (None, Some(k)) if !a.is_negative() => Some(a >> k),
+            _ => None,
+        },
+        _ => unreachable!("BUG ALERT"),
+    };
+    r.map_or(Number::Float(f64::NAN), Number::Int)
+}
+
+/// `a << b`, or `a >> b` with `right`. Right shifts round down, so they
+/// keep the sign, and a negative shift goes the other way.
+fn shift(a: &BigInt, b: &BigInt, right: bool) -> Option<BigInt> {
+    let k = b.abs().to_u64()?;
+    if right != b.is_negative() {
+        Some(a >> k)
+    } else if a.is_zero() {
+        Some(BigInt::zero())
+    } else if a.bits() + k <= MAX_SHIFT {
+        Some(a << k)
+    } else {
+        None
+    }
+}
+
+/// `~n`
+pub fn not(n: &Number) -> Number {
+    match n {
+        Number::Int(n) => Number::Int(-n - 1),
+        _ => Number::Float(f64::NAN),
+    }
+}
+
+/// Number of one bits. Negative numbers have infinitely many without a
+/// word size.
+pub fn popcount(n: &BigInt, word: Option<u32>) -> Option<BigInt> {
+    match word {
+        Some(bits) => Some(unsigned(n, bits).magnitude().count_ones().into()),
+        None if !n.is_negative() => Some(n.magnitude().count_ones().into()),
+        None => None,
+    }
+}
+
+/// Number of zero bits above the highest one, which needs a word size.
+pub fn clz(n: &BigInt, word: Option<u32>) -> Option<BigInt> {
+    let bits = word?;
+    Some((u64::from(bits) - unsigned(n, bits).bits()).into())
+}
+
+/// Number of zero bits below the lowest one. Zero has as many as the word
+/// size.
+pub fn ctz(n: &BigInt, word: Option<u32>) -> Option<BigInt> {
+    match n.trailing_zeros() {
+        Some(k) => Some(k.min(word.map_or(u64::MAX, u64::from)).into()),
+        None => word.map(BigInt::from),
+    }
+}
+
+/// `n` rotated left by `k` bits within the word, right for negative `k`.
+pub fn rotl(n: &BigInt, k: &BigInt, word: Option<u32>) -> Option<BigInt> {
+    let bits = word?;
+    let k = k.mod_floor(&BigInt::from(bits)).to_usize()?;
+    let u = unsigned(n, bits);
+    Some(wrap(&((&u << k) | (u >> (bits as usize - k))), bits))
+}
+
+/// `n` rotated right by `k` bits within the word.
+pub fn rotr(n: &BigInt, k: &BigInt, word: Option<u32>) -> Option<BigInt> {
+    rotl(n, &-k, word)
+}
@@ -1,38 +1,177 @@
-use crate::calc::{Parser, Scanner};
-use std::cell::RefCell;
+use crate::calc::{
+    bits, math, BigFloat, BinaryOp, Complex, Error, Expr, ExprKind, Format, FunctionDef, Matrix,
+    Number, Parser, PostfixOp, Quantity, Scanner, Span, Stmt, TokenType, UnaryOp, Unit, Value,
+};
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_traits::ToPrimitive;
+use std::cell::Cell;
+use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::fmt;
+use std::num::NonZeroU32;
 use std::rc::Rc;
 
-pub struct Function {
-    pub param_count: isize,
-    pub f: fn(&[f64]) -> f64,
+/// How deeply user-defined functions may call each other before evaluation
+/// is abandoned.
+const MAX_CALL_DEPTH: usize = 100;
+
+/// Steps `preview` may take: one per node evaluated, one per factor of an
+/// exact factorial and one per 64 bits of an exact power. Enough for any
+/// line typed by hand, so only slow ones go without a preview.
+const PREVIEW_BUDGET: u64 = 20_000;
+
+/// A builtin's implementation for complex arguments, given the precision in
+/// bits.
+pub(crate) type ComplexFn = fn(&[Complex], Option<u32>) -> Option<Complex>;
+
+/// A builtin on numbers, like `sin`.
+#[derive(Clone, Copy)]
+struct Function {
+    param_count: isize,
+    f: fn(&[f64]) -> f64,
+    /// `f` with big floats, used when a precision is set. `None` where
+    /// there is no finite result.
+    big: fn(&[BigFloat]) -> Option<BigFloat>,
+    /// `f` for complex arguments, and for real ones where `f` has no real
+    /// result, like `sqrt(-1)`. `None` if the function is only defined for
+    /// real numbers.
+    complex: Option<ComplexFn>,
+    trig: Trig,
+    desc: &'static str,
+}
+
+/// How a builtin relates to the angle mode: the trigonometric functions
+/// take an angle, their inverses return one.
+#[derive(Clone, Copy, PartialEq)]
+enum Trig {
+    None,
+    Sin,
+    Cos,
+    Tan,
+    Asin,
+    Acos,
+    Atan,
+    Atan2,
+}
+
+/// The unit of angles taken and returned by the trigonometric builtins.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AngleMode {
+    /// A full turn is `2 PI`.
+    Radians,
+    /// A full turn is 360.
+    Degrees,
+    /// A full turn is 400.
+    Gradians,
+}
+
+impl AngleMode {
+    /// The angle of a full turn, `None` for radians where it is not exact.
+    fn full_turn(self) -> Option<Number> {
+        match self {
+            AngleMode::Radians => None,
+            AngleMode::Degrees => Some(Number::from(360)),
+            AngleMode::Gradians => Some(Number::from(400)),
+        }
+    }
+}
+
+impl fmt::Display for AngleMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AngleMode::Radians => write!(f, "rad"),
+            AngleMode::Degrees => write!(f, "deg"),
+            AngleMode::Gradians => write!(f, "grad"),
+        }
+    }
+}
+
+/// A builtin's implementation for vectors and matrices, given the precision
+/// in bits. `None` where there is no result, like the inverse of a singular
+/// matrix. Errors are reported at the call.
+type ArrayFn = fn(&[Value], Option<u32>) -> Result<Option<Value>, Error>;
+
+/// A builtin on vectors and matrices, like `det`.
+struct ArrayFunction {
+    param_count: isize,
+    f: ArrayFn,
+    desc: &'static str,
+}
+
+/// A builtin on integers, like `popcount`, given the word size. `None`
+/// where there is no result.
+type IntFn = fn(&[BigInt], Option<u32>) -> Option<BigInt>;
+
+/// A builtin on integers and their bits.
+struct IntFunction {
+    param_count: isize,
+    f: IntFn,
     desc: &'static str,
 }
 
+/// Evaluates input line by line, remembering variables (including `ans`,
+/// the result of the last evaluation) between calls.
 pub struct Calculator {
     parser: Parser,
-    memory: Rc<RefCell<HashMap<String, f64>>>,
-    functions: Rc<RefCell<HashMap<&'static str, Function>>>,
-    constants: Rc<HashMap<&'static str, f64>>,
+    memory: HashMap<String, Value>,
+    functions: HashMap<&'static str, Function>,
+    array_functions: HashMap<&'static str, ArrayFunction>,
+    int_functions: HashMap<&'static str, IntFunction>,
+    user_functions: HashMap<String, Rc<FunctionDef>>,
+    constants: HashMap<&'static str, Value>,
+    /// Arguments of the user-defined functions currently being evaluated,
+    /// innermost call last.
+    scopes: Vec<HashMap<String, Value>>,
+    /// Whether `run` shows fractions exactly, as `1/3`, or as floats.
+    exact: bool,
+    /// Significant digits of inexact results, `None` for plain `f64`.
+    precision: Option<u32>,
+    /// Whether `run` shows complex numbers as `r ∠ θ` instead of `a + bi`.
+    polar: bool,
+    angle_mode: AngleMode,
+    format: Format,
+    /// Bits of integers as two's complement, `None` for integers of any
+    /// size. Radix formats show negative integers in this many bits.
+    word_size: Option<u32>,
+    /// Whether integers too big for the word size wrap around rather than
+    /// being an error.
+    wrapping: bool,
+    /// Steps left while evaluating a preview, `None` otherwise.
+    budget: Option<u64>,
+}
+
+impl Default for Calculator {
+    fn default() -> Self {
+        Calculator::new()
+    }
 }
 
 impl Calculator {
+    /// Creates a calculator with the built-in constants and functions and
+    /// `ans` set to 0.
     pub fn new() -> Self {
-        let memory = Rc::new(RefCell::new(HashMap::new()));
+        let mut memory = HashMap::new();
         let mut constants = HashMap::new();
         let mut functions = HashMap::new();
 
-        memory.borrow_mut().insert("ans".into(), 0.0);
+        memory.insert("ans".into(), Value::from(0));
 
-        constants.insert("PI", ::std::f64::consts::PI);
-        constants.insert("E", ::std::f64::consts::E);
-        constants.insert("INF", ::std::f64::INFINITY);
+        constants.insert("PI", Value::from(::std::f64::consts::PI));
+        constants.insert("E", Value::from(::std::f64::consts::E));
+        constants.insert("INF", Value::from(f64::INFINITY));
+        constants.insert("i", Value::Number(Complex::i().into_number()));
+        constants.insert("true", Value::Bool(true));
+        constants.insert("false", Value::Bool(false));
 
         functions.insert(
             "sin",
             Function {
                 param_count: 1,
                 f: |p: &[f64]| -> f64 { p[0].sin_cos().0 },
+                big: |p: &[BigFloat]| -> Option<BigFloat> { p[0].sin() },
+                complex: Some(|p: &[Complex], prec| -> Option<Complex> { Some(p[0].sin(prec)) }),
+                trig: Trig::Sin,
                 desc: "sin(n)",
             },
         );
@@ -41,6 +180,9 @@ impl Calculator {
             Function {
                 param_count: 1,
                 f: |p: &[f64]| -> f64 { p[0].sin_cos().1 },
+                big: |p: &[BigFloat]| -> Option<BigFloat> { p[0].cos() },
+                complex: Some(|p: &[Complex], prec| -> Option<Complex> { Some(p[0].cos(prec)) }),
+                trig: Trig::Cos,
                 desc: "cos(n)",
             },
         );
@@ -49,6 +191,9 @@ impl Calculator {
             Function {
                 param_count: 1,
                 f: |p: &[f64]| -> f64 { p[0].tan() },
+                big: |p: &[BigFloat]| -> Option<BigFloat> { p[0].tan() },
+                complex: Some(|p: &[Complex], prec| -> Option<Complex> { Some(p[0].tan(prec)) }),
+                trig: Trig::Tan,
                 desc: "tan(n)",
             },
         );
@@ -58,6 +203,9 @@ impl Calculator {
             Function {
                 param_count: 1,
                 f: |p: &[f64]| -> f64 { p[0].sinh() },
+                big: |p: &[BigFloat]| -> Option<BigFloat> { p[0].sinh() },
+                complex: Some(|p: &[Complex], prec| -> Option<Complex> { Some(p[0].sinh(prec)) }),
+                trig: Trig::None,
                 desc: "sinh(n)",
             },
         );
@@ -66,6 +214,9 @@ impl Calculator {
             Function {
                 param_count: 1,
                 f: |p: &[f64]| -> f64 { p[0].cosh() },
+                big: |p: &[BigFloat]| -> Option<BigFloat> { p[0].cosh() },
+                complex: Some(|p: &[Complex], prec| -> Option<Complex> { Some(p[0].cosh(prec)) }),
+                trig: Trig::None,
                 desc: "cosh(n)",
             },
         );
@@ -74,7 +225,10 @@ impl Calculator {
             Function {
                 param_count: 1,
                 f: |p: &[f64]| -> f64 { p[0].tanh() },
-                desc: "tan(n)",
+                big: |p: &[BigFloat]| -> Option<BigFloat> { p[0].tanh() },
+                complex: Some(|p: &[Complex], prec| -> Option<Complex> { Some(p[0].tanh(prec)) }),
+                trig: Trig::None,
+                desc: "tanh(n)",
             },
         );
 
@@ -83,6 +237,9 @@ impl Calculator {
             Function {
                 param_count: 1,
                 f: |p: &[f64]| -> f64 { p[0].asin() },
+                big: |p: &[BigFloat]| -> Option<BigFloat> { p[0].asin() },
+                complex: Some(|p: &[Complex], prec| -> Option<Complex> { p[0].asin(prec) }),
+                trig: Trig::Asin,
                 desc: "asin(n)",
             },
         );
@@ -91,6 +248,9 @@ impl Calculator {
             Function {
                 param_count: 1,
                 f: |p: &[f64]| -> f64 { p[0].acos() },
+                big: |p: &[BigFloat]| -> Option<BigFloat> { p[0].acos() },
+                complex: Some(|p: &[Complex], prec| -> Option<Complex> { p[0].acos(prec) }),
+                trig: Trig::Acos,
                 desc: "acos(n)",
             },
         );
@@ -99,6 +259,9 @@ impl Calculator {
             Function {
                 param_count: 1,
                 f: |p: &[f64]| -> f64 { p[0].atan() },
+                big: |p: &[BigFloat]| -> Option<BigFloat> { Some(p[0].atan()) },
+                complex: Some(|p: &[Complex], prec| -> Option<Complex> { p[0].atan(prec) }),
+                trig: Trig::Atan,
                 desc: "atan(n)",
             },
         );
@@ -107,6 +270,9 @@ impl Calculator {
             Function {
                 param_count: 2,
                 f: |p: &[f64]| -> f64 { p[1].atan2(p[0]) },
+                big: |p: &[BigFloat]| -> Option<BigFloat> { Some(p[1].atan2(&p[0])) },
+                complex: None,
+                trig: Trig::Atan2,
                 desc: "atan2(x, y)",
             },
         );
@@ -116,6 +282,11 @@ impl Calculator {
             Function {
                 param_count: 1,
                 f: |p: &[f64]| -> f64 { p[0].abs() },
+                big: |p: &[BigFloat]| -> Option<BigFloat> { Some(p[0].abs()) },
+                complex: Some(|p: &[Complex], prec| -> Option<Complex> {
+                    Some(Complex::real(p[0].abs(prec)))
+                }),
+                trig: Trig::None,
                 desc: "abs(n)",
             },
         );
@@ -124,6 +295,13 @@ impl Calculator {
             Function {
                 param_count: 1,
                 f: |p: &[f64]| -> f64 { p[0].log10() },
+                big: |p: &[BigFloat]| -> Option<BigFloat> {
+                    p[0].log(&BigFloat::from_i64(10, p[0].prec()))
+                },
+                complex: Some(|p: &[Complex], prec| -> Option<Complex> {
+                    p[0].log(&Complex::real(Number::from(10)), prec)
+                }),
+                trig: Trig::None,
                 desc: "log10(n)",
             },
         );
@@ -132,6 +310,9 @@ impl Calculator {
             Function {
                 param_count: 1,
                 f: |p: &[f64]| -> f64 { p[0].ln() },
+                big: |p: &[BigFloat]| -> Option<BigFloat> { p[0].ln() },
+                complex: Some(|p: &[Complex], prec| -> Option<Complex> { p[0].ln(prec) }),
+                trig: Trig::None,
                 desc: "ln(n)",
             },
         );
@@ -140,6 +321,13 @@ impl Calculator {
             Function {
                 param_count: 1,
                 f: |p: &[f64]| -> f64 { p[0].log2() },
+                big: |p: &[BigFloat]| -> Option<BigFloat> {
+                    p[0].log(&BigFloat::from_i64(2, p[0].prec()))
+                },
+                complex: Some(|p: &[Complex], prec| -> Option<Complex> {
+                    p[0].log(&Complex::real(Number::from(2)), prec)
+                }),
+                trig: Trig::None,
                 desc: "log2(n)",
             },
         );
@@ -148,6 +336,9 @@ impl Calculator {
             Function {
                 param_count: 2,
                 f: |p: &[f64]| -> f64 { p[0].log(p[1]) },
+                big: |p: &[BigFloat]| -> Option<BigFloat> { p[0].log(&p[1]) },
+                complex: Some(|p: &[Complex], prec| -> Option<Complex> { p[0].log(&p[1], prec) }),
+                trig: Trig::None,
                 desc: "log(n, base)",
             },
         );
@@ -156,6 +347,9 @@ impl Calculator {
             Function {
                 param_count: 1,
                 f: |p: &[f64]| -> f64 { p[0].sqrt() },
+                big: |p: &[BigFloat]| -> Option<BigFloat> { p[0].sqrt() },
+                complex: Some(|p: &[Complex], prec| -> Option<Complex> { Some(p[0].sqrt(prec)) }),
+                trig: Trig::None,
                 desc: "sqrt(n)",
             },
         );
@@ -164,6 +358,9 @@ impl Calculator {
             Function {
                 param_count: 1,
                 f: |p: &[f64]| -> f64 { p[0].cbrt() },
+                big: |p: &[BigFloat]| -> Option<BigFloat> { Some(p[0].cbrt()) },
+                complex: None,
+                trig: Trig::None,
                 desc: "cbrt(n)",
             },
         );
@@ -172,6 +369,9 @@ impl Calculator {
             Function {
                 param_count: 2,
                 f: |p: &[f64]| -> f64 { p[0].powf(p[1].recip()) },
+                big: |p: &[BigFloat]| -> Option<BigFloat> { p[0].root(&p[1]) },
+                complex: None,
+                trig: Trig::None,
                 desc: "root(n, root)",
             },
         );
@@ -180,6 +380,14 @@ impl Calculator {
             Function {
                 param_count: 1,
                 f: |p: &[f64]| -> f64 { p[0].signum() },
+                big: |p: &[BigFloat]| -> Option<BigFloat> {
+                    Some(BigFloat::from_i64(
+                        if p[0].is_negative() { -1 } else { 1 },
+                        p[0].prec(),
+                    ))
+                },
+                complex: None,
+                trig: Trig::None,
                 desc: "sign(n)",
             },
         );
@@ -188,15 +396,76 @@ impl Calculator {
             Function {
                 param_count: 1,
                 f: |p: &[f64]| -> f64 { p[0].exp() },
+                big: |p: &[BigFloat]| -> Option<BigFloat> { p[0].exp() },
+                complex: Some(|p: &[Complex], prec| -> Option<Complex> { Some(p[0].exp(prec)) }),
+                trig: Trig::None,
                 desc: "exp(n)",
             },
         );
 
+        functions.insert(
+            "re",
+            Function {
+                param_count: 1,
+                f: |p: &[f64]| -> f64 { p[0] },
+                big: |p: &[BigFloat]| -> Option<BigFloat> { Some(p[0].clone()) },
+                complex: Some(|p: &[Complex], _| -> Option<Complex> {
+                    Some(Complex::real(p[0].re.clone()))
+                }),
+                trig: Trig::None,
+                desc: "re(z)",
+            },
+        );
+        functions.insert(
+            "im",
+            Function {
+                param_count: 1,
+                f: |_: &[f64]| -> f64 { 0.0 },
+                big: |p: &[BigFloat]| -> Option<BigFloat> {
+                    Some(BigFloat::from_i64(0, p[0].prec()))
+                },
+                complex: Some(|p: &[Complex], _| -> Option<Complex> {
+                    Some(Complex::real(p[0].im.clone()))
+                }),
+                trig: Trig::None,
+                desc: "im(z)",
+            },
+        );
+        functions.insert(
+            "arg",
+            Function {
+                param_count: 1,
+                f: |p: &[f64]| -> f64 { 0_f64.atan2(p[0]) },
+                big: |p: &[BigFloat]| -> Option<BigFloat> {
+                    Some(BigFloat::from_i64(0, p[0].prec()).atan2(&p[0]))
+                },
+                complex: Some(|p: &[Complex], prec| -> Option<Complex> {
+                    Some(Complex::real(p[0].arg(prec)))
+                }),
+                trig: Trig::None,
+                desc: "arg(z)",
+            },
+        );
+        functions.insert(
+            "conj",
+            Function {
+                param_count: 1,
+                f: |p: &[f64]| -> f64 { p[0] },
+                big: |p: &[BigFloat]| -> Option<BigFloat> { Some(p[0].clone()) },
+                complex: Some(|p: &[Complex], _| -> Option<Complex> { Some(p[0].conj()) }),
+                trig: Trig::None,
+                desc: "conj(z)",
+            },
+        );
+
         functions.insert(
             "trunc",
             Function {
                 param_count: 1,
                 f: |p: &[f64]| -> f64 { p[0].trunc() },
+                big: |p: &[BigFloat]| -> Option<BigFloat> { Some(p[0].trunc()) },
+                complex: None,
+                trig: Trig::None,
                 desc: "trunc(n)",
             },
         );
@@ -205,6 +474,9 @@ impl Calculator {
             Function {
                 param_count: 1,
                 f: |p: &[f64]| -> f64 { p[0].round() },
+                big: |p: &[BigFloat]| -> Option<BigFloat> { Some(p[0].round()) },
+                complex: None,
+                trig: Trig::None,
                 desc: "round(n)",
             },
         );
@@ -213,6 +485,9 @@ impl Calculator {
             Function {
                 param_count: 1,
                 f: |p: &[f64]| -> f64 { p[0].floor() },
+                big: |p: &[BigFloat]| -> Option<BigFloat> { Some(p[0].floor()) },
+                complex: None,
+                trig: Trig::None,
                 desc: "floor(n)",
             },
         );
@@ -221,15 +496,41 @@ impl Calculator {
             Function {
                 param_count: 1,
                 f: |p: &[f64]| -> f64 { p[0].ceil() },
+                big: |p: &[BigFloat]| -> Option<BigFloat> { Some(p[0].ceil()) },
+                complex: None,
+                trig: Trig::None,
                 desc: "ceil(n)",
             },
         );
 
+        functions.insert(
+            "gamma",
+            Function {
+                param_count: 1,
+                f: |p: &[f64]| -> f64 { math::gamma(p[0]) },
+                big: |p: &[BigFloat]| -> Option<BigFloat> { p[0].gamma() },
+                complex: None,
+                trig: Trig::None,
+                desc: "gamma(n)",
+            },
+        );
+
         functions.insert(
             "min",
             Function {
                 param_count: -2,
                 f: |p: &[f64]| -> f64 { p.iter().skip(1).fold(p[0], |a, &b| a.min(b)) },
+                big: |p: &[BigFloat]| -> Option<BigFloat> {
+                    Some(p.iter().skip(1).fold(p[0].clone(), |a, b| {
+                        if b.compare(&a) == Ordering::Less {
+                            b.clone()
+                        } else {
+                            a
+                        }
+                    }))
+                },
+                complex: None,
+                trig: Trig::None,
                 desc: "min(a, b, ...)",
             },
         );
@@ -238,6 +539,17 @@ impl Calculator {
             Function {
                 param_count: -2,
                 f: |p: &[f64]| -> f64 { p.iter().skip(1).fold(p[0], |a, &b| a.max(b)) },
+                big: |p: &[BigFloat]| -> Option<BigFloat> {
+                    Some(p.iter().skip(1).fold(p[0].clone(), |a, b| {
+                        if b.compare(&a) == Ordering::Greater {
+                            b.clone()
+                        } else {
+                            a
+                        }
+                    }))
+                },
+                complex: None,
+                trig: Trig::None,
                 desc: "max(a, b, ...)",
             },
         );
@@ -247,6 +559,12 @@ impl Calculator {
             Function {
                 param_count: 1,
                 f: |p: &[f64]| -> f64 { p[0] * (180_f64 / ::std::f64::consts::PI) },
+                big: |p: &[BigFloat]| -> Option<BigFloat> {
+                    p[0].mul(&BigFloat::from_i64(180, p[0].prec()))
+                        .div(&BigFloat::pi(p[0].prec()))
+                },
+                complex: None,
+                trig: Trig::None,
                 desc: "rad2deg(radians)",
             },
         );
@@ -255,6 +573,12 @@ impl Calculator {
             Function {
                 param_count: 1,
                 f: |p: &[f64]| -> f64 { p[0] * (::std::f64::consts::PI / 180_f64) },
+                big: |p: &[BigFloat]| -> Option<BigFloat> {
+                    p[0].mul(&BigFloat::pi(p[0].prec()))
+                        .div(&BigFloat::from_i64(180, p[0].prec()))
+                },
+                complex: None,
+                trig: Trig::None,
                 desc: "deg2rad(degrees)",
             },
         );
@@ -264,6 +588,12 @@ impl Calculator {
             Function {
                 param_count: 1,
                 f: |p: &[f64]| -> f64 { p[0] * (9_f64 / 10_f64) },
+                big: |p: &[BigFloat]| -> Option<BigFloat> {
+                    p[0].mul(&BigFloat::from_i64(9, p[0].prec()))
+                        .div(&BigFloat::from_i64(10, p[0].prec()))
+                },
+                complex: None,
+                trig: Trig::None,
                 desc: "grad2deg(gradians)",
             },
         );
@@ -272,6 +602,12 @@ impl Calculator {
             Function {
                 param_count: 1,
                 f: |p: &[f64]| -> f64 { p[0] * (10_f64 / 9_f64) },
+                big: |p: &[BigFloat]| -> Option<BigFloat> {
+                    p[0].mul(&BigFloat::from_i64(10, p[0].prec()))
+                        .div(&BigFloat::from_i64(9, p[0].prec()))
+                },
+                complex: None,
+                trig: Trig::None,
                 desc: "deg2grad(degrees)",
             },
         );
@@ -281,6 +617,12 @@ impl Calculator {
             Function {
                 param_count: 1,
                 f: |p: &[f64]| -> f64 { p[0] * (::std::f64::consts::PI / 200_f64) },
+                big: |p: &[BigFloat]| -> Option<BigFloat> {
+                    p[0].mul(&BigFloat::pi(p[0].prec()))
+                        .div(&BigFloat::from_i64(200, p[0].prec()))
+                },
+                complex: None,
+                trig: Trig::None,
                 desc: "grad2rad(gradians)",
             },
         );
@@ -289,71 +631,1435 @@ impl Calculator {
             Function {
                 param_count: 1,
                 f: |p: &[f64]| -> f64 { p[0] * (200_f64 / ::std::f64::consts::PI) },
+                big: |p: &[BigFloat]| -> Option<BigFloat> {
+                    p[0].mul(&BigFloat::from_i64(200, p[0].prec()))
+                        .div(&BigFloat::pi(p[0].prec()))
+                },
+                complex: None,
+                trig: Trig::None,
                 desc: "rad2grad(radians)",
             },
         );
 
-        let functions = Rc::new(RefCell::new(functions));
-        let constants = Rc::new(constants);
+        let mut array_functions = HashMap::new();
+
+        array_functions.insert(
+            "det",
+            ArrayFunction {
+                param_count: 1,
+                f: |p: &[Value], _| Ok(square(&p[0])?.det().map(Value::Number)),
+                desc: "det(matrix)",
+            },
+        );
+        array_functions.insert(
+            "inv",
+            ArrayFunction {
+                param_count: 1,
+                f: |p: &[Value], _| Ok(square(&p[0])?.inv().map(Value::Matrix)),
+                desc: "inv(matrix)",
+            },
+        );
+        array_functions.insert(
+            "transpose",
+            ArrayFunction {
+                param_count: 1,
+                f: |p: &[Value], _| match &p[0] {
+                    Value::Matrix(m) => Ok(Some(Value::Matrix(m.transpose()))),
+                    Value::Vector(v) => Ok(Some(Value::Matrix(Matrix::new(v.len(), 1, v.clone())))),
+                    v => Err(type_error("a matrix", v)),
+                },
+                desc: "transpose(matrix)",
+            },
+        );
+        array_functions.insert(
+            "dot",
+            ArrayFunction {
+                param_count: 2,
+                f: |p: &[Value], _| {
+                    let (a, b) = (vector(&p[0])?, vector(&p[1])?);
+                    if a.len() != b.len() {
+                        return Err(incompatible(&p[0], &p[1]));
+                    }
+                    let dot = a
+                        .iter()
+                        .zip(b)
+                        .fold(Number::from(0), |acc, (a, b)| acc.add(&a.mul(b)));
+                    Ok(Some(Value::Number(dot)))
+                },
+                desc: "dot(a, b)",
+            },
+        );
+        array_functions.insert(
+            "cross",
+            ArrayFunction {
+                param_count: 2,
+                f: |p: &[Value], _| match (vector(&p[0])?, vector(&p[1])?) {
+                    ([a1, a2, a3], [b1, b2, b3]) => Ok(Some(Value::Vector(vec![
+                        a2.mul(b3).sub(&a3.mul(b2)),
+                        a3.mul(b1).sub(&a1.mul(b3)),
+                        a1.mul(b2).sub(&a2.mul(b1)),
+                    ]))),
+                    _ => Err(Error::Dimension {
+                        message: format!(
+                            "cross requires two 3-vectors, found {} and {}",
+                            p[0].shape_name(),
+                            p[1].shape_name()
+                        ),
+                        span: Span::new(0, 0),
+                    }),
+                },
+                desc: "cross(a, b)",
+            },
+        );
+        array_functions.insert(
+            "norm",
+            ArrayFunction {
+                param_count: 1,
+                f: |p: &[Value], prec| {
+                    if let Value::Bool(_) = p[0] {
+                        return Err(type_error("a vector or matrix", &p[0]));
+                    }
+                    let squares = p[0].numbers().iter().fold(Number::from(0), |acc, n| {
+                        let z = n.to_complex();
+                        acc.add(&z.re.mul(&z.re)).add(&z.im.mul(&z.im))
+                    });
+                    let norm = squares.real_fn(prec, f64::sqrt, BigFloat::sqrt);
+                    Ok(Some(Value::Number(norm)))
+                },
+                desc: "norm(v)",
+            },
+        );
+        array_functions.insert(
+            "solve",
+            ArrayFunction {
+                param_count: 2,
+                f: |p: &[Value], _| {
+                    let a = square(&p[0])?;
+                    let b = match &p[1] {
+                        Value::Vector(v) => Matrix::new(v.len(), 1, v.clone()),
+                        Value::Matrix(m) => m.clone(),
+                        v => return Err(type_error("a vector or matrix", v)),
+                    };
+                    if b.rows() != a.rows() {
+                        return Err(incompatible(&p[0], &p[1]));
+                    }
+                    Ok(a.solve(&b).map(|x| match p[1] {
+                        Value::Vector(_) => Value::Vector(x.data().to_vec()),
+                        _ => Value::Matrix(x),
+                    }))
+                },
+                desc: "solve(A, b)",
+            },
+        );
+
+        let mut int_functions = HashMap::new();
+
+        int_functions.insert(
+            "popcount",
+            IntFunction {
+                param_count: 1,
+                f: |p: &[BigInt], word| bits::popcount(&p[0], word),
+                desc: "popcount(n)",
+            },
+        );
+        int_functions.insert(
+            "clz",
+            IntFunction {
+                param_count: 1,
+                f: |p: &[BigInt], word| bits::clz(&p[0], word),
+                desc: "clz(n)",
+            },
+        );
+        int_functions.insert(
+            "ctz",
+            IntFunction {
+                param_count: 1,
+                f: |p: &[BigInt], word| bits::ctz(&p[0], word),
+                desc: "ctz(n)",
+            },
+        );
+        int_functions.insert(
+            "rotl",
+            IntFunction {
+                param_count: 2,
+                f: |p: &[BigInt], word| bits::rotl(&p[0], &p[1], word),
+                desc: "rotl(n, bits)",
+            },
+        );
+        int_functions.insert(
+            "rotr",
+            IntFunction {
+                param_count: 2,
+                f: |p: &[BigInt], word| bits::rotr(&p[0], &p[1], word),
+                desc: "rotr(n, bits)",
+            },
+        );
 
         Calculator {
-            parser: Parser::new(functions.clone(), constants.clone(), memory.clone()),
+            parser: Parser::new(),
             memory,
             functions,
+            array_functions,
+            int_functions,
+            user_functions: HashMap::new(),
             constants,
+            scopes: Vec::new(),
+            exact: true,
+            precision: None,
+            polar: false,
+            angle_mode: AngleMode::Radians,
+            format: Format::Decimal,
+            word_size: None,
+            wrapping: true,
+            budget: None,
+        }
+    }
+
+    /// Enables or disables implicit multiplication, so that `2pi`, `3(x+1)`
+    /// and `(a)(b)` are products. Off by default.
+    pub fn set_implicit_multiplication(&mut self, enabled: bool) {
+        self.parser.set_implicit_multiplication(enabled);
+    }
+
+    /// Whether implicit multiplication is enabled.
+    pub fn implicit_multiplication(&self) -> bool {
+        self.parser.implicit_multiplication()
+    }
+
+    /// Chooses whether `run` shows fractions exactly, as `1/3`, or as the
+    /// nearest float. Exact by default; evaluation is exact either way.
+    pub fn set_exact(&mut self, exact: bool) {
+        self.exact = exact;
+    }
+
+    /// Whether `run` shows fractions exactly.
+    pub fn exact(&self) -> bool {
+        self.exact
+    }
+
+    /// Chooses whether `run` shows complex numbers in polar form, as
+    /// `2 ∠ 1.5707963267948966`, or as `a + bi`. Off by default.
+    pub fn set_polar(&mut self, polar: bool) {
+        self.polar = polar;
+    }
+
+    /// Whether `run` shows complex numbers in polar form.
+    pub fn polar(&self) -> bool {
+        self.polar
+    }
+
+    /// Chooses the unit of angles for `sin`, `cos` and `tan` and the angles
+    /// returned by their inverses. Radians by default.
+    pub fn set_angle_mode(&mut self, mode: AngleMode) {
+        self.angle_mode = mode;
+    }
+
+    /// The unit of angles of the trigonometric builtins.
+    pub fn angle_mode(&self) -> AngleMode {
+        self.angle_mode
+    }
+
+    /// Chooses how `run` shows numbers, unless the input asks for a format
+    /// with `to`, as in `255 to hex`. Decimal by default.
+    pub fn set_format(&mut self, format: Format) {
+        self.format = format;
+    }
+
+    /// How `run` shows numbers.
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Limits integers to `bits` bits of two's complement, as in C, or
+    /// lifts the limit for `None`, the default. Radix formats then show
+    /// negative integers in that many bits, and `>>>`, `clz`, `rotl` and
+    /// `rotr` need a word size.
+    pub fn set_word_size(&mut self, bits: Option<NonZeroU32>) {
+        self.word_size = bits.map(NonZeroU32::get);
+    }
+
+    /// The word size in bits, `None` if integers are unlimited.
+    pub fn word_size(&self) -> Option<u32> {
+        self.word_size
+    }
+
+    /// Chooses whether integers too big for the word size wrap around, the
+    /// default, or are an `Error::Overflow`.
+    pub fn set_wrapping(&mut self, wrapping: bool) {
+        self.wrapping = wrapping;
+    }
+
+    /// Whether integers too big for the word size wrap around.
+    pub fn wrapping(&self) -> bool {
+        self.wrapping
+    }
+
+    /// Computes inexact results, including builtins and the constants `PI`
+    /// and `E`, with `digits` significant digits instead of as `f64`.
+    pub fn set_precision(&mut self, digits: Option<NonZeroU32>) {
+        self.precision = digits.map(NonZeroU32::get);
+        let (pi, e) = match self.prec_bits() {
+            Some(bits) => (
+                Value::Number(Number::Big(BigFloat::pi(bits))),
+                Value::Number(Number::Big(BigFloat::e(bits))),
+            ),
+            None => (
+                Value::from(std::f64::consts::PI),
+                Value::from(std::f64::consts::E),
+            ),
+        };
+        self.constants.insert("PI", pi);
+        self.constants.insert("E", e);
+    }
+
+    /// The significant digits of inexact results, `None` for `f64`.
+    pub fn precision(&self) -> Option<u32> {
+        self.precision
+    }
+
+    fn prec_bits(&self) -> Option<u32> {
+        self.precision.map(BigFloat::bits_for_digits)
+    }
+
+    /// Parses `input` into a statement without evaluating it.
+    pub fn parse(&mut self, input: &str) -> Result<Option<Stmt>, Error> {
+        self.parser.parse(Scanner::new(input))
+    }
+
+    /// Parses `input` into statements separated by `;` or line breaks.
+    pub fn parse_all(&mut self, input: &str) -> Result<Vec<Stmt>, Error> {
+        self.parser.parse_all(Scanner::new(input))
+    }
+
+    /// Whether `input` ends inside brackets or after an operator, so that
+    /// its last statement goes on in the next line.
+    pub fn needs_more(&self, input: &str) -> bool {
+        let mut scanner = Scanner::new(input);
+        while scanner.next_token().token_type != TokenType::EOL {}
+        scanner.continues()
+    }
+
+    /// Executes a previously parsed statement. Expressions yield their value,
+    /// function definitions are stored and yield `None`.
+    pub fn exec(&mut self, stmt: &Stmt) -> Result<Option<Value>, Error> {
+        match stmt {
+            Stmt::Expr(expr) => Ok(Some(self.eval(expr)?)),
+            Stmt::Function(def) => {
+                self.define(def)?;
+                Ok(None)
+            }
+        }
+    }
+
+    fn define(&mut self, def: &FunctionDef) -> Result<(), Error> {
+        if self.is_builtin(&def.name) {
+            return Err(Error::InvalidAssignment {
+                name: def.name.clone(),
+                span: def.span,
+            });
+        }
+        if let Some(p) = def
+            .params
+            .iter()
+            .find(|p| self.constants.contains_key(p.as_str()))
+        {
+            return Err(Error::InvalidAssignment {
+                name: p.clone(),
+                span: def.span,
+            });
+        }
+
+        self.user_functions
+            .insert(def.name.clone(), Rc::new(def.clone()));
+        Ok(())
+    }
+
+    /// Evaluates a previously parsed expression against the current state.
+    /// Assignments update the calculator's memory.
+    pub fn eval(&mut self, expr: &Expr) -> Result<Value, Error> {
+        self.spend(1, expr.span)?;
+        match &expr.kind {
+            ExprKind::Literal(n) => self.fit(expr, Value::Number(n.clone())),
+            ExprKind::Var(name) => {
+                if let Some(v) = self.scopes.last().and_then(|s| s.get(name)) {
+                    return Ok(v.clone());
+                }
+                match self.constants.get(name.as_str()) {
+                    Some(v) => Ok(v.clone()),
+                    None => match self.memory.get(name) {
+                        Some(v) => Ok(v.clone()),
+                        None => unit(name, expr.span),
+                    },
+                }
+            }
+            ExprKind::Unary(UnaryOp::Not, e) => Ok(Value::Bool(!self.eval_bool(e)?)),
+            ExprKind::Unary(op, e) => self.unary(expr, *op, e),
+            ExprKind::Postfix(op, e) => self.postfix(expr, *op, e),
+            ExprKind::Binary(BinaryOp::And, a, b) => {
+                Ok(Value::Bool(self.eval_bool(a)? && self.eval_bool(b)?))
+            }
+            ExprKind::Binary(BinaryOp::Or, a, b) => {
+                Ok(Value::Bool(self.eval_bool(a)? || self.eval_bool(b)?))
+            }
+            ExprKind::Binary(op @ BinaryOp::Eq, a, b)
+            | ExprKind::Binary(op @ BinaryOp::Ne, a, b) => {
+                let eq = self.equal(a, b)?;
+                Ok(Value::Bool(eq == (*op == BinaryOp::Eq)))
+            }
+            ExprKind::Binary(op, x, y) => self.binary(expr, *op, x, y),
+            ExprKind::Convert(a, b) => self.convert(expr, a, b),
+            ExprKind::Cond(c, a, b) => {
+                if self.eval_bool(c)? {
+                    self.eval(a)
+                } else {
+                    self.eval(b)
+                }
+            }
+            ExprKind::Call(name, args) => self.call(expr, name, args),
+            ExprKind::Vector(elements) => self.vector(elements),
+            ExprKind::Index(e, indices) => self.index(e, indices),
+            ExprKind::Assign(var, e) => {
+                if !self.is_valid_assignment(var) {
+                    return Err(Error::InvalidAssignment {
+                        name: var.clone(),
+                        span: expr.span,
+                    });
+                }
+                let v = self.eval(e)?;
+                self.memory.insert(var.clone(), v.clone());
+                Ok(v)
+            }
+        }
+    }
+
+    // The operators live outside `eval` to keep its stack frame small, as it
+    // recurses for every level of nesting and user function call.
+
+    fn unary(&mut self, expr: &Expr, op: UnaryOp, e: &Expr) -> Result<Value, Error> {
+        let v = self.eval(e)?;
+        let r = match (op, &v) {
+            (UnaryOp::BitNot, Value::Quantity(_)) => None,
+            (UnaryOp::Neg, v) => v.map(Number::neg),
+            (UnaryOp::BitNot, v) => v.map(bits::not),
+            (_, v) => v.map(Number::clone),
+        };
+        match r {
+            Some(r) => self.fit(expr, Self::check_array_domain(expr, &[&v], r)?),
+            None => Err(Error::Type {
+                expected: "a number",
+                found: v.type_name(),
+                span: e.span,
+            }),
         }
     }
 
-    pub fn run(&mut self, input: &str) -> Result<String, String> {
-        match self.parser.parse(Scanner::new(input)) {
-            Ok(result) => match result {
-                Some(result) => {
-                    self.memory.borrow_mut().insert("ans".into(), result.val);
-                    if result.var.is_some() {
-                        self.memory
-                            .borrow_mut()
-                            .insert(result.var.as_ref().unwrap().clone(), result.val);
-                        Ok(format!("{} = {}", result.var.unwrap(), result.val))
-                    } else {
-                        Ok(format!("{}", result.val))
+    fn postfix(&mut self, expr: &Expr, op: PostfixOp, e: &Expr) -> Result<Value, Error> {
+        let v = self.eval(e)?;
+        let prec = self.prec_bits();
+        if matches!(v, Value::Quantity(_)) && op != PostfixOp::Percent {
+            return Err(Error::Type {
+                expected: "a number",
+                found: v.type_name(),
+                span: e.span,
+            });
+        }
+        if op != PostfixOp::Percent {
+            let cost = Cell::new(0);
+            v.map(|n| {
+                cost.set(cost.get() + n.factorial_cost());
+                n.clone()
+            });
+            self.spend(cost.get(), expr.span)?;
+        }
+        let too_large = Cell::new(false);
+        let r = v.map(|n| {
+            let r = match op {
+                PostfixOp::Factorial => n.factorial(prec),
+                PostfixOp::DoubleFactorial => n.double_factorial(prec),
+                PostfixOp::Percent => n.div(&Number::from(100)),
+            };
+            // An integer too big for an exact factorial, as in `10001!`.
+            if matches!(n, Number::Int(_)) && matches!(r, Number::Float(f) if f.is_infinite()) {
+                too_large.set(true);
+            }
+            r
+        });
+        if too_large.get() {
+            return Err(Error::TooLarge { span: expr.span });
+        }
+        match r {
+            Some(r) => self.fit(expr, Self::check_array_domain(expr, &[&v], r)?),
+            None => Err(Error::Type {
+                expected: "a number",
+                found: v.type_name(),
+                span: e.span,
+            }),
+        }
+    }
+
+    fn equal(&mut self, a: &Expr, b: &Expr) -> Result<bool, Error> {
+        let x = self.eval(a)?;
+        let y = self.eval(b)?;
+        match (&x, &y) {
+            (Value::Number(x), Value::Number(y)) => Ok(x.num_eq(y)),
+            (Value::Bool(x), Value::Bool(y)) => Ok(x == y),
+            (Value::Vector(_), Value::Vector(_)) | (Value::Matrix(_), Value::Matrix(_)) => {
+                Ok(x.shape_name() == y.shape_name()
+                    && x.numbers()
+                        .iter()
+                        .zip(y.numbers())
+                        .all(|(x, y)| x.num_eq(y)))
+            }
+            (Value::Quantity(x), Value::Quantity(y)) => {
+                Ok(x.convert(&y.unit).is_some_and(|x| x.value.num_eq(&y.value)))
+            }
+            _ => Err(Error::Type {
+                expected: x.type_name(),
+                found: y.type_name(),
+                span: b.span,
+            }),
+        }
+    }
+
+    fn binary(&mut self, expr: &Expr, op: BinaryOp, x: &Expr, y: &Expr) -> Result<Value, Error> {
+        let a = self.eval(x)?;
+        let b = self.eval(y)?;
+        if op == BinaryOp::Pow {
+            let cost = Cell::new(0);
+            a.zip_with(&b, |a, b| {
+                cost.set(cost.get() + a.pow_cost(b) / 64);
+                a.clone()
+            });
+            self.spend(cost.get(), expr.span)?;
+        }
+        match (a, b) {
+            (Value::Number(a), Value::Number(b)) => self.number_binary(expr, op, x, y, a, b),
+            (a @ Value::Quantity(_), b) | (a, b @ Value::Quantity(_)) => {
+                self.quantity_binary(expr, op, x, y, a, b)
+            }
+            (a, b) => self.array_binary(expr, op, x, y, a, b),
+        }
+    }
+
+    fn number_binary(
+        &self,
+        expr: &Expr,
+        op: BinaryOp,
+        x: &Expr,
+        y: &Expr,
+        a: Number,
+        b: Number,
+    ) -> Result<Value, Error> {
+        let ordering = matches!(
+            op,
+            BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge
+        );
+        if let Some(e) = [(&a, x), (&b, y)]
+            .iter()
+            .find(|(n, _)| ordering && n.is_complex())
+            .map(|(_, e)| e)
+        {
+            return Err(Error::Type {
+                expected: "a real number",
+                found: "a complex number",
+                span: e.span,
+            });
+        }
+        let v = match op {
+            BinaryOp::Add => a.add(&b),
+            BinaryOp::Sub => a.sub(&b),
+            BinaryOp::Mul => a.mul(&b),
+            BinaryOp::Div => a.div(&b),
+            BinaryOp::Mod => a.rem(&b),
+            BinaryOp::Pow => a
+                .checked_pow(&b, self.prec_bits())
+                .ok_or(Error::TooLarge { span: expr.span })?,
+            op if op.is_bitwise() => bits::binary(op, &a, &b, self.word_size),
+            BinaryOp::Lt => return Ok(Value::Bool(a.num_cmp(&b) == Some(Ordering::Less))),
+            BinaryOp::Le => {
+                return Ok(Value::Bool(matches!(
+                    a.num_cmp(&b),
+                    Some(Ordering::Less | Ordering::Equal)
+                )))
+            }
+            BinaryOp::Gt => return Ok(Value::Bool(a.num_cmp(&b) == Some(Ordering::Greater))),
+            BinaryOp::Ge => {
+                return Ok(Value::Bool(matches!(
+                    a.num_cmp(&b),
+                    Some(Ordering::Greater | Ordering::Equal)
+                )))
+            }
+            _ => unreachable!("BUG ALERT"),
+        };
+        let v = Self::check_domain(expr, &[a, b], v)?;
+        self.fit(expr, Value::Number(v))
+    }
+
+    /// The integers in `v` wrapped to the word size, or an overflow error
+    /// if they do not fit in it and wrapping is off.
+    fn fit(&self, expr: &Expr, v: Value) -> Result<Value, Error> {
+        let bits = match self.word_size {
+            Some(bits) => bits,
+            None => return Ok(v),
+        };
+        // Literals may be written unsigned, as `0xff` for -1 in a byte.
+        let literal = matches!(expr.kind, ExprKind::Literal(_));
+        let overflow = v.numbers().iter().any(|n| match n {
+            Number::Int(i) => !bits::fits(i, bits, literal),
+            _ => false,
+        });
+        if overflow && !self.wrapping {
+            return Err(Error::Overflow {
+                bits,
+                span: expr.span,
+            });
+        }
+        let wrapped = v.map(|n| match n {
+            Number::Int(i) => Number::Int(bits::wrap(i, bits)),
+            n => n.clone(),
+        });
+        Ok(wrapped.unwrap_or(v))
+    }
+
+    /// `number_binary` with a quantity operand. Sums, remainders and
+    /// comparisons take the right operand in the unit of the left one, so
+    /// `3 ft + 2 in` is in feet, while products combine the units.
+    fn quantity_binary(
+        &self,
+        expr: &Expr,
+        op: BinaryOp,
+        x: &Expr,
+        y: &Expr,
+        a: Value,
+        b: Value,
+    ) -> Result<Value, Error> {
+        if op.is_bitwise() {
+            let (v, e) = match a {
+                Value::Quantity(_) => (&a, x),
+                _ => (&b, y),
+            };
+            return Err(type_error("a number", v).with_span(e.span));
+        }
+        let qa = quantity(&a).map_err(|e| e.with_span(x.span))?;
+        let qb = quantity(&b).map_err(|e| e.with_span(y.span))?;
+        let (unit, a, b) = match op {
+            BinaryOp::Mul => (qa.unit.mul(&qb.unit), qa.value, qb.value),
+            BinaryOp::Div => (qa.unit.div(&qb.unit), qa.value, qb.value),
+            BinaryOp::Pow => {
+                let n = match b {
+                    Value::Number(Number::Int(n)) => n,
+                    Value::Number(_) => {
+                        return Err(Error::Dimension {
+                            message: format!("cannot raise {} to a fractional power", qa.unit),
+                            span: expr.span,
+                        })
                     }
+                    b => return Err(type_error("a number", &b).with_span(y.span)),
+                };
+                let unit = n.to_i64().and_then(|n| qa.unit.pow(n));
+                (unit, qa.value, Number::Int(n))
+            }
+            _ => match qb.convert(&qa.unit) {
+                Some(qb) => (Some(qa.unit), qa.value, qb.value),
+                None => {
+                    return Err(Error::Dimension {
+                        message: format!(
+                            "incompatible units: {} and {}",
+                            qa.unit.describe(),
+                            qb.unit.describe()
+                        ),
+                        span: expr.span,
+                    })
                 }
-                None => Ok("".into()),
-            },
-            Err(e) => match self.functions.borrow().get(input) {
-                Some(f) => Ok(f.desc.to_string()),
-                None => Err(e),
             },
+        };
+        let unit = unit.ok_or_else(|| Error::Domain {
+            expr: expr.to_string(),
+            span: expr.span,
+        })?;
+        match self.number_binary(expr, op, x, y, a, b)? {
+            Value::Number(n) => Ok(Quantity::new(n, unit).into_value()),
+            v => Ok(v),
+        }
+    }
+
+    /// `a to b`, the quantity `a` in the unit `b`. A format such as `hex`
+    /// for `b` leaves the value alone, `run` shows it in that format.
+    fn convert(&mut self, expr: &Expr, a: &Expr, b: &Expr) -> Result<Value, Error> {
+        let x = self.eval(a)?;
+        if format_target(b).is_some() {
+            return Ok(x);
+        }
+        let y = self.eval(b)?;
+        let unit = match &y {
+            Value::Quantity(q) if q.value.num_eq(&Number::from(1)) => &q.unit,
+            y => return Err(type_error("a unit", y).with_span(b.span)),
+        };
+        let q = quantity(&x).map_err(|e| e.with_span(a.span))?;
+        match q.convert(unit) {
+            Some(q) => Ok(Value::Quantity(Box::new(q))),
+            None => Err(Error::Dimension {
+                message: format!("cannot convert {} to {}", q.unit.describe(), unit),
+                span: expr.span,
+            }),
+        }
+    }
+
+    /// `number_binary` with a vector or matrix operand: element-wise, except that
+    /// `*` between matrices and vectors is the matrix product and a matrix
+    /// to an integer power is repeated multiplication.
+    fn array_binary(
+        &self,
+        expr: &Expr,
+        op: BinaryOp,
+        x: &Expr,
+        y: &Expr,
+        a: Value,
+        b: Value,
+    ) -> Result<Value, Error> {
+        let ordering = matches!(
+            op,
+            BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge
+        );
+        for (v, e) in [(&a, x), (&b, y)] {
+            let number = match v {
+                Value::Number(_) => true,
+                Value::Bool(_) | Value::Quantity(_) => false,
+                Value::Vector(_) | Value::Matrix(_) => !ordering,
+            };
+            if !number {
+                return Err(Error::Type {
+                    expected: "a number",
+                    found: v.type_name(),
+                    span: e.span,
+                });
+            }
+        }
+        let prec = self.prec_bits();
+        let v = match (op, &a, &b) {
+            (BinaryOp::Mul, Value::Matrix(m), Value::Matrix(n)) => m.mul(n).map(Value::Matrix),
+            (BinaryOp::Mul, Value::Matrix(m), Value::Vector(v)) => {
+                m.mul_vector(v).map(Value::Vector)
+            }
+            (BinaryOp::Mul, Value::Vector(v), Value::Matrix(m)) => {
+                Matrix::vector_mul(v, m).map(Value::Vector)
+            }
+            (BinaryOp::Pow, Value::Matrix(_), Value::Number(n)) => {
+                let m = square(&a).map_err(|e| e.with_span(x.span))?;
+                match m.pow(n) {
+                    Some(p) => Some(Value::Matrix(p)),
+                    None => {
+                        return Err(Error::Domain {
+                            expr: expr.to_string(),
+                            span: expr.span,
+                        })
+                    }
+                }
+            }
+            (BinaryOp::Add, ..) => a.zip_with(&b, Number::add),
+            (BinaryOp::Sub, ..) => a.zip_with(&b, Number::sub),
+            (BinaryOp::Mul, ..) => a.zip_with(&b, Number::mul),
+            (BinaryOp::Div, ..) => a.zip_with(&b, Number::div),
+            (BinaryOp::Mod, ..) => a.zip_with(&b, Number::rem),
+            (BinaryOp::Pow, ..) => {
+                let too_large = Cell::new(false);
+                let v = a.zip_with(&b, |a, b| {
+                    a.checked_pow(b, prec).unwrap_or_else(|| {
+                        too_large.set(true);
+                        Number::Float(f64::NAN)
+                    })
+                });
+                if too_large.get() {
+                    return Err(Error::TooLarge { span: expr.span });
+                }
+                v
+            }
+            (op, ..) if op.is_bitwise() => {
+                a.zip_with(&b, |a, b| bits::binary(op, a, b, self.word_size))
+            }
+            _ => unreachable!("BUG ALERT"),
+        };
+        match v {
+            Some(v) => self.fit(expr, Self::check_array_domain(expr, &[&a, &b], v)?),
+            None => Err(incompatible(&a, &b).with_span(expr.span)),
+        }
+    }
+
+    /// `[a, b, c]` as a vector, or as a matrix with rows `a`, `b` and `c`
+    /// if they are vectors.
+    fn vector(&mut self, elements: &[Expr]) -> Result<Value, Error> {
+        let mut values = Vec::with_capacity(elements.len());
+        for e in elements {
+            values.push(self.eval(e)?);
+        }
+        if !matches!(values.first(), Some(Value::Vector(_))) {
+            let mut numbers = Vec::with_capacity(values.len());
+            for (v, e) in values.into_iter().zip(elements) {
+                match v {
+                    Value::Number(n) => numbers.push(n),
+                    v => {
+                        return Err(Error::Type {
+                            expected: "a number",
+                            found: v.type_name(),
+                            span: e.span,
+                        })
+                    }
+                }
+            }
+            return Ok(Value::Vector(numbers));
+        }
+
+        let cols = values[0].numbers().len();
+        if cols == 0 {
+            return Err(Error::Dimension {
+                message: "matrix rows cannot be empty".to_owned(),
+                span: elements[0].span,
+            });
+        }
+        let mut rows = Vec::with_capacity(values.len());
+        for (v, e) in values.into_iter().zip(elements) {
+            match v {
+                Value::Vector(row) if row.len() == cols => rows.push(row),
+                v => {
+                    return Err(Error::Dimension {
+                        message: format!(
+                            "expected a matrix row of length {}, found {}",
+                            cols,
+                            v.shape_name()
+                        ),
+                        span: e.span,
+                    })
+                }
+            }
+        }
+        let m = Matrix::from_rows(rows).expect("BUG ALERT: rows checked above");
+        Ok(Value::Matrix(m))
+    }
+
+    /// `v[i]`, `m[i]` (a row) or `m[i, j]`.
+    fn index(&mut self, e: &Expr, index_exprs: &[Expr]) -> Result<Value, Error> {
+        let v = self.eval(e)?;
+        let mut indices = Vec::with_capacity(index_exprs.len());
+        for i in index_exprs {
+            indices.push((self.eval_number(i)?, i.span));
+        }
+        let position = |(i, span): &(Number, Span), len: usize| {
+            match i {
+                Number::Int(n) => n.to_usize().filter(|&k| k < len),
+                _ => None,
+            }
+            .ok_or_else(|| Error::Index {
+                index: i.to_string(),
+                len,
+                span: *span,
+            })
+        };
+        match (&v, indices.as_slice()) {
+            (Value::Vector(v), [i]) => Ok(Value::Number(v[position(i, v.len())?].clone())),
+            (Value::Matrix(m), [i]) => Ok(Value::Vector(m.row(position(i, m.rows())?))),
+            (Value::Matrix(m), [i, j]) => {
+                let (i, j) = (position(i, m.rows())?, position(j, m.cols())?);
+                Ok(Value::Number(m.get(i, j).clone()))
+            }
+            (Value::Vector(_), _) => Err(Error::Dimension {
+                message: format!("a vector takes 1 index, found {}", indices.len()),
+                span: e.span,
+            }),
+            _ => Err(Error::Type {
+                expected: "a vector or matrix",
+                found: v.type_name(),
+                span: e.span,
+            }),
+        }
+    }
+
+    fn eval_number(&mut self, expr: &Expr) -> Result<Number, Error> {
+        match self.eval(expr)? {
+            Value::Number(v) => Ok(v),
+            v => Err(Error::Type {
+                expected: "a number",
+                found: v.type_name(),
+                span: expr.span,
+            }),
+        }
+    }
+
+    fn eval_bool(&mut self, expr: &Expr) -> Result<bool, Error> {
+        match self.eval(expr)? {
+            Value::Bool(b) => Ok(b),
+            v => Err(Error::Type {
+                expected: "a boolean",
+                found: v.type_name(),
+                span: expr.span,
+            }),
+        }
+    }
+
+    /// Turns a NaN produced from non-NaN operands into a domain error.
+    fn check_domain(expr: &Expr, operands: &[Number], v: Number) -> Result<Number, Error> {
+        if v.is_nan() && !operands.iter().any(|o| o.is_nan()) {
+            Err(Error::Domain {
+                expr: expr.to_string(),
+                span: expr.span,
+            })
+        } else {
+            Ok(v)
+        }
+    }
+
+    /// `check_domain` for operations on vectors and matrices, element by
+    /// element.
+    fn check_array_domain(expr: &Expr, operands: &[&Value], v: Value) -> Result<Value, Error> {
+        let nan = |v: &Value| v.numbers().iter().any(Number::is_nan);
+        if nan(&v) && !operands.iter().any(|o| nan(o)) {
+            Err(Error::Domain {
+                expr: expr.to_string(),
+                span: expr.span,
+            })
+        } else {
+            Ok(v)
+        }
+    }
+
+    fn call(&mut self, expr: &Expr, name: &str, args: &[Expr]) -> Result<Value, Error> {
+        if let Some(def) = self.user_functions.get(name).cloned() {
+            let mut params = Vec::with_capacity(args.len());
+            for arg in args {
+                params.push(self.eval(arg)?);
+            }
+            return self.call_user(&def, params, expr.span);
+        }
+
+        if let Some(function) = self.array_functions.get(name) {
+            let (param_count, f) = (function.param_count, function.f);
+            Self::check_arity(name, param_count, args.len(), expr.span)?;
+            let mut params = Vec::with_capacity(args.len());
+            for arg in args {
+                params.push(self.eval(arg)?);
+            }
+            return match f(&params, self.prec_bits()).map_err(|e| e.with_span(expr.span))? {
+                Some(v) => Ok(v),
+                None => Err(Error::Domain {
+                    expr: expr.to_string(),
+                    span: expr.span,
+                }),
+            };
+        }
+
+        if let Some(function) = self.int_functions.get(name) {
+            let (param_count, f) = (function.param_count, function.f);
+            Self::check_arity(name, param_count, args.len(), expr.span)?;
+            let mut params = Vec::with_capacity(args.len());
+            for arg in args {
+                match self.eval(arg)? {
+                    Value::Number(Number::Int(n)) => params.push(n),
+                    v => return Err(type_error("an integer", &v).with_span(arg.span)),
+                }
+            }
+            return match f(&params, self.word_size) {
+                Some(n) => self.fit(expr, Value::Number(Number::Int(n))),
+                None => Err(Error::Domain {
+                    expr: expr.to_string(),
+                    span: expr.span,
+                }),
+            };
+        }
+
+        let function = match self.functions.get(name) {
+            Some(function) => *function,
+            None => {
+                return Err(Error::UnknownFunction {
+                    name: name.to_owned(),
+                    span: expr.span,
+                })
+            }
+        };
+        Self::check_arity(name, function.param_count, args.len(), expr.span)?;
+
+        let mut params = Vec::with_capacity(args.len());
+        for arg in args {
+            params.push(self.eval(arg)?);
+        }
+        // Functions of one number apply to each element of a vector or matrix.
+        if let [v @ Value::Vector(_)] | [v @ Value::Matrix(_)] = params.as_slice() {
+            let mut elements = Vec::with_capacity(v.numbers().len());
+            for n in v.numbers() {
+                elements.push(self.apply(expr, args, &function, std::slice::from_ref(n))?);
+            }
+            return Ok(match v {
+                Value::Matrix(m) => Value::Matrix(Matrix::new(m.rows(), m.cols(), elements)),
+                _ => Value::Vector(elements),
+            });
+        }
+        let mut numbers = Vec::with_capacity(params.len());
+        for (v, arg) in params.into_iter().zip(args) {
+            match v {
+                Value::Number(n) => numbers.push(n),
+                v => {
+                    return Err(Error::Type {
+                        expected: "a number",
+                        found: v.type_name(),
+                        span: arg.span,
+                    })
+                }
+            }
+        }
+        self.apply(expr, args, &function, &numbers)
+            .map(Value::Number)
+    }
+
+    fn check_arity(name: &str, param_count: isize, found: usize, span: Span) -> Result<(), Error> {
+        if (param_count < 0 && found >= param_count.unsigned_abs())
+            || (found == param_count as usize)
+        {
+            Ok(())
+        } else {
+            Err(Error::Arity {
+                name: name.to_owned(),
+                expected: param_count.unsigned_abs(),
+                variadic: param_count < 0,
+                found,
+                span,
+            })
+        }
+    }
+
+    /// Calls the builtin `function` with the numbers `params`, evaluated
+    /// from `args`, taking and giving angles in the angle mode.
+    fn apply(
+        &self,
+        expr: &Expr,
+        args: &[Expr],
+        function: &Function,
+        params: &[Number],
+    ) -> Result<Number, Error> {
+        let turn = match (function.trig, self.angle_mode.full_turn()) {
+            (Trig::None, _) | (_, None) => return self.apply_radians(expr, args, function, params),
+            (_, Some(turn)) => turn,
+        };
+        if let Some(v) = exact_trig(function.trig, params, &turn) {
+            return Self::check_domain(expr, params, v);
+        }
+        let pi = match self.prec_bits() {
+            Some(bits) => Number::Big(BigFloat::pi(bits)),
+            None => Number::Float(std::f64::consts::PI),
+        };
+        // One unit of the angle mode in radians.
+        let unit = pi.mul(&Number::from(2)).div(&turn);
+        match function.trig {
+            Trig::Sin | Trig::Cos | Trig::Tan => {
+                let radians = [params[0].mul(&unit)];
+                self.apply_radians(expr, args, function, &radians)
+            }
+            _ => Ok(self.apply_radians(expr, args, function, params)?.div(&unit)),
+        }
+    }
+
+    /// `apply` with angles in radians, whatever the angle mode.
+    fn apply_radians(
+        &self,
+        expr: &Expr,
+        args: &[Expr],
+        function: &Function,
+        params: &[Number],
+    ) -> Result<Number, Error> {
+        let prec = self.prec_bits();
+        let complex_result = |complex: ComplexFn| {
+            let z: Vec<Complex> = params.iter().map(Number::to_complex).collect();
+            complex(&z, prec).map_or(Number::Float(f64::NAN), Complex::into_number)
+        };
+        let v = if let Some(i) = params.iter().position(Number::is_complex) {
+            match function.complex {
+                Some(complex) => complex_result(complex),
+                None => {
+                    return Err(Error::Type {
+                        expected: "a real number",
+                        found: "a complex number",
+                        span: args[i].span,
+                    })
+                }
+            }
+        } else {
+            let refs: Vec<&Number> = params.iter().collect();
+            let v = Number::with_big(prec, &refs, function.big).unwrap_or_else(|| {
+                let floats: Vec<f64> = params.iter().map(Number::to_f64).collect();
+                Number::Float((function.f)(&floats))
+            });
+            // Real functions without a real result, like sqrt(-1), may have
+            // a complex one.
+            match function.complex {
+                Some(complex) if v.is_nan() => complex_result(complex),
+                _ => v,
+            }
+        };
+        Self::check_domain(expr, params, v)
+    }
+
+    fn call_user(
+        &mut self,
+        def: &FunctionDef,
+        params: Vec<Value>,
+        span: Span,
+    ) -> Result<Value, Error> {
+        if params.len() != def.params.len() {
+            return Err(Error::Arity {
+                name: def.name.clone(),
+                expected: def.params.len(),
+                variadic: false,
+                found: params.len(),
+                span,
+            });
+        }
+        if self.scopes.len() >= MAX_CALL_DEPTH {
+            return Err(Error::RecursionLimit {
+                name: def.name.clone(),
+                span,
+            });
+        }
+
+        let scope = def.params.iter().cloned().zip(params);
+        self.scopes.push(scope.collect());
+        let result = self.eval(&def.body);
+        self.scopes.pop();
+
+        // The body was parsed from an earlier line, so its spans mean nothing
+        // in the current input; point at the call instead.
+        result.map_err(|e| e.with_span(span))
+    }
+
+    fn is_valid_assignment(&self, var: &str) -> bool {
+        match var {
+            "ans" | "if" => false,
+            v => !self.is_builtin(v),
+        }
+    }
+
+    /// Whether `name` is a built-in constant or function, which neither
+    /// variables nor user-defined functions may replace.
+    fn is_builtin(&self, name: &str) -> bool {
+        self.constants.contains_key(name)
+            || self.functions.contains_key(name)
+            || self.array_functions.contains_key(name)
+            || self.int_functions.contains_key(name)
+    }
+
+    /// Parses and evaluates a single statement, returning the formatted
+    /// result. Empty input yields an empty string, and the name of a
+    /// function on its own yields its signature.
+    pub fn run(&mut self, input: &str) -> Result<String, Error> {
+        match self.parse(input)? {
+            Some(stmt) => self.run_stmt(&stmt),
+            None => Ok("".into()),
+        }
+    }
+
+    /// Runs the statements in `input`, separated by `;` or line breaks, and
+    /// returns what `run` would for each. Nothing runs if `input` does not
+    /// parse, and nothing after a statement that fails, so only the last
+    /// result may be an error.
+    pub fn run_all(&mut self, input: &str) -> Vec<Result<String, Error>> {
+        let stmts = match self.parse_all(input) {
+            Ok(stmts) => stmts,
+            Err(e) => return vec![Err(e)],
+        };
+        let mut results = Vec::new();
+        for stmt in &stmts {
+            let result = self.run_stmt(stmt);
+            let failed = result.is_err();
+            results.push(result);
+            if failed {
+                break;
+            }
+        }
+        results
+    }
+
+    /// Executes `stmt`, remembering its value as `ans`, and formats the
+    /// result.
+    fn run_stmt(&mut self, stmt: &Stmt) -> Result<String, Error> {
+        match stmt {
+            Stmt::Expr(expr) => {
+                let val = match self.eval(expr) {
+                    Ok(val) => val,
+                    Err(e) => {
+                        return match &expr.kind {
+                            ExprKind::Var(name) => self.signature(name).ok_or(e),
+                            _ => Err(e),
+                        }
+                    }
+                };
+                self.memory.insert("ans".into(), val.clone());
+                match &expr.kind {
+                    ExprKind::Assign(var, e) => {
+                        Ok(format!("{} = {}", var, self.format_value(&val, e)))
+                    }
+                    _ => Ok(self.format_value(&val, expr)),
+                }
+            }
+            Stmt::Function(def) => {
+                self.define(def)?;
+                Ok(def.to_string())
+            }
         }
     }
 
+    /// What `run` would show for `input`, without assigning variables,
+    /// defining functions or changing `ans`, to preview a line being typed.
+    /// `None` where `run` would show nothing, an error or a definition.
+    pub fn preview(&mut self, input: &str) -> Option<String> {
+        let expr = match self.parse(input) {
+            Ok(Some(Stmt::Expr(expr))) => expr,
+            _ => return None,
+        };
+        let e = match &expr.kind {
+            ExprKind::Assign(var, e) if self.is_valid_assignment(var) => e,
+            ExprKind::Assign(..) => return None,
+            _ => &expr,
+        };
+        self.budget = Some(PREVIEW_BUDGET);
+        let val = self.eval(e);
+        self.budget = None;
+        Some(self.format_value(&val.ok()?, e))
+    }
+
+    /// Takes `cost` steps from the budget of a preview, which fails with
+    /// `TooLarge` once they run out.
+    fn spend(&mut self, cost: u64, span: Span) -> Result<(), Error> {
+        match &mut self.budget {
+            Some(left) if *left < cost => Err(Error::TooLarge { span }),
+            Some(left) => {
+                *left -= cost;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Whether `name` is a constant, a variable or a unit, which expressions
+    /// may use as a value.
+    pub fn is_defined(&self, name: &str) -> bool {
+        self.constants.contains_key(name)
+            || self.memory.contains_key(name)
+            || Unit::lookup(name).is_some()
+    }
+
+    /// The signature of the function `name`, as in `log(n, base)`, or the
+    /// definition of a user-defined one.
+    pub fn signature(&self, name: &str) -> Option<String> {
+        if let Some(f) = self.functions.get(name) {
+            return Some(f.desc.to_owned());
+        }
+        if let Some(f) = self.array_functions.get(name) {
+            return Some(f.desc.to_owned());
+        }
+        if let Some(f) = self.int_functions.get(name) {
+            return Some(f.desc.to_owned());
+        }
+        self.user_functions.get(name).map(|def| def.to_string())
+    }
+
+    /// `val`, the value of `expr`, in the format `expr` asks for or else the
+    /// session's.
+    fn format_value(&self, val: &Value, expr: &Expr) -> String {
+        let prec = self.prec_bits();
+        let format = match &expr.kind {
+            ExprKind::Convert(_, b) => format_target(b),
+            _ => None,
+        }
+        .unwrap_or(self.format);
+        let real = |n: &Number| {
+            let decimal = match n {
+                Number::Int(_) => n.to_string(),
+                _ if self.exact && format == Format::Decimal => n.to_string(),
+                _ => n.to_approx_string(prec),
+            };
+            format.apply(n, &decimal, self.word_size).unwrap_or(decimal)
+        };
+        val.to_string_with(|n| match n {
+            Number::Complex(c) if self.polar => c.to_polar_string(prec, real),
+            // Radix formats leave complex numbers, as all but integers, alone.
+            Number::Complex(c) if !matches!(format, Format::Radix(_)) => c.to_string_with(real),
+            n => real(n),
+        })
+    }
+
+    /// Forgets all variables and user-defined functions and resets `ans`
+    /// to 0.
     pub fn reset(&mut self) {
-        self.memory.borrow_mut().clear();
-        self.memory.borrow_mut().insert("ans".into(), 0.0);
+        self.memory.clear();
+        self.user_functions.clear();
+        self.memory.insert("ans".into(), Value::from(0));
     }
 
+    /// Names of the built-in and user-defined functions, sorted.
     pub fn get_functions(&self) -> Vec<&str> {
         let mut v = Vec::new();
-        for (n, _) in self.functions.borrow().iter() {
+        for n in self.functions.keys() {
+            v.push(*n);
+        }
+        for n in self.array_functions.keys() {
             v.push(*n);
         }
+        for n in self.int_functions.keys() {
+            v.push(*n);
+        }
+        for n in self.user_functions.keys() {
+            v.push(n.as_str());
+        }
         v.sort();
         v
     }
 
+    /// Names of the built-in constants, sorted.
     pub fn get_constants(&self) -> Vec<&str> {
         let mut v = Vec::new();
-        for (c, _) in self.constants.iter() {
+        for c in self.constants.keys() {
             v.push(*c);
         }
         v.sort();
         v
     }
+
+    /// Names of the variables, including `ans`, sorted.
+    pub fn get_variables(&self) -> Vec<&str> {
+        let mut v: Vec<&str> = self.memory.keys().map(String::as_str).collect();
+        v.sort();
+        v
+    }
+}
+
+/// Rational values of the trigonometric functions at multiples of 30 and
+/// 45 degrees, and of their inverses, so that `sin(30)` in degrees is
+/// exactly `0.5` rather than `0.49999999999999994`. `turn` is a full turn in
+/// the angle mode. `None` where there is no such value.
+fn exact_trig(trig: Trig, params: &[Number], turn: &Number) -> Option<Number> {
+    let x = &params[0];
+    if !matches!(x, Number::Int(_) | Number::Rational(_)) {
+        return None;
+    }
+    // `x` in `n`ths of a turn, if a whole number of them.
+    let parts = |n: i64| match x.div(turn).mul(&Number::from(n)) {
+        Number::Int(k) => k.mod_floor(&BigInt::from(n)).to_i64(),
+        _ => None,
+    };
+    // The sine of `k` twelfths of a turn.
+    let sin = |k: i64| {
+        let (n, d) = match k.rem_euclid(12) {
+            0 | 6 => (0, 1),
+            3 => (1, 1),
+            9 => (-1, 1),
+            1 | 5 => (1, 2),
+            7 | 11 => (-1, 2),
+            _ => return None,
+        };
+        Some(Number::from(n).div(&Number::from(d)))
+    };
+    let inverse = |ks: std::ops::RangeInclusive<i64>, f: &dyn Fn(i64) -> Option<Number>| {
+        let k = ks
+            .into_iter()
+            .find(|&k| f(k).is_some_and(|y| y.num_eq(x)))?;
+        Some(turn.mul(&Number::from(k)).div(&Number::from(12)))
+    };
+    match trig {
+        Trig::Sin => sin(parts(12)?),
+        Trig::Cos => sin(parts(12)? + 3),
+        Trig::Tan => match parts(8) {
+            Some(k) if k % 2 == 1 => Some(Number::from(if k % 4 == 1 { 1 } else { -1 })),
+            _ => {
+                let k = parts(12)?;
+                let (s, c) = (sin(k)?, sin(k + 3)?);
+                Some(if c.is_zero() {
+                    Number::Float(f64::NAN)
+                } else {
+                    s.div(&c)
+                })
+            }
+        },
+        Trig::Asin => inverse(-3..=3, &sin),
+        Trig::Acos => inverse(0..=6, &|k| sin(k + 3)),
+        Trig::Atan if x.is_zero() => Some(Number::from(0)),
+        Trig::Atan if x.num_eq(&Number::from(1)) || x.num_eq(&Number::from(-1)) => {
+            Some(turn.mul(x).div(&Number::from(8)))
+        }
+        _ => None,
+    }
+}
+
+/// Type error for an argument of an `ArrayFunction`, reported at the call.
+fn type_error(expected: &'static str, found: &Value) -> Error {
+    Error::Type {
+        expected,
+        found: found.type_name(),
+        span: Span::new(0, 0),
+    }
+}
+
+/// Error for vectors or matrices of sizes that do not fit together.
+fn incompatible(a: &Value, b: &Value) -> Error {
+    Error::Dimension {
+        message: format!(
+            "incompatible dimensions: {} and {}",
+            a.shape_name(),
+            b.shape_name()
+        ),
+        span: Span::new(0, 0),
+    }
+}
+
+/// The built-in unit `name` as the quantity 1 of it.
+fn unit(name: &str, span: Span) -> Result<Value, Error> {
+    match Unit::lookup(name) {
+        Some(unit) => Ok(Value::Quantity(Box::new(Quantity::new(
+            Number::from(1),
+            unit,
+        )))),
+        None => Err(Error::UnknownVariable {
+            name: name.to_owned(),
+            span,
+        }),
+    }
+}
+
+/// A number or quantity as a quantity, numbers having no unit.
+fn quantity(v: &Value) -> Result<Quantity, Error> {
+    match v {
+        Value::Number(n) => Ok(Quantity::new(n.clone(), Unit::none())),
+        Value::Quantity(q) => Ok((**q).clone()),
+        v => Err(type_error("a number", v)),
+    }
+}
+
+/// The format named by the target of `a to b`, if `b` names one.
+fn format_target(b: &Expr) -> Option<Format> {
+    match &b.kind {
+        ExprKind::Var(name) => Format::from_name(name),
+        _ => None,
+    }
+}
+
+fn vector(v: &Value) -> Result<&[Number], Error> {
+    match v {
+        Value::Vector(v) => Ok(v),
+        v => Err(type_error("a vector", v)),
+    }
+}
+
+fn square(v: &Value) -> Result<&Matrix, Error> {
+    match v {
+        Value::Matrix(m) if m.is_square() => Ok(m),
+        Value::Matrix(_) => Err(Error::Dimension {
+            message: format!("expected a square matrix, found {}", v.shape_name()),
+            span: Span::new(0, 0),
+        }),
+        v => Err(type_error("a matrix", v)),
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::calc::Calculator;
+    use crate::calc::{AngleMode, Calculator, Error, Format, Number, Radix, Span, Value};
+    use num_bigint::BigInt;
+    use std::num::NonZeroU32;
 
     macro_rules! run_test {
         ($input:expr, $expected: expr) => {
@@ -400,7 +2106,7 @@ mod test {
     fn constants() {
         run_test!("PI", format!("{}", ::std::f64::consts::PI));
         run_test!("E", format!("{}", ::std::f64::consts::E));
-        run_test!("INF", format!("{}", ::std::f64::INFINITY));
+        run_test!("INF", format!("{}", f64::INFINITY));
     }
 
     #[test]
@@ -428,7 +2134,7 @@ mod test {
         assert_eq!(calc.run("5*4").unwrap(), "20");
         assert_eq!(
             calc.run("12/10").unwrap(),
-            (12.0 as f64 / 10.0 as f64).to_string()
+            (12.0_f64 / 10.0_f64).to_string()
         );
         assert_eq!(calc.run("2+5").unwrap(), "7");
         assert_eq!(calc.run("ans").unwrap(), "7");
@@ -448,6 +2154,45 @@ mod test {
         run_test!(calc, "-(a^2)", "-1");
     }
 
+    #[test]
+    fn defined() {
+        let mut calc = Calculator::new();
+        run_test!(calc, "a = -1", "a = -1");
+        assert_eq!(calc.get_variables(), vec!["a", "ans"]);
+        assert!(calc.is_defined("a") && calc.is_defined("PI") && calc.is_defined("km"));
+        assert!(!calc.is_defined("b") && !calc.is_defined("sin"));
+    }
+
+    #[test]
+    fn preview() {
+        let mut calc = Calculator::new();
+        run_test!(calc, "a = -1", "a = -1");
+
+        // Previews leave variables and `ans` alone.
+        assert_eq!(calc.preview("a * 3").as_deref(), Some("-3"));
+        assert_eq!(calc.preview("b = a + 1").as_deref(), Some("0"));
+        assert_eq!(calc.preview("255 to hex").as_deref(), Some("0xff"));
+        assert_eq!(calc.preview("b"), None);
+        assert_eq!(calc.preview("a +"), None);
+        assert_eq!(calc.preview("f(x) = x"), None);
+        assert_eq!(calc.preview("PI = 3"), None);
+        run_test!(calc, "ans", "-1");
+        assert!(!calc.get_functions().contains(&"f"));
+
+        // Or give up on lines too slow to evaluate while typing.
+        run_test!(
+            calc,
+            "g(n) = n < 1 ? 1 : g(n - 1) + g(n - 1)",
+            "g(n) = n < 1 ? 1 : g(n - 1) + g(n - 1)"
+        );
+        assert_eq!(calc.preview("g(5)").as_deref(), Some("32"));
+        assert_eq!(calc.preview("g(30)"), None);
+        assert_eq!(calc.preview("1000!").map(|s| s.len()), Some(2568));
+        assert_eq!(calc.preview("10000! + 10000!"), None);
+        assert_eq!(calc.preview("7^300000 * 7^300000"), None);
+        run_test!(calc, "g(10)", "1024");
+    }
+
     #[test]
     fn builtins() {
         run_test!("sin(PI)", format!("{}", ::std::f64::consts::PI.sin_cos().0));
@@ -472,8 +2217,8 @@ mod test {
         run_test!("sinh(20)", format!("{}", 20_f64.sinh()));
         run_test!("cosh(20)", format!("{}", 20_f64.cosh()));
         run_test!("tanh(20)", format!("{}", 20_f64.tanh()));
-        run_test!("asin(20)", format!("{}", 20_f64.asin()));
-        run_test!("acos(20)", format!("{}", 20_f64.acos()));
+        run_test!("asin(0.5)", format!("{}", 0.5_f64.asin()));
+        run_test!("acos(0.5)", format!("{}", 0.5_f64.acos()));
         run_test!("atan(20)", format!("{}", 20_f64.atan()));
         run_test!("atan2(10, 20)", format!("{}", 20_f64.atan2(10_f64)));
         run_test!("sin", "sin(n)");
@@ -541,4 +2286,854 @@ mod test {
         run_test!("(2+2)", "4");
         run_test!("(-2+2)", "0");
     }
+
+    #[test]
+    fn ast() {
+        let mut calc = Calculator::new();
+
+        let expr = calc.parse("a = -(1+2)*x^2").unwrap().unwrap();
+        assert_eq!(expr.to_string(), "a = -(1 + 2) * x ^ 2");
+        let expr = calc.parse("(1-2)-(3-4)").unwrap().unwrap();
+        assert_eq!(expr.to_string(), "1 - 2 - (3 - 4)");
+        let expr = calc.parse("(2^3)^2 + max(1, 2)").unwrap().unwrap();
+        assert_eq!(expr.to_string(), "(2 ^ 3) ^ 2 + max(1, 2)");
+        assert!(calc.parse("").unwrap().is_none());
+
+        let stmt = calc.parse("x*2").unwrap().unwrap();
+        run_test!(calc, "x = 1", "x = 1");
+        assert_eq!(calc.exec(&stmt).unwrap(), Some(Value::from(2)));
+        run_test!(calc, "x = 21", "x = 21");
+        assert_eq!(calc.exec(&stmt).unwrap(), Some(Value::from(42)));
+    }
+
+    #[test]
+    fn syntax_errors() {
+        let mut calc = Calculator::new();
+        let err = calc.run("1+(2*3").unwrap_err();
+        assert_eq!(err.to_string(), "expected ')' to close '(' at column 3");
+        assert_eq!(err.span(), Span::new(6, 6));
+
+        let err = calc.run("max(1, 2").unwrap_err();
+        assert_eq!(err.to_string(), "expected ')' to close '(' at column 4");
+
+        let err = calc.run("2 * ) + 1").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "expected a number, variable, function call, '(' or '[', found ')'"
+        );
+        assert_eq!(err.span(), Span::new(4, 5));
+
+        let err = calc.run("1 + 2 $").unwrap_err();
+        assert_eq!(err.to_string(), "invalid character '$'");
+        assert_eq!(err.span(), Span::new(6, 7));
+
+        let err = calc.run("a)").unwrap_err();
+        assert_eq!(err.to_string(), "unexpected ')'");
+        assert_eq!(err.span(), Span::new(1, 2));
+    }
+
+    #[test]
+    fn statements() {
+        let mut calc = Calculator::new();
+        let results: Vec<String> = calc
+            .run_all("a = 2; b = a^2\n\nf(x) = (x +\n  b)\n;f(a);; 10 / 4;")
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(results, ["a = 2", "b = 4", "f(x) = x + b", "6", "2.5"]);
+        run_test!(calc, "ans", "2.5");
+        assert!(calc.run_all(" ;\n ").is_empty());
+        assert_eq!(calc.parse_all("1; 2\n3").unwrap().len(), 3);
+
+        // Statements after an error do not run, nor any if one does not parse.
+        let results = calc.run_all("c = 1; d; c = 2");
+        assert_eq!(results.len(), 2);
+        match &results[1] {
+            Err(Error::UnknownVariable { name, span }) => {
+                assert_eq!(name, "d");
+                assert_eq!(*span, Span::new(7, 8));
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+        run_test!(calc, "c", "1");
+        let results = calc.run_all("c = 3\nc = )");
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].as_ref().unwrap_err().span(), Span::new(10, 11));
+        run_test!(calc, "c", "1");
+        let err = calc.run_all("1 + 2 3").remove(0).unwrap_err();
+        assert_eq!(err.to_string(), "unexpected '3'");
+
+        // Comments are skipped, and do not end a statement.
+        let results: Vec<String> = calc
+            .run_all("# setup\nr = 2 /* m */; h = 10 # m\nv = PI * r^2 * # base\n  h")
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(results.len(), 3);
+        run_test!(calc, "2 * /* twice */ 3 # six", "6");
+        run_test!(calc, "# nothing", "");
+        let err = calc.run("1 + /* 2").unwrap_err();
+        assert_eq!(err.to_string(), "unterminated comment");
+        assert_eq!(err.span(), Span::new(4, 8));
+        assert!(calc.needs_more("1 /* open"));
+
+        // `run` takes a single statement.
+        run_test_err!(calc, "1; 2");
+        let err = calc.run("1\n2").unwrap_err();
+        assert_eq!(err.to_string(), "unexpected line break");
+
+        assert!(calc.needs_more("sin(1,"));
+        assert!(calc.needs_more("[1, 2,\n 3"));
+        assert!(calc.needs_more("x = 1 +"));
+        assert!(calc.needs_more("c > 0 ?"));
+        assert!(!calc.needs_more("1 + 2; 3"));
+        assert!(!calc.needs_more("5!"));
+        assert!(!calc.needs_more(""));
+    }
+
+    #[test]
+    fn error_kinds() {
+        let mut calc = Calculator::new();
+        match calc.run("1 + foo * 2") {
+            Err(Error::UnknownVariable { name, span }) => {
+                assert_eq!(name, "foo");
+                assert_eq!(span, Span::new(4, 7));
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+        match calc.run("foo(1)") {
+            Err(Error::UnknownFunction { name, .. }) => assert_eq!(name, "foo"),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        match calc.run("2 * atan2(1)") {
+            Err(Error::Arity {
+                expected,
+                variadic,
+                found,
+                span,
+                ..
+            }) => {
+                assert_eq!((expected, variadic, found), (2, false, 1));
+                assert_eq!(span, Span::new(4, 12));
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+        match calc.run("max(1)") {
+            Err(e @ Error::Arity { .. }) => {
+                assert_eq!(e.to_string(), "max requires 2 or more arguments")
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+        match calc.run("PI = 3") {
+            Err(Error::InvalidAssignment { name, .. }) => assert_eq!(name, "PI"),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        match calc.run("1 + gamma(-1)") {
+            Err(e @ Error::Domain { .. }) => {
+                assert_eq!(e.to_string(), "gamma(-1) is undefined");
+                assert_eq!(e.span(), Span::new(4, 13));
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert!(matches!(calc.run("0/0"), Err(Error::Domain { .. })));
+        assert!(matches!(calc.run("(1"), Err(Error::Syntax { .. })));
+    }
+
+    #[test]
+    fn user_functions() {
+        let mut calc = Calculator::new();
+        run_test!(calc, "f(x, y) = x^2 + y", "f(x, y) = x ^ 2 + y");
+        run_test!(calc, "f(3, 1)", "10");
+        run_test!(calc, "f(2, f(1, 1)) * 2", "12");
+        run_test!(calc, "f", "f(x, y) = x ^ 2 + y");
+        assert!(calc.get_functions().contains(&"f"));
+        assert_eq!(calc.signature("f").unwrap(), "f(x, y) = x ^ 2 + y");
+        assert_eq!(calc.signature("log").unwrap(), "log(n, base)");
+        assert_eq!(calc.signature("x"), None);
+
+        // Parameters shadow variables, everything else is looked up on call.
+        run_test!(calc, "x = 100", "x = 100");
+        run_test!(calc, "k = 2", "k = 2");
+        run_test!(calc, "g(x) = k*x", "g(x) = k * x");
+        run_test!(calc, "g(5)", "10");
+        run_test!(calc, "k = 3", "k = 3");
+        run_test!(calc, "g(5)", "15");
+        run_test!(calc, "g(x) = x - 1", "g(x) = x - 1");
+        run_test!(calc, "g(5)", "4");
+        run_test!(calc, "h() = PI", "h() = PI");
+        run_test!(calc, "h()", format!("{}", std::f64::consts::PI));
+
+        match calc.run("f(1)") {
+            Err(Error::Arity {
+                expected, found, ..
+            }) => assert_eq!((expected, found), (2, 1)),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        run_test!(calc, "u(x) = x + z", "u(x) = x + z");
+        match calc.run("1 + u(2)") {
+            Err(Error::UnknownVariable { name, span }) => {
+                assert_eq!(name, "z");
+                assert_eq!(span, Span::new(4, 8));
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+        match calc.run("r(x) = r(x) + 1") {
+            Ok(_) => assert!(matches!(
+                calc.run("r(1)"),
+                Err(Error::RecursionLimit { .. })
+            )),
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        run_test_err!(calc, "sin(x) = x");
+        run_test_err!(calc, "popcount(x) = x + 100");
+        run_test_err!(calc, "popcount = 1");
+        run_test!(calc, "popcount(7)", "3");
+        run_test_err!(calc, "q(PI) = PI");
+        run_test_err!(calc, "q(x, x) = x");
+        run_test_err!(calc, "q(2) = 2");
+        run_test_err!(calc, "q(x) + 1 = 2");
+
+        calc.reset();
+        run_test_err!(calc, "f(3, 1)");
+    }
+
+    #[test]
+    fn implicit_multiplication() {
+        let mut calc = Calculator::new();
+        run_test_err!(calc, "2PI");
+        run_test_err!(calc, "3(1+1)");
+
+        calc.set_implicit_multiplication(true);
+        run_test!(calc, "x = 3", "x = 3");
+        run_test!(calc, "2PI", format!("{}", 2.0 * std::f64::consts::PI));
+        run_test!(calc, "3(x+1)", "12");
+        run_test!(calc, "(x)(x+1)", "12");
+        run_test!(calc, "(x+1)x", "12");
+        run_test!(calc, "2x^2", "18");
+        run_test!(calc, "-2x", "-6");
+        run_test!(calc, "1/2x", "1.5");
+        run_test!(calc, "y = 2", "y = 2");
+        run_test!(calc, "x y", "6");
+        run_test!(calc, "2 sin(0) + 1", "1");
+        run_test!(calc, "2e3", "2000");
+        run_test_err!(calc, "2 3");
+        run_test_err!(calc, "2e");
+
+        let stmt = calc.parse("2(x+1)x").unwrap().unwrap();
+        assert_eq!(stmt.to_string(), "2 * (x + 1) * x");
+        let stmt = calc.parse("2x(x+1)").unwrap().unwrap();
+        assert_eq!(stmt.to_string(), "2 * x(x + 1)");
+    }
+
+    #[test]
+    fn postfix() {
+        run_test!("5!", "120");
+        run_test!("0!", "1");
+        run_test!("3!!", "3");
+        run_test!("8!!", "384");
+        run_test!("(3!)!", "720");
+        run_test!("-3!", "-6");
+        run_test!("2^3!", "64");
+        run_test!("3!^2", "36");
+        run_test!("(1+2)! * 2", "12");
+        run_test!("20!", "2432902008176640000");
+        run_test_err!("(-1)!");
+        run_test_err!("(-2)!!");
+
+        let mut calc = Calculator::new();
+        let approx = |calc: &mut Calculator, input: &str, expected: f64| {
+            let stmt = calc.parse(input).unwrap().unwrap();
+            let v = calc.exec(&stmt).unwrap().unwrap().to_f64().unwrap();
+            assert!((v - expected).abs() < 1e-12, "{} => {}", input, v);
+        };
+        approx(&mut calc, "0.5!", std::f64::consts::PI.sqrt() / 2.0);
+        approx(&mut calc, "(-0.5)!", std::f64::consts::PI.sqrt());
+        approx(&mut calc, "gamma(5)", 24.0);
+        approx(&mut calc, "(-3)!!", -1.0);
+        approx(&mut calc, "gamma(171.5) / gamma(170.5) / 170.5", 1.0);
+        run_test!("gamma(200)", "inf");
+        run_test!("gamma(1000)", "inf");
+        run_test!("1000.5!", "inf");
+        run_test!("gamma(-200.5)", "-0");
+        assert!(matches!(
+            Calculator::new().run("10001!"),
+            Err(Error::TooLarge { .. })
+        ));
+        assert!(matches!(
+            Calculator::new().run("20001!!"),
+            Err(Error::TooLarge { .. })
+        ));
+
+        run_test!("50%", "0.5");
+        run_test!("200 * 15%", "30");
+        run_test!("(50%) + 1", "1.5");
+        run_test!("15% * 200", "30");
+        run_test!("26 % 12", "2");
+        run_test!("26 % (12)", "2");
+        run_test!("26%-12", "2");
+        run_test!("26 % +12", "2");
+        run_test!("(10)%", "0.1");
+        run_test!("max(10%, 1)", "1");
+
+        let stmt = Calculator::new().parse("26 % (-12) + 3!% * (2!)!").unwrap();
+        assert_eq!(stmt.unwrap().to_string(), "26 % (-12) + (3!)% * (2!)!");
+    }
+
+    #[test]
+    fn logic() {
+        run_test!("1 < 2", "true");
+        run_test!("1 >= 2", "false");
+        run_test!("2 == 2.0", "true");
+        run_test!("1 + 1 != 2", "false");
+        run_test!("3! == 6", "true");
+        run_test!("true && !false", "true");
+        run_test!("!!true", "true");
+        run_test!("!(1 < 2) || 2 <= 2", "true");
+        run_test!("(1 < 2) == true", "true");
+        run_test!("true ? 1 : 2", "1");
+        run_test!("false ? 1 : true ? 2 : 3", "2");
+        run_test!("1 > 2 ? 1 : 2 > 1 ? 5 : 6", "5");
+        run_test!("if(2 > 1, 10, 20) * 2", "20");
+
+        let mut calc = Calculator::new();
+        run_test!(
+            calc,
+            "tax(x) = x <= 1000 ? 0 : x <= 5000 ? (x - 1000) * 10% : 400 + (x - 5000) * 20%",
+            "tax(x) = x <= 1000 ? 0 : x <= 5000 ? (x - 1000) * 10% : 400 + (x - 5000) * 20%"
+        );
+        run_test!(calc, "tax(800)", "0");
+        run_test!(calc, "tax(3000)", "200");
+        run_test!(calc, "tax(6000)", "600");
+        run_test!(
+            calc,
+            "fact(n) = if(n <= 1, 1, n * fact(n - 1))",
+            "fact(n) = n <= 1 ? 1 : n * fact(n - 1)"
+        );
+        run_test!(calc, "fact(10)", "3628800");
+        run_test!(calc, "b = 2 > 1", "b = true");
+        run_test!(calc, "ans", "true");
+        run_test!(calc, "true || undefined_var == 1", "true");
+        run_test!(calc, "false && undefined_var == 1", "false");
+
+        match calc.run("1 + true") {
+            Err(e @ Error::Type { .. }) => {
+                assert_eq!(e.to_string(), "expected a number, found a boolean");
+                assert_eq!(e.span(), Span::new(4, 8));
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+        run_test_err!(calc, "1 ? 2 : 3");
+        run_test_err!(calc, "sin(true)");
+        run_test_err!(calc, "1 == true");
+        run_test_err!(calc, "1 < 2 < 3");
+        run_test_err!(calc, "if(true, 1)");
+        run_test_err!(calc, "true = 1");
+        run_test_err!(calc, "if = 1");
+    }
+
+    #[test]
+    fn big_integers() {
+        run_test!("2^64 + 1", "18446744073709551617");
+        run_test!("2^53 + 1", "9007199254740993");
+        run_test!("-2^63", "-9223372036854775808");
+        run_test!(
+            "123456789012345678901234567890 * 987654321",
+            "121932631124828532112482853211126352690"
+        );
+        run_test!("(2^127 - 1) % 1000000007", "639816141");
+        run_test!("-7 % 3", "-1");
+        run_test!("2^100 / 2^98", "4");
+        run_test!("7 / 2", "3.5");
+        run_test!("2^-1", "0.5");
+        run_test!("2^0.5", format!("{}", 2_f64.sqrt()));
+        run_test!("sqrt(4) + 2^64", "18446744073709552000");
+        run_test!("25!", "15511210043330985984000000");
+        run_test!("31!!", "191898783962510625");
+        run_test!("(-1)^1000001", "-1");
+        run_test!("0^-1", "inf");
+        run_test!("0^-1 == 1/0", "true");
+        for input in [
+            "2^10000000",
+            "3^1000000",
+            "2^999999",
+            "[2, 3]^1000000",
+            "0.5^-(2^30)",
+        ] {
+            match Calculator::new().run(input) {
+                Err(e @ Error::TooLarge { .. }) => assert_eq!(e.to_string(), "result too large"),
+                r => panic!("unexpected result for {}: {:?}", input, r),
+            }
+        }
+        run_test!("2^(1/2) > 1.41", "true");
+        run_test!("2^64 == 2^64 + 1", "false");
+        run_test!("2^64 < 2^64 + 1", "true");
+        run_test!("2 == 2.0", "true");
+
+        let mut calc = Calculator::new();
+        run_test!(calc, "id = 2^70 + 12345", "id = 1180591620717411315769");
+        run_test!(calc, "id - 2^70", "12345");
+        run_test_err!(calc, "5 % 0");
+
+        let stmt = calc.parse("2^64").unwrap().unwrap();
+        assert_eq!(
+            calc.exec(&stmt).unwrap(),
+            Some(Value::Number(Number::Int(BigInt::from(1u128 << 64))))
+        );
+    }
+
+    #[test]
+    fn radix_literals() {
+        let mut calc = Calculator::new();
+        run_test!(calc, "0xff", "255");
+        run_test!(calc, "0XFF + 0o17 + 0b101", "275");
+        run_test!(calc, "0xdead_beef", "3735928559");
+        run_test!(calc, "1_000_000 * 2", "2000000");
+        run_test!(calc, "1_000.25", "1000.25");
+        run_test!(calc, "1e1_0", "10000000000");
+        run_test!(calc, "0x1.8p3", "12");
+        run_test!(calc, "0x1p-2", "0.25");
+        run_test!(calc, "0x10000000000000000", "18446744073709551616");
+        run_test_err!(calc, "0x.8");
+        run_test_err!(calc, "0b102");
+        run_test_err!(calc, "1__000");
+        run_test_err!(calc, "0x");
+    }
+
+    #[test]
+    fn rationals() {
+        run_test!("0.1 + 0.2", "0.3");
+        run_test!("0.1 + 0.2 == 0.3", "true");
+        run_test!("1/3", "1/3");
+        run_test!("1/3 * 3", "1");
+        run_test!("-2/6", "-1/3");
+        run_test!("1/3 + 1/6", "0.5");
+        run_test!("-1/8", "-0.125");
+        run_test!("0.05 - 0.1", "-0.05");
+        run_test!("(2/3)^2", "4/9");
+        run_test!("(2/3)^-2", "2.25");
+        run_test!("2^-3", "0.125");
+        run_test!("1.5e-3", "0.0015");
+        run_test!("2.5e2", "250");
+        run_test!("7.5 % 2", "1.5");
+        run_test!("-7/2 % 2", "-1.5");
+        run_test!("19.99 * 3", "59.97");
+        run_test!("12.5%", "0.125");
+        run_test!("1/3 < 0.34", "true");
+        run_test!("(1/4)^0.5", "0.5");
+        run_test!("1/3 + sqrt(0)", format!("{}", 1.0 / 3.0));
+        run_test_err!("(1/3) % 0");
+
+        let mut calc = Calculator::new();
+        assert!(calc.exact());
+        run_test!(calc, "x = 2/3", "x = 2/3");
+        calc.set_exact(false);
+        run_test!(calc, "x", format!("{}", 2.0 / 3.0));
+        run_test!(calc, "x * 3", "2");
+        run_test!(calc, "0.1 + 0.2", "0.3");
+        calc.set_exact(true);
+        run_test!(calc, "x + 1/3", "1");
+    }
+
+    #[test]
+    fn precision() {
+        let mut calc = Calculator::new();
+        assert_eq!(calc.precision(), None);
+        run_test!(calc, "sqrt(2)", "1.4142135623730951");
+
+        // No digits cannot be asked for; `new` leaves plain floats.
+        calc.set_precision(NonZeroU32::new(0));
+        assert_eq!(calc.precision(), None);
+        run_test!(calc, "sqrt(2)", "1.4142135623730951");
+
+        calc.set_precision(NonZeroU32::new(50));
+        run_test!(
+            calc,
+            "PI",
+            "3.1415926535897932384626433832795028841971693993751"
+        );
+        calc.set_precision(NonZeroU32::new(40));
+        run_test!(calc, "E", "2.718281828459045235360287471352662497757");
+
+        calc.set_precision(NonZeroU32::new(30));
+        run_test!(calc, "sqrt(2)", "1.41421356237309504880168872421");
+        run_test!(calc, "2^0.5", "1.41421356237309504880168872421");
+        run_test!(calc, "sqrt(2) + 0.1", "1.51421356237309504880168872421");
+        run_test!(calc, "ln(2)", "0.693147180559945309417232121458");
+        run_test!(calc, "gamma(0.5)", "1.77245385090551602729816748334");
+        run_test!(calc, "exp(100)", "2.68811714181613544841262555158e43");
+        run_test!(calc, "atan(1) * 4", "3.14159265358979323846264338328");
+        run_test!(calc, "x = sqrt(2)", "x = 1.41421356237309504880168872421");
+        run_test!(calc, "x^2", "2");
+        run_test!(calc, "floor(PI * 1000)", "3141");
+
+        calc.set_precision(NonZeroU32::new(25));
+        run_test!(calc, "sin(1)", "0.8414709848078965066525023");
+        run_test!(calc, "atan2(-1, 1)", "2.356194490192344928846983");
+        run_test!(calc, "(1/3 - 1)!", "2.678938534707747633655693");
+        run_test!(calc, "cbrt(-27)", "-3");
+        run_test!(calc, "1/3", "1/3");
+        run_test!(calc, "2^64 + 1", "18446744073709551617");
+        run_test!(
+            calc,
+            "asin(2)",
+            "1.570796326794896619231322 - 1.316957896924816708625046i"
+        );
+        run_test!(calc, "sqrt(-1)", "i");
+        run_test_err!(calc, "gamma(-1)");
+
+        calc.set_exact(false);
+        run_test!(calc, "1/3", "0.3333333333333333333333333");
+
+        calc.set_precision(None);
+        run_test!(calc, "PI", format!("{}", std::f64::consts::PI));
+        run_test!(calc, "1/3", format!("{}", 1.0 / 3.0));
+    }
+
+    #[test]
+    fn complex() {
+        let mut calc = Calculator::new();
+        run_test!(calc, "3+4i", "3 + 4i");
+        run_test!(calc, "(1+2i)*(3-i)", "5 + 5i");
+        run_test!(calc, "(1+2i)/(3-i)", "0.1 + 0.7i");
+        run_test!(calc, "1/2 + i/3", "0.5 + (1/3)i");
+        run_test!(calc, "i^2", "-1");
+        run_test!(calc, "-i", "-i");
+        run_test!(calc, "2.5e1i", "25i");
+        run_test!(calc, "sqrt(-4)", "2i");
+        run_test!(calc, "abs(3+4i)", "5");
+        run_test!(calc, "conj(3+4i)", "3 - 4i");
+        run_test!(calc, "re(3+4i) + im(3-4i)", "-1");
+        run_test!(calc, "arg(-1)", format!("{}", std::f64::consts::PI));
+        run_test!(calc, "ln(-1)", format!("{}i", std::f64::consts::PI));
+        run_test!(calc, "i == 1i", "true");
+        run_test!(calc, "(-8)^(1/3)", "1 + 1.732050807568877i");
+        run_test_err!(calc, "i/0");
+        run_test_err!(calc, "log(i, 1)");
+
+        match calc.run("1 < 2i") {
+            Err(e @ Error::Type { .. }) => {
+                assert_eq!(
+                    e.to_string(),
+                    "expected a real number, found a complex number"
+                );
+                assert_eq!(e.span(), Span::new(4, 6));
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert!(matches!(calc.run("gamma(i)"), Err(Error::Type { .. })));
+        assert!(matches!(
+            calc.run("i = 2"),
+            Err(Error::InvalidAssignment { .. })
+        ));
+        run_test!(calc, "2in", "2 in");
+
+        calc.set_polar(true);
+        run_test!(calc, "2i", format!("2 ∠ {}", std::f64::consts::FRAC_PI_2));
+        run_test!(calc, "3", "3");
+
+        calc.set_precision(NonZeroU32::new(25));
+        run_test!(
+            calc,
+            "sqrt(-2)",
+            "1.414213562373095048801689 ∠ 1.570796326794896619231322"
+        );
+    }
+
+    #[test]
+    fn matrices() {
+        let mut calc = Calculator::new();
+        run_test!(calc, "[1, 2] + [3, 4]", "[4, 6]");
+        run_test!(calc, "[1, 2] * [3, 4]", "[3, 8]");
+        run_test!(calc, "2 * [1, 2] - 1", "[1, 3]");
+        run_test!(calc, "[1, 2] / 3", "[1/3, 2/3]");
+        run_test!(calc, "-[1, 2]", "[-1, -2]");
+        run_test!(calc, "sqrt([1, 4, 9])", "[1, 2, 3]");
+        run_test!(calc, "[1, 2] == [1, 2]", "true");
+        run_test!(calc, "[]", "[]");
+
+        run_test!(calc, "A = [[1, 2], [3, 4]]", "A = [[1, 2], [3, 4]]");
+        run_test!(calc, "det(A)", "-2");
+        run_test!(calc, "inv(A)", "[[-2, 1], [1.5, -0.5]]");
+        run_test!(calc, "inv([[1, 2], [3, 3]])", "[[-1, 2/3], [1, -1/3]]");
+        run_test!(calc, "A * inv(A)", "[[1, 0], [0, 1]]");
+        run_test!(calc, "A^-1 == inv(A)", "true");
+        run_test!(calc, "A^3", "[[37, 54], [81, 118]]");
+        run_test!(calc, "A * [1, 1]", "[3, 7]");
+        run_test!(calc, "[1, 1] * A", "[4, 6]");
+        run_test!(calc, "transpose(A)", "[[1, 3], [2, 4]]");
+        run_test!(calc, "transpose([1, 2])", "[[1], [2]]");
+        run_test!(calc, "solve(A, [5, 6])", "[-4, 4.5]");
+        run_test!(calc, "solve(A, A)", "[[1, 0], [0, 1]]");
+        run_test!(calc, "det([[0, 1, 2], [1, 0, 3], [4, -3, 8]])", "-2");
+        run_test!(calc, "dot([1, 2, 3], [4, 5, 6])", "32");
+        run_test!(calc, "cross([1, 0, 0], [0, 1, 0])", "[0, 0, 1]");
+        run_test!(calc, "norm([3, 4])", "5");
+        run_test!(calc, "norm([3i, 4])", "5");
+
+        run_test!(calc, "A[1]", "[3, 4]");
+        run_test!(calc, "A[1, 0]", "3");
+        run_test!(calc, "A[0][1]", "2");
+        run_test!(calc, "[10, 20, 30][2]", "30");
+        run_test!(calc, "f(v) = v[0] + v[1]", "f(v) = v[0] + v[1]");
+        run_test!(calc, "f([1, 2])", "3");
+
+        match calc.run("[1, 2] + [1, 2, 3]") {
+            Err(e @ Error::Dimension { .. }) => {
+                assert_eq!(
+                    e.to_string(),
+                    "incompatible dimensions: a 2-vector and a 3-vector"
+                );
+                assert_eq!(e.span(), Span::new(0, 18));
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+        match calc.run("[1, 2, 3][3]") {
+            Err(e @ Error::Index { .. }) => {
+                assert_eq!(e.to_string(), "invalid index 3 for length 3");
+                assert_eq!(e.span(), Span::new(10, 11));
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert!(matches!(
+            calc.run("[[1, 2], [3]]"),
+            Err(Error::Dimension { .. })
+        ));
+        assert!(matches!(
+            calc.run("det([[1, 2, 3], [4, 5, 6]])"),
+            Err(Error::Dimension { .. })
+        ));
+        assert!(matches!(
+            calc.run("inv([[1, 2], [2, 4]])"),
+            Err(Error::Domain { .. })
+        ));
+        assert!(matches!(calc.run("[1, 2] < 3"), Err(Error::Type { .. })));
+        assert!(matches!(calc.run("det(5)"), Err(Error::Type { .. })));
+        assert!(matches!(calc.run("[1, [2]]"), Err(Error::Type { .. })));
+
+        calc.set_exact(false);
+        run_test!(
+            calc,
+            "inv([[1, 2], [3, 3]])",
+            format!("[[-1, {}], [1, {}]]", 2.0 / 3.0, -1.0 / 3.0)
+        );
+        run_test!(
+            calc,
+            "solve([[2.5, 1], [1, 3]], [1, 2])",
+            format!("[{}, {}]", 1.0 / 6.5, 4.0 / 6.5)
+        );
+    }
+
+    #[test]
+    fn angle_mode() {
+        let mut calc = Calculator::new();
+        assert_eq!(calc.angle_mode(), AngleMode::Radians);
+        run_test!(calc, "asin(1)", format!("{}", std::f64::consts::FRAC_PI_2));
+
+        calc.set_angle_mode(AngleMode::Degrees);
+        run_test!(calc, "sin(30)", "0.5");
+        run_test!(calc, "cos(60)", "0.5");
+        run_test!(calc, "cos(-180)", "-1");
+        run_test!(calc, "sin(720)", "0");
+        run_test!(calc, "tan(135)", "-1");
+        run_test!(calc, "asin(0.5)", "30");
+        run_test!(calc, "acos(-1/2)", "120");
+        run_test!(calc, "atan(-1)", "-45");
+        run_test!(calc, "atan2(0, 1)", "90");
+        run_test!(calc, "sin(1)", format!("{}", 1f64.to_radians().sin()));
+        run_test!(calc, "sin([0, 90])", "[0, 1]");
+        match calc.run("tan(90)") {
+            Err(e @ Error::Domain { .. }) => assert_eq!(e.to_string(), "tan(90) is undefined"),
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        calc.set_angle_mode(AngleMode::Gradians);
+        run_test!(calc, "cos(200)", "-1");
+        run_test!(calc, "asin(1)", "100");
+
+        calc.set_precision(NonZeroU32::new(25));
+        calc.set_angle_mode(AngleMode::Degrees);
+        run_test!(calc, "sin(1)", "0.01745240643728351281941898");
+        run_test!(calc, "sinh(1)", "1.175201193643801456882382");
+    }
+
+    #[test]
+    fn units() {
+        let mut calc = Calculator::new();
+        run_test!(calc, "5 km/h", "5 km/h");
+        run_test!(calc, "3 ft + 2 in", "19/6 ft");
+        run_test!(calc, "3 ft + 2 in to cm", "96.52 cm");
+        run_test!(calc, "60 mph to m/s", "26.8224 m/s");
+        run_test!(calc, "12 in in ft", "1 ft");
+        run_test!(calc, "2in", "2 in");
+        run_test!(calc, "1 kg m/s^2 to N", "1 N");
+        run_test!(calc, "1 km / 1 m", "1000");
+        run_test!(calc, "2 m * 3 m", "6 m^2");
+        run_test!(calc, "(2 m)^-1", "0.5 m^-1");
+        run_test!(calc, "3 mA * 2 kohm to V", "6 V");
+        run_test!(calc, "1 KiB to bit", "8192 bit");
+        run_test!(calc, "1 kWh to MJ", "3.6 MJ");
+        run_test!(calc, "-5 m", "-5 m");
+        run_test!(calc, "50 m%", "0.5 m");
+        run_test!(calc, "1 m == 100 cm", "true");
+        run_test!(calc, "1 ft < 1 m", "true");
+        run_test!(calc, "x = 10 m", "x = 10 m");
+        run_test!(calc, "x / 2 s", "5 m/s");
+        run_test!(calc, "f(v) = v to km/h", "f(v) = v to km / h");
+        run_test!(calc, "f(x / 2 s)", "18 km/h");
+        run_test!(calc, "m = 3", "m = 3");
+        run_test!(calc, "2 m", "6");
+
+        match calc.run("1 s + 1 ft") {
+            Err(e @ Error::Dimension { .. }) => {
+                assert_eq!(e.to_string(), "incompatible units: s and ft");
+                assert_eq!(e.span(), Span::new(0, 10));
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+        match calc.run("1 ft to s") {
+            Err(e @ Error::Dimension { .. }) => {
+                assert_eq!(e.to_string(), "cannot convert ft to s");
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert!(matches!(calc.run("1 ft + 1"), Err(Error::Dimension { .. })));
+        assert!(matches!(
+            calc.run("1 ft ^ 0.5"),
+            Err(Error::Dimension { .. })
+        ));
+        assert!(matches!(calc.run("sin(1 ft)"), Err(Error::Type { .. })));
+        assert!(matches!(calc.run("5 to ft"), Err(Error::Dimension { .. })));
+        assert!(matches!(calc.run("1 ft to 2 ft"), Err(Error::Type { .. })));
+        assert!(matches!(calc.run("[1, 2] * 1 ft"), Err(Error::Type { .. })));
+        run_test_err!(calc, "2 parsec");
+
+        calc.set_exact(false);
+        run_test!(calc, "100 km/h to mph", "62.1371192237334 mph");
+    }
+
+    #[test]
+    fn formats() {
+        let mut calc = Calculator::new();
+        assert_eq!(calc.format(), Format::Decimal);
+        run_test!(calc, "255 to hex", "0xff");
+        run_test!(calc, "ans", "255");
+        run_test!(calc, "5 to bin", "0b101");
+        run_test!(calc, "8 to oct", "0o10");
+        run_test!(calc, "-1 to hex", "-0x1");
+        run_test!(calc, "x = 2^70 to hex", "x = 0x400000000000000000");
+        run_test!(calc, "1/2 to hex", "0.5");
+        run_test!(calc, "[10, 11] to hex", "[0xa, 0xb]");
+        run_test!(calc, "12300 to sci", "1.23e4");
+        run_test!(calc, "-0.00012 to sci", "-1.2e-4");
+        run_test!(calc, "12300 to eng", "12.3e3");
+        run_test!(calc, "0.0012 to eng", "1.2e-3");
+        run_test!(calc, "4700 to si", "4.7k");
+        run_test!(calc, "0 to sci", "0e0");
+
+        calc.set_word_size(NonZeroU32::new(8));
+        run_test!(calc, "-1 to hex", "0xff");
+        run_test!(calc, "-128 to bin", "0b10000000");
+        run_test!(calc, "-129 to hex", "0x7f");
+        run_test!(calc, "-1", "-1");
+
+        calc.set_format(Format::Fixed(2));
+        run_test!(calc, "2/3", "0.67");
+        run_test!(calc, "9.999", "10.00");
+        run_test!(calc, "-0.001", "0.00");
+        run_test!(calc, "12", "12.00");
+        run_test!(calc, "12 to dec", "12");
+        run_test!(calc, "3 ft", "3.00 ft");
+        run_test!(calc, "1/3 + i", "0.33 + 1.00i");
+        run_test!(calc, "2 - i/4 to sci", "2e0 - 2.5e-1i");
+
+        calc.set_format(Format::Fixed(usize::MAX));
+        assert_eq!(calc.format().to_string(), "fixed 1000");
+        assert_eq!(calc.run("1/8").unwrap().len(), 1002);
+
+        calc.set_format(Format::Radix(Radix::Hex));
+        assert_eq!(calc.format().to_string(), "hex");
+        run_test!(calc, "-2", "0xfe");
+        run_test!(calc, "1.5", "1.5");
+        run_test!(calc, "1 + 2i", "1 + 2i");
+    }
+
+    #[test]
+    fn bitwise() {
+        let mut calc = Calculator::new();
+        run_test!(calc, "6 & 3", "2");
+        run_test!(calc, "6 | 3", "7");
+        run_test!(calc, "6 xor 3", "5");
+        run_test!(calc, "~5", "-6");
+        run_test!(calc, "-6 & 0xff", "250");
+        run_test!(calc, "1 << 4", "16");
+        run_test!(calc, "1 << -1", "0");
+        run_test!(calc, "-16 >> 2", "-4");
+        run_test!(calc, "16 >>> 2", "4");
+        run_test!(calc, "1 << 2 + 1", "8");
+        run_test!(calc, "1 | 2 xor 3 & 6", "1");
+        run_test!(calc, "5 & 0xf == 5", "true");
+        run_test!(calc, "[1, 2, 3] & 1", "[1, 0, 1]");
+        run_test!(calc, "2^100 >> 98", "4");
+        run_test!(calc, "popcount(255)", "8");
+        run_test!(calc, "ctz(8)", "3");
+        run_test!(calc, "ctz(-8)", "3");
+        run_test!(calc, "f(x) = x & ~1 | 2", "f(x) = x & ~1 | 2");
+        run_test!(calc, "f(5)", "6");
+        run_test!(calc, "x = 3", "x = 3");
+        run_test!(calc, "x xor 1", "2");
+        run_test!(calc, "popcount", "popcount(n)");
+        assert!(matches!(calc.run("1.5 & 1"), Err(Error::Domain { .. })));
+        assert!(matches!(calc.run("-16 >>> 2"), Err(Error::Domain { .. })));
+        assert!(matches!(
+            calc.run("popcount(-1)"),
+            Err(Error::Domain { .. })
+        ));
+        assert!(matches!(calc.run("clz(1)"), Err(Error::Domain { .. })));
+        assert!(matches!(calc.run("1 << 2^30"), Err(Error::Domain { .. })));
+        assert!(matches!(calc.run("popcount(0.5)"), Err(Error::Type { .. })));
+        assert!(matches!(calc.run("1 ft & 1"), Err(Error::Type { .. })));
+        run_test_err!(calc, "1 & & 1");
+
+        calc.set_word_size(NonZeroU32::new(8));
+        run_test!(calc, "0xff", "-1");
+        run_test!(calc, "127 + 1", "-128");
+        run_test!(calc, "200 * 2", "-112");
+        run_test!(calc, "~0 to hex", "0xff");
+        run_test!(calc, "-16 >>> 2", "60");
+        run_test!(calc, "clz(1)", "7");
+        run_test!(calc, "ctz(0)", "8");
+        run_test!(calc, "popcount(-1)", "8");
+        run_test!(calc, "rotl(0x81, 1) to hex", "0x3");
+        run_test!(calc, "rotr(1, 1) to bin", "0b10000000");
+        run_test!(calc, "rotr(1, 9) to bin", "0b10000000");
+        run_test!(calc, "1.5 + 1", "2.5");
+
+        calc.set_word_size(NonZeroU32::new(16));
+        run_test!(calc, "0xffff to hex", "0xffff");
+        run_test!(calc, "1 << 15", "-32768");
+
+        calc.set_wrapping(false);
+        run_test!(calc, "0xffff", "-1");
+        run_test!(calc, "-32767 - 1", "-32768");
+        assert!(matches!(calc.run("32767 + 1"), Err(Error::Overflow { .. })));
+        assert!(matches!(
+            calc.run("-32768 - 1"),
+            Err(Error::Overflow { .. })
+        ));
+        match calc.run("0x7fff * 4") {
+            Err(e @ Error::Overflow { .. }) => {
+                assert_eq!(e.to_string(), "result does not fit in 16 bits");
+                assert_eq!(e.span(), Span::new(0, 10));
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert!(matches!(calc.run("0x10000"), Err(Error::Overflow { .. })));
+        assert!(matches!(calc.run("1 << 16"), Err(Error::Overflow { .. })));
+
+        // A word of no bits cannot be asked for; `new` gives no limit.
+        calc.set_word_size(NonZeroU32::new(0));
+        assert_eq!(calc.word_size(), None);
+        run_test!(calc, "0xffff * 2", "131070");
+    }
 }
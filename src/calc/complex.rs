@@ -0,0 +1,302 @@
+use crate::calc::{BigFloat, Number};
+use num_traits::ToPrimitive;
+use std::fmt;
+
+/// `re + im i`. The parts are real numbers and keep their exactness, so
+/// `(1 + 2i) * (3 - i)` is exactly `5 + 5i`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Complex {
+    /// The real part.
+    pub re: Number,
+    /// The imaginary part.
+    pub im: Number,
+}
+
+impl Complex {
+    /// `re + im i`
+    pub fn new(re: Number, im: Number) -> Self {
+        Complex { re, im }
+    }
+
+    /// `i`
+    pub fn i() -> Self {
+        Complex::new(Number::from(0), Number::from(1))
+    }
+
+    /// A real number as a complex one.
+    pub fn real(re: Number) -> Self {
+        Complex::new(re, Number::from(0))
+    }
+
+    /// A complex number, or a real one if the imaginary part is zero.
+    pub fn into_number(self) -> Number {
+        if self.im.is_zero() {
+            self.re
+        } else {
+            Number::Complex(Box::new(self))
+        }
+    }
+
+    /// `self + other`
+    pub fn add(&self, other: &Complex) -> Complex {
+        Complex::new(self.re.add(&other.re), self.im.add(&other.im))
+    }
+
+    /// `self - other`
+    pub fn sub(&self, other: &Complex) -> Complex {
+        Complex::new(self.re.sub(&other.re), self.im.sub(&other.im))
+    }
+
+    /// `self * other`
+    pub fn mul(&self, other: &Complex) -> Complex {
+        Complex::new(
+            self.re.mul(&other.re).sub(&self.im.mul(&other.im)),
+            self.re.mul(&other.im).add(&self.im.mul(&other.re)),
+        )
+    }
+
+    /// `self / other`
+    pub fn div(&self, other: &Complex) -> Complex {
+        let d = other.re.mul(&other.re).add(&other.im.mul(&other.im));
+        let conj = self.mul(&other.conj());
+        Complex::new(conj.re.div(&d), conj.im.div(&d))
+    }
+
+    /// `-self`
+    pub fn neg(&self) -> Complex {
+        Complex::new(self.re.neg(), self.im.neg())
+    }
+
+    /// The complex conjugate, `re - im i`.
+    pub fn conj(&self) -> Complex {
+        Complex::new(self.re.clone(), self.im.neg())
+    }
+
+    /// Multiplies by a real number.
+    fn scale(&self, k: &Number) -> Complex {
+        Complex::new(self.re.mul(k), self.im.mul(k))
+    }
+
+    /// Whether either part is NaN.
+    pub fn is_nan(&self) -> bool {
+        self.re.is_nan() || self.im.is_nan()
+    }
+
+    /// Distance from zero, `sqrt(re^2 + im^2)`.
+    pub fn abs(&self, prec: Option<u32>) -> Number {
+        self.re
+            .mul(&self.re)
+            .add(&self.im.mul(&self.im))
+            .real_fn(prec, f64::sqrt, BigFloat::sqrt)
+    }
+
+    /// Angle to the positive real axis, in `(-pi, pi]`.
+    pub fn arg(&self, prec: Option<u32>) -> Number {
+        Number::real_fn2(&self.im, &self.re, prec, f64::atan2, |y, x| {
+            Some(y.atan2(x))
+        })
+    }
+
+    /// `e^self`
+    pub fn exp(&self, prec: Option<u32>) -> Complex {
+        let r = self.re.real_fn(prec, f64::exp, BigFloat::exp);
+        Complex::new(
+            r.mul(&self.im.real_fn(prec, f64::cos, BigFloat::cos)),
+            r.mul(&self.im.real_fn(prec, f64::sin, BigFloat::sin)),
+        )
+    }
+
+    /// Principal natural logarithm, `None` at zero.
+    pub fn ln(&self, prec: Option<u32>) -> Option<Complex> {
+        if self.re.is_zero() && self.im.is_zero() {
+            return None;
+        }
+        let abs = self.abs(prec);
+        Some(Complex::new(
+            abs.real_fn(prec, f64::ln, BigFloat::ln),
+            self.arg(prec),
+        ))
+    }
+
+    /// Principal square root, exact for perfect squares like `-4`.
+    pub fn sqrt(&self, prec: Option<u32>) -> Complex {
+        let abs = self.abs(prec);
+        let two = Number::from(2);
+        let re = abs.add(&self.re).div(&two);
+        let im = abs.sub(&self.re).div(&two);
+        let im = im.real_fn(prec, f64::sqrt, BigFloat::sqrt);
+        Complex::new(
+            re.real_fn(prec, f64::sqrt, BigFloat::sqrt),
+            if self.im.is_negative() { im.neg() } else { im },
+        )
+    }
+
+    /// Principal value of `self^w`, exact for small integer powers.
+    pub fn pow(&self, w: &Complex, prec: Option<u32>) -> Option<Complex> {
+        if let (Number::Int(n), true) = (&w.re, w.im.is_zero()) {
+            if let Some(n) = n.to_i64().filter(|n| n.unsigned_abs() <= 64) {
+                let mut acc = Complex::real(Number::from(1));
+                for _ in 0..n.unsigned_abs() {
+                    acc = acc.mul(self);
+                }
+                return Some(if n < 0 {
+                    Complex::real(Number::from(1)).div(&acc)
+                } else {
+                    acc
+                });
+            }
+        }
+        if self.re.is_zero() && self.im.is_zero() {
+            return if w.re.is_positive() {
+                Some(Complex::real(Number::from(0)))
+            } else {
+                None
+            };
+        }
+        Some(w.mul(&self.ln(prec)?).exp(prec))
+    }
+
+    /// `sin(re) cosh(im) + cos(re) sinh(im) i`
+    pub fn sin(&self, prec: Option<u32>) -> Complex {
+        let (a, b) = (&self.re, &self.im);
+        Complex::new(
+            a.real_fn(prec, f64::sin, BigFloat::sin).mul(&b.real_fn(
+                prec,
+                f64::cosh,
+                BigFloat::cosh,
+            )),
+            a.real_fn(prec, f64::cos, BigFloat::cos).mul(&b.real_fn(
+                prec,
+                f64::sinh,
+                BigFloat::sinh,
+            )),
+        )
+    }
+
+    /// `cos(re) cosh(im) - sin(re) sinh(im) i`
+    pub fn cos(&self, prec: Option<u32>) -> Complex {
+        let (a, b) = (&self.re, &self.im);
+        Complex::new(
+            a.real_fn(prec, f64::cos, BigFloat::cos).mul(&b.real_fn(
+                prec,
+                f64::cosh,
+                BigFloat::cosh,
+            )),
+            a.real_fn(prec, f64::sin, BigFloat::sin)
+                .mul(&b.real_fn(prec, f64::sinh, BigFloat::sinh))
+                .neg(),
+        )
+    }
+
+    /// `sin(self) / cos(self)`
+    pub fn tan(&self, prec: Option<u32>) -> Complex {
+        self.sin(prec).div(&self.cos(prec))
+    }
+
+    /// `sinh(re) cos(im) + cosh(re) sin(im) i`
+    pub fn sinh(&self, prec: Option<u32>) -> Complex {
+        let (a, b) = (&self.re, &self.im);
+        Complex::new(
+            a.real_fn(prec, f64::sinh, BigFloat::sinh).mul(&b.real_fn(
+                prec,
+                f64::cos,
+                BigFloat::cos,
+            )),
+            a.real_fn(prec, f64::cosh, BigFloat::cosh).mul(&b.real_fn(
+                prec,
+                f64::sin,
+                BigFloat::sin,
+            )),
+        )
+    }
+
+    /// `cosh(re) cos(im) + sinh(re) sin(im) i`
+    pub fn cosh(&self, prec: Option<u32>) -> Complex {
+        let (a, b) = (&self.re, &self.im);
+        Complex::new(
+            a.real_fn(prec, f64::cosh, BigFloat::cosh).mul(&b.real_fn(
+                prec,
+                f64::cos,
+                BigFloat::cos,
+            )),
+            a.real_fn(prec, f64::sinh, BigFloat::sinh).mul(&b.real_fn(
+                prec,
+                f64::sin,
+                BigFloat::sin,
+            )),
+        )
+    }
+
+    /// `sinh(self) / cosh(self)`
+    pub fn tanh(&self, prec: Option<u32>) -> Complex {
+        self.sinh(prec).div(&self.cosh(prec))
+    }
+
+    /// `-i ln(iz + sqrt(1 - z^2))`
+    pub fn asin(&self, prec: Option<u32>) -> Option<Complex> {
+        let one = Complex::real(Number::from(1));
+        let root = one.sub(&self.mul(self)).sqrt(prec);
+        let l = Complex::i().mul(self).add(&root).ln(prec)?;
+        Some(l.mul(&Complex::i()).neg())
+    }
+
+    /// `-i ln(z + i sqrt(1 - z^2))`
+    pub fn acos(&self, prec: Option<u32>) -> Option<Complex> {
+        let one = Complex::real(Number::from(1));
+        let root = one.sub(&self.mul(self)).sqrt(prec);
+        let l = self.add(&Complex::i().mul(&root)).ln(prec)?;
+        Some(l.mul(&Complex::i()).neg())
+    }
+
+    /// `i/2 (ln(1 - iz) - ln(1 + iz))`
+    pub fn atan(&self, prec: Option<u32>) -> Option<Complex> {
+        let one = Complex::real(Number::from(1));
+        let iz = Complex::i().mul(self);
+        let l = one.sub(&iz).ln(prec)?.sub(&one.add(&iz).ln(prec)?);
+        Some(
+            l.mul(&Complex::i())
+                .scale(&Number::from(1).div(&Number::from(2))),
+        )
+    }
+
+    /// Logarithm to a complex `base`.
+    pub fn log(&self, base: &Complex, prec: Option<u32>) -> Option<Complex> {
+        let b = base.ln(prec)?;
+        if b.re.is_zero() && b.im.is_zero() {
+            return None;
+        }
+        Some(self.ln(prec)?.div(&b))
+    }
+
+    /// `self` formatted with `part` formatting the real and imaginary parts,
+    /// as `a + bi`.
+    pub fn to_string_with(&self, part: impl Fn(&Number) -> String) -> String {
+        let im = if self.im.is_negative() {
+            self.im.neg()
+        } else {
+            self.im.clone()
+        };
+        let im = match part(&im).as_str() {
+            "1" => "i".to_owned(),
+            s if s.contains('/') => format!("({})i", s),
+            s => format!("{}i", s),
+        };
+        let sign = if self.im.is_negative() { "-" } else { "+" };
+        if self.re.is_zero() {
+            format!("{}{}", if sign == "-" { "-" } else { "" }, im)
+        } else {
+            format!("{} {} {}", part(&self.re), sign, im)
+        }
+    }
+
+    /// `self` in polar form, `r ∠ θ` with the angle in radians.
+    pub fn to_polar_string(&self, prec: Option<u32>, part: impl Fn(&Number) -> String) -> String {
+        format!("{} ∠ {}", part(&self.abs(prec)), part(&self.arg(prec)))
+    }
+}
+
+impl fmt::Display for Complex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_string_with(Number::to_string))
+    }
+}
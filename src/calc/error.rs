@@ -0,0 +1,184 @@
+use crate::calc::Span;
+use std::fmt;
+
+/// Everything that can go wrong while parsing or evaluating an input.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Error {
+    /// The input does not match the grammar.
+    Syntax {
+        /// What was expected and what was found instead.
+        message: String,
+        /// The offending token.
+        span: Span,
+    },
+    /// A variable that is neither a constant nor in memory.
+    UnknownVariable {
+        /// The variable as written.
+        name: String,
+        /// The variable in the input.
+        span: Span,
+    },
+    /// A call of a function that is neither builtin nor defined.
+    UnknownFunction {
+        /// The function as written.
+        name: String,
+        /// The call in the input.
+        span: Span,
+    },
+    /// A function was called with the wrong number of arguments. Variadic
+    /// functions accept `expected` or more.
+    Arity {
+        /// The function called.
+        name: String,
+        /// How many arguments the function takes.
+        expected: usize,
+        /// Whether it also takes more than `expected`.
+        variadic: bool,
+        /// How many arguments it was given.
+        found: usize,
+        /// The call in the input.
+        span: Span,
+    },
+    /// Assignment to `ans`, a constant or a built-in function name.
+    InvalidAssignment {
+        /// The name assigned to.
+        name: String,
+        /// The assignment in the input.
+        span: Span,
+    },
+    /// An operand of the wrong type, e.g. `true + 1`.
+    Type {
+        /// The type the operation takes, e.g. "a number".
+        expected: &'static str,
+        /// The type it was given.
+        found: &'static str,
+        /// The operand in the input.
+        span: Span,
+    },
+    /// User-defined functions nested too deeply, usually endless recursion.
+    RecursionLimit {
+        /// The function called once too often.
+        name: String,
+        /// The call in the input.
+        span: Span,
+    },
+    /// The operation has no result for its operands, e.g. `0/0` or `gamma(-1)`.
+    Domain {
+        /// The operation with its operands, e.g. "sqrt(-1)".
+        expr: String,
+        /// The operation in the input.
+        span: Span,
+    },
+    /// Vectors or matrices of sizes the operation does not accept, e.g.
+    /// `[1, 2] + [1, 2, 3]`, or quantities of units that do not fit
+    /// together, e.g. `1 m + 1 s`.
+    Dimension {
+        /// The sizes or units that do not fit.
+        message: String,
+        /// The operation in the input.
+        span: Span,
+    },
+    /// An integer result too big for the word size, with wrapping off.
+    Overflow {
+        /// The word size.
+        bits: u32,
+        /// The operation in the input.
+        span: Span,
+    },
+    /// An exact result too big to compute, e.g. `3^1000000`.
+    TooLarge {
+        /// The operation in the input.
+        span: Span,
+    },
+    /// An index past the end of a vector or matrix, or not a whole number.
+    Index {
+        /// The index as shown to the user.
+        index: String,
+        /// The length of the vector, or the rows or columns of the matrix.
+        len: usize,
+        /// The index in the input.
+        span: Span,
+    },
+}
+
+impl Error {
+    pub(crate) fn syntax(message: String, span: Span) -> Self {
+        Error::Syntax { message, span }
+    }
+
+    /// Location in the input the error refers to.
+    pub fn span(&self) -> Span {
+        match self {
+            Error::Syntax { span, .. }
+            | Error::UnknownVariable { span, .. }
+            | Error::UnknownFunction { span, .. }
+            | Error::Arity { span, .. }
+            | Error::InvalidAssignment { span, .. }
+            | Error::Type { span, .. }
+            | Error::RecursionLimit { span, .. }
+            | Error::Domain { span, .. }
+            | Error::Dimension { span, .. }
+            | Error::Overflow { span, .. }
+            | Error::TooLarge { span }
+            | Error::Index { span, .. } => *span,
+        }
+    }
+
+    /// The same error, reported at `span` instead.
+    pub(crate) fn with_span(mut self, span: Span) -> Self {
+        match &mut self {
+            Error::Syntax { span: s, .. }
+            | Error::UnknownVariable { span: s, .. }
+            | Error::UnknownFunction { span: s, .. }
+            | Error::Arity { span: s, .. }
+            | Error::InvalidAssignment { span: s, .. }
+            | Error::Type { span: s, .. }
+            | Error::RecursionLimit { span: s, .. }
+            | Error::Domain { span: s, .. }
+            | Error::Dimension { span: s, .. }
+            | Error::Overflow { span: s, .. }
+            | Error::TooLarge { span: s }
+            | Error::Index { span: s, .. } => *s = span,
+        }
+        self
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Syntax { message, .. } => write!(f, "{}", message),
+            Error::UnknownVariable { name, .. } => write!(f, "unknown variable: {}", name),
+            Error::UnknownFunction { name, .. } => write!(f, "unknown function: {}", name),
+            Error::Arity {
+                name,
+                expected,
+                variadic: true,
+                ..
+            } => write!(f, "{} requires {} or more arguments", name, expected),
+            Error::Arity { name, expected, .. } => {
+                write!(f, "{} requires {} arguments", name, expected)
+            }
+            Error::InvalidAssignment { name, .. } => write!(
+                f,
+                "cannot assign to {}: built-in vars, constants and functions are read-only",
+                name
+            ),
+            Error::Type {
+                expected, found, ..
+            } => write!(f, "expected {}, found {}", expected, found),
+            Error::RecursionLimit { name, .. } => {
+                write!(f, "maximum call depth exceeded in {}", name)
+            }
+            Error::Domain { expr, .. } => write!(f, "{} is undefined", expr),
+            Error::Dimension { message, .. } => write!(f, "{}", message),
+            Error::Overflow { bits, .. } => write!(f, "result does not fit in {} bits", bits),
+            Error::TooLarge { .. } => write!(f, "result too large"),
+            Error::Index { index, len, .. } => {
+                write!(f, "invalid index {} for length {}", index, len)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
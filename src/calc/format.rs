@@ -0,0 +1,230 @@
+use crate::calc::Number;
+use num_bigint::BigInt;
+use num_traits::{One, Signed};
+use std::fmt;
+
+/// SI prefixes for the exponents -24 to 24 in steps of 3.
+const SI_PREFIXES: [&str; 17] = [
+    "y", "z", "a", "f", "p", "n", "u", "m", "", "k", "M", "G", "T", "P", "E", "Z", "Y",
+];
+
+/// Most decimal places `Format::Fixed` shows; more are taken as this many.
+const MAX_PLACES: usize = 1000;
+
+/// How `Calculator::run` shows real numbers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Plain decimals, or fractions while exact display is on.
+    Decimal,
+    /// `1.23e4`
+    Scientific,
+    /// `12.3e3`, with the exponent a multiple of 3.
+    Engineering,
+    /// `12.3k`, engineering notation with SI prefixes.
+    Si,
+    /// `3.1416`, rounded to this many decimal places, at most 1000.
+    Fixed(usize),
+    /// Integers in base 2, 8 or 16, as `0b11`, `0o7` or `0xff`. Other
+    /// numbers stay decimal.
+    Radix(Radix),
+}
+
+/// The bases `Format::Radix` shows integers in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Radix {
+    /// Base 2, `0b11`.
+    Bin,
+    /// Base 8, `0o7`.
+    Oct,
+    /// Base 16, `0xff`.
+    Hex,
+}
+
+impl Radix {
+    /// 2, 8 or 16.
+    pub fn base(self) -> u32 {
+        match self {
+            Radix::Bin => 2,
+            Radix::Oct => 8,
+            Radix::Hex => 16,
+        }
+    }
+
+    fn prefix(self) -> &'static str {
+        match self {
+            Radix::Bin => "0b",
+            Radix::Oct => "0o",
+            Radix::Hex => "0x",
+        }
+    }
+}
+
+impl Format {
+    /// The format called `name`, as in `255 to hex`. `Fixed` needs a
+    /// number of places and has no name.
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name {
+            "dec" => Some(Format::Decimal),
+            "sci" => Some(Format::Scientific),
+            "eng" => Some(Format::Engineering),
+            "si" => Some(Format::Si),
+            "hex" => Some(Format::Radix(Radix::Hex)),
+            "oct" => Some(Format::Radix(Radix::Oct)),
+            "bin" => Some(Format::Radix(Radix::Bin)),
+            _ => None,
+        }
+    }
+
+    /// `n` in this format, `None` where it does not apply and `n` should be
+    /// shown as usual. `decimal` is the usual decimal form of `n`. With a
+    /// word size, negative integers in a radix are shown in two's
+    /// complement of that many bits.
+    pub fn apply(self, n: &Number, decimal: &str, word_size: Option<u32>) -> Option<String> {
+        let radix = match self {
+            Format::Decimal => return None,
+            Format::Radix(radix) => radix,
+            _ => {
+                let (negative, digits, exp) = decimal_parts(decimal)?;
+                let s = self.digits(&digits, exp);
+                // Rounding may leave nothing but zeros, shown without a sign.
+                let zero = !s.bytes().any(|b| (b'1'..=b'9').contains(&b));
+                let sign = if negative && !zero { "-" } else { "" };
+                return Some(format!("{}{}", sign, s));
+            }
+        };
+        let n = match n {
+            Number::Int(n) => n,
+            _ => return None,
+        };
+        let (prefix, radix) = (radix.prefix(), radix.base());
+        if let (true, Some(bits)) = (n.is_negative(), word_size) {
+            let modulus = BigInt::one() << bits as usize;
+            if n.abs() * 2 <= modulus {
+                return Some(format!("{}{}", prefix, (modulus + n).to_str_radix(radix)));
+            }
+        }
+        let sign = if n.is_negative() { "-" } else { "" };
+        Some(format!("{}{}{}", sign, prefix, n.abs().to_str_radix(radix)))
+    }
+
+    /// The decimal `0.digits * 10^(exp + 1)`, without a sign, in this format.
+    fn digits(self, digits: &str, exp: i64) -> String {
+        match self {
+            Format::Scientific => format!("{}e{}", mantissa(digits, 0), exp),
+            Format::Engineering | Format::Si => {
+                let e3 = exp.div_euclid(3) * 3;
+                let m = mantissa(digits, (exp - e3) as usize);
+                let prefix = Some(e3 / 3 + 8)
+                    .filter(|i| (0..SI_PREFIXES.len() as i64).contains(i))
+                    .map(|i| SI_PREFIXES[i as usize]);
+                match (self, prefix) {
+                    (Format::Si, Some(prefix)) => format!("{}{}", m, prefix),
+                    _ => format!("{}e{}", m, e3),
+                }
+            }
+            Format::Fixed(places) => fixed(digits, exp, places.min(MAX_PLACES)),
+            _ => unreachable!("BUG ALERT"),
+        }
+    }
+}
+
+/// Splits a decimal like `-12.5` or `1.5e-9` into its sign, significant
+/// digits and the exponent of the first one: `(true, "125", 1)`. Zero has
+/// no digits. `None` for `inf` and `NaN`.
+fn decimal_parts(s: &str) -> Option<(bool, String, i64)> {
+    let (negative, s) = match s.strip_prefix('-') {
+        Some(s) => (true, s),
+        None => (false, s),
+    };
+    let (s, e) = match s.find('e') {
+        Some(i) => (&s[..i], s[i + 1..].parse::<i64>().ok()?),
+        None => (s, 0),
+    };
+    let (int, frac) = s.split_at(s.find('.').unwrap_or(s.len()));
+    let frac = frac.trim_start_matches('.');
+    if !int.bytes().chain(frac.bytes()).all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let all = format!("{}{}", int, frac);
+    let digits = all.trim_start_matches('0');
+    if digits.is_empty() {
+        return Some((negative, String::new(), 0));
+    }
+    let exp = e + int.len() as i64 - 1 - (all.len() - digits.len()) as i64;
+    Some((negative, digits.trim_end_matches('0').to_owned(), exp))
+}
+
+/// `digits` with the decimal point after `int + 1` of them.
+fn mantissa(digits: &str, int: usize) -> String {
+    if digits.is_empty() {
+        return "0".to_owned();
+    }
+    let digits = format!("{:0<width$}", digits, width = int + 1);
+    let (i, f) = digits.split_at(int + 1);
+    if f.is_empty() {
+        i.to_owned()
+    } else {
+        format!("{}.{}", i, f)
+    }
+}
+
+/// `0.digits * 10^(exp + 1)` rounded half up to `places` decimal places.
+fn fixed(digits: &str, exp: i64, places: usize) -> String {
+    // Digits before the point, and the number kept.
+    let int = exp + 1;
+    let keep = int + places as i64;
+    let mut kept: Vec<u8> = if keep <= 0 {
+        Vec::new()
+    } else {
+        format!("{:0<width$}", digits, width = keep as usize).into_bytes()[..keep as usize].to_vec()
+    };
+    let round_up = keep >= 0
+        && digits
+            .as_bytes()
+            .get(keep as usize)
+            .is_some_and(|&d| d >= b'5');
+    let mut int = int;
+    if round_up {
+        let mut i = kept.len();
+        loop {
+            if i == 0 {
+                kept.insert(0, b'1');
+                int += 1;
+                break;
+            }
+            i -= 1;
+            if kept[i] == b'9' {
+                kept[i] = b'0';
+            } else {
+                kept[i] += 1;
+                break;
+            }
+        }
+    }
+    // Pad to `int + places` digits with leading zeros for numbers below 1.
+    let total = (int.max(0) + places as i64) as usize;
+    let lead = total.saturating_sub(kept.len());
+    let s = format!("{}{}", "0".repeat(lead), String::from_utf8(kept).unwrap());
+    let (i, f) = s.split_at(s.len() - places);
+    let i = if i.is_empty() { "0" } else { i };
+    if places == 0 {
+        i.to_owned()
+    } else {
+        format!("{}.{}", i, f)
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Format::Decimal => write!(f, "dec"),
+            Format::Scientific => write!(f, "sci"),
+            Format::Engineering => write!(f, "eng"),
+            Format::Si => write!(f, "si"),
+            Format::Fixed(places) => write!(f, "fixed {}", places.min(&MAX_PLACES)),
+            Format::Radix(Radix::Hex) => write!(f, "hex"),
+            Format::Radix(Radix::Oct) => write!(f, "oct"),
+            Format::Radix(Radix::Bin) => write!(f, "bin"),
+        }
+    }
+}
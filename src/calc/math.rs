@@ -0,0 +1,98 @@
+use crate::calc::BigFloat;
+use num_integer::Integer;
+use std::f64::consts::PI;
+
+const LANCZOS_G: f64 = 7.0;
+const LANCZOS_COEF: [f64; 9] = [
+    0.999_999_999_999_809_9,
+    676.520_368_121_885_1,
+    -1_259.139_216_722_402_8,
+    771.323_428_777_653_1,
+    -176.615_029_162_140_6,
+    12.507_343_278_686_905,
+    -0.138_571_095_265_720_12,
+    9.984_369_578_019_572e-6,
+    1.505_632_735_149_311_6e-7,
+];
+
+/// Gamma function, using the Lanczos approximation. NaN at the poles (zero
+/// and the negative integers).
+pub fn gamma(x: f64) -> f64 {
+    if x <= 0.0 && x.fract() == 0.0 {
+        return f64::NAN;
+    }
+    if x < 0.5 {
+        // Reflection formula.
+        return PI / ((PI * x).sin() * gamma(1.0 - x));
+    }
+
+    let x = x - 1.0;
+    let t = x + LANCZOS_G + 0.5;
+    let mut a = LANCZOS_COEF[0];
+    for (i, c) in LANCZOS_COEF.iter().enumerate().skip(1) {
+        a += c / (x + i as f64);
+    }
+    let r = (2.0 * PI).sqrt() * t.powf(x + 0.5) * (-t).exp() * a;
+    if r.is_finite() {
+        r
+    } else {
+        // The power overflows before `exp` can scale it down, leaving
+        // inf * 0, so add logarithms instead.
+        (0.5 * (2.0 * PI).ln() + (x + 0.5) * t.ln() - t + a.ln()).exp()
+    }
+}
+
+/// `n!`, exact for small non-negative integers and `gamma(n + 1)` otherwise.
+pub fn factorial(n: f64) -> f64 {
+    if n >= 0.0 && n.fract() == 0.0 && n <= 170.0 {
+        (2..=n as u64).fold(1.0, |acc, i| acc * i as f64)
+    } else {
+        gamma(n + 1.0)
+    }
+}
+
+/// `n!!`, the product of the positive integers up to `n` that have the same
+/// parity as `n`, extended to other values through the gamma function.
+pub fn double_factorial(n: f64) -> f64 {
+    if n >= -1.0 && n.fract() == 0.0 && n <= 300.0 {
+        let mut acc = 1.0;
+        let mut i = n;
+        while i > 1.0 {
+            acc *= i;
+            i -= 2.0;
+        }
+        acc
+    } else if n < 0.0 && n.fract() == 0.0 && n as i64 % 2 == 0 {
+        f64::NAN
+    } else {
+        let c = (PI * n).cos();
+        2_f64.powf((1.0 + 2.0 * n - c) / 4.0) * PI.powf((c - 1.0) / 4.0) * gamma(n / 2.0 + 1.0)
+    }
+}
+
+/// `factorial` with big floats.
+pub fn big_factorial(n: &BigFloat) -> Option<BigFloat> {
+    n.add(&BigFloat::from_i64(1, n.prec())).gamma()
+}
+
+/// `double_factorial` with big floats.
+pub fn big_double_factorial(n: &BigFloat) -> Option<BigFloat> {
+    let prec = n.prec();
+    if n.is_negative() && n.to_integer().is_some_and(|i| i.is_even()) {
+        return None;
+    }
+
+    let wp = prec + 32;
+    let n = n.with_prec(wp);
+    let one = BigFloat::from_i64(1, wp);
+    let pi = BigFloat::pi(wp);
+    let c = pi.mul(&n).cos()?;
+    let two_exp = one.add(&n.mul_pow2(1)).sub(&c).mul_pow2(-2);
+    let pi_exp = c.sub(&one).mul_pow2(-2);
+    let g = n.mul_pow2(-1).add(&one).gamma()?;
+    let r = BigFloat::from_i64(2, wp)
+        .pow(&two_exp)?
+        .mul(&pi.pow(&pi_exp)?)
+        .mul(&g);
+    Some(r.with_prec(prec))
+}
@@ -0,0 +1,271 @@
+use crate::calc::Number;
+use num_traits::ToPrimitive;
+use std::cmp::Ordering;
+use std::fmt;
+
+/// Largest `|n|` accepted by `Matrix::pow`.
+const MAX_MATRIX_EXPONENT: i64 = 1 << 16;
+
+/// A rectangular grid of numbers with at least one row and column, stored
+/// row by row. Arithmetic keeps the entries exact where it can, so the
+/// inverse of an integer matrix has fractions rather than floats.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Matrix {
+    rows: usize,
+    cols: usize,
+    data: Vec<Number>,
+}
+
+impl Matrix {
+    /// A matrix of the entries in `data`, row by row, which must be
+    /// `rows * cols` of them.
+    pub fn new(rows: usize, cols: usize, data: Vec<Number>) -> Self {
+        assert_eq!(rows * cols, data.len(), "BUG ALERT: wrong matrix size");
+        Matrix { rows, cols, data }
+    }
+
+    /// `None` if the rows are empty or differ in length.
+    pub fn from_rows(rows: Vec<Vec<Number>>) -> Option<Self> {
+        let cols = rows.first()?.len();
+        if cols == 0 || rows.iter().any(|r| r.len() != cols) {
+            return None;
+        }
+        let n = rows.len();
+        Some(Matrix::new(n, cols, rows.into_iter().flatten().collect()))
+    }
+
+    /// The `n` by `n` identity matrix.
+    pub fn identity(n: usize) -> Self {
+        let data = (0..n * n)
+            .map(|k| Number::from(i64::from(k % (n + 1) == 0)))
+            .collect();
+        Matrix::new(n, n, data)
+    }
+
+    /// The number of rows.
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// The number of columns.
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    /// Whether it has as many rows as columns.
+    pub fn is_square(&self) -> bool {
+        self.rows == self.cols
+    }
+
+    /// All entries, row by row.
+    pub fn data(&self) -> &[Number] {
+        &self.data
+    }
+
+    /// The entry in row `i` and column `j`, counting from zero.
+    pub fn get(&self, i: usize, j: usize) -> &Number {
+        &self.data[i * self.cols + j]
+    }
+
+    /// Row `i`, counting from zero.
+    pub fn row(&self, i: usize) -> Vec<Number> {
+        self.data[i * self.cols..(i + 1) * self.cols].to_vec()
+    }
+
+    /// `f` applied to every entry.
+    pub fn map(&self, f: impl Fn(&Number) -> Number) -> Matrix {
+        Matrix::new(self.rows, self.cols, self.data.iter().map(f).collect())
+    }
+
+    /// `f` applied to corresponding entries, `None` if the shapes differ.
+    pub fn zip_with(
+        &self,
+        other: &Matrix,
+        f: impl Fn(&Number, &Number) -> Number,
+    ) -> Option<Matrix> {
+        if (self.rows, self.cols) != (other.rows, other.cols) {
+            return None;
+        }
+        let data = self.data.iter().zip(&other.data).map(|(a, b)| f(a, b));
+        Some(Matrix::new(self.rows, self.cols, data.collect()))
+    }
+
+    /// Rows and columns swapped.
+    pub fn transpose(&self) -> Matrix {
+        let data = (0..self.cols)
+            .flat_map(|j| (0..self.rows).map(move |i| (i, j)))
+            .map(|(i, j)| self.get(i, j).clone())
+            .collect();
+        Matrix::new(self.cols, self.rows, data)
+    }
+
+    /// The matrix product, `None` unless `self` has as many columns as
+    /// `other` has rows.
+    pub fn mul(&self, other: &Matrix) -> Option<Matrix> {
+        if self.cols != other.rows {
+            return None;
+        }
+        let data = (0..self.rows)
+            .flat_map(|i| (0..other.cols).map(move |j| (i, j)))
+            .map(|(i, j)| {
+                (0..self.cols).fold(Number::from(0), |acc, k| {
+                    acc.add(&self.get(i, k).mul(other.get(k, j)))
+                })
+            })
+            .collect();
+        Some(Matrix::new(self.rows, other.cols, data))
+    }
+
+    /// `self v` with `v` as a column vector.
+    pub fn mul_vector(&self, v: &[Number]) -> Option<Vec<Number>> {
+        let col = Matrix::new(v.len(), 1, v.to_vec());
+        self.mul(&col).map(|m| m.data)
+    }
+
+    /// `v self` with `v` as a row vector.
+    pub fn vector_mul(v: &[Number], m: &Matrix) -> Option<Vec<Number>> {
+        let row = Matrix::new(1, v.len(), v.to_vec());
+        row.mul(m).map(|m| m.data)
+    }
+
+    /// The determinant, `None` if the matrix is not square.
+    pub fn det(&self) -> Option<Number> {
+        if !self.is_square() {
+            return None;
+        }
+        Some(self.gauss_jordan(Matrix::identity(self.rows)).0)
+    }
+
+    /// The inverse, `None` if the matrix is not square or singular.
+    pub fn inv(&self) -> Option<Matrix> {
+        if !self.is_square() {
+            return None;
+        }
+        self.gauss_jordan(Matrix::identity(self.rows)).1
+    }
+
+    /// `x` with `self x = b`, `None` if the matrix is not square or
+    /// singular, or `b` has the wrong number of rows.
+    pub fn solve(&self, b: &Matrix) -> Option<Matrix> {
+        if !self.is_square() || b.rows != self.rows {
+            return None;
+        }
+        self.gauss_jordan(b.clone()).1
+    }
+
+    /// `self^n` by repeated squaring, with negative `n` raising the
+    /// inverse. `None` if the matrix is not square or singular, or `n` is
+    /// not a reasonably small integer.
+    pub fn pow(&self, n: &Number) -> Option<Matrix> {
+        let n = match n {
+            Number::Int(n) => n.to_i64().filter(|n| n.abs() <= MAX_MATRIX_EXPONENT)?,
+            _ => return None,
+        };
+        if !self.is_square() {
+            return None;
+        }
+        let mut base = if n < 0 { self.inv()? } else { self.clone() };
+        let mut acc = Matrix::identity(self.rows);
+        let mut n = n.unsigned_abs();
+        while n > 0 {
+            if n & 1 == 1 {
+                acc = acc.mul(&base)?;
+            }
+            n >>= 1;
+            if n > 0 {
+                base = base.mul(&base)?;
+            }
+        }
+        Some(acc)
+    }
+
+    /// Gauss-Jordan elimination of `[self | rhs]` for a square `self`,
+    /// giving the determinant and, unless it is zero, `self^-1 rhs`.
+    fn gauss_jordan(&self, mut rhs: Matrix) -> (Number, Option<Matrix>) {
+        let n = self.rows;
+        let mut a = self.clone();
+        let mut det = Number::from(1);
+        for k in 0..n {
+            // The largest pivot keeps float rounding errors small; exact
+            // entries only need a non-zero one.
+            let pivot = (k..n).filter(|&i| !a.get(i, k).is_zero()).max_by(|&i, &j| {
+                magnitude(a.get(i, k))
+                    .partial_cmp(&magnitude(a.get(j, k)))
+                    .unwrap_or(Ordering::Equal)
+            });
+            let p = match pivot {
+                Some(p) => p,
+                None => return (Number::from(0), None),
+            };
+            if p != k {
+                a.swap_rows(p, k);
+                rhs.swap_rows(p, k);
+                det = det.neg();
+            }
+            let pivot = a.get(k, k).clone();
+            det = det.mul(&pivot);
+            a.scale_row(k, &pivot);
+            rhs.scale_row(k, &pivot);
+            for i in (0..n).filter(|&i| i != k) {
+                let f = a.get(i, k).clone();
+                if !f.is_zero() {
+                    a.sub_row(i, k, &f);
+                    rhs.sub_row(i, k, &f);
+                }
+            }
+        }
+        (det, Some(rhs))
+    }
+
+    fn swap_rows(&mut self, i: usize, j: usize) {
+        for c in 0..self.cols {
+            self.data.swap(i * self.cols + c, j * self.cols + c);
+        }
+    }
+
+    /// Divides row `i` by `d`.
+    fn scale_row(&mut self, i: usize, d: &Number) {
+        for c in 0..self.cols {
+            let k = i * self.cols + c;
+            self.data[k] = self.data[k].div(d);
+        }
+    }
+
+    /// Subtracts `f` times row `j` from row `i`.
+    fn sub_row(&mut self, i: usize, j: usize, f: &Number) {
+        for c in 0..self.cols {
+            let x = self.get(j, c).mul(f);
+            let k = i * self.cols + c;
+            self.data[k] = self.data[k].sub(&x);
+        }
+    }
+
+    /// `self` formatted with `part` formatting the entries, as
+    /// `[[1, 2], [3, 4]]`.
+    pub fn to_string_with(&self, part: impl Fn(&Number) -> String) -> String {
+        let rows: Vec<String> = (0..self.rows)
+            .map(|i| vector_string(&self.row(i), &part))
+            .collect();
+        format!("[{}]", rows.join(", "))
+    }
+}
+
+/// Absolute value as a float, for choosing pivots.
+fn magnitude(n: &Number) -> f64 {
+    match n {
+        Number::Complex(c) => c.re.to_f64().hypot(c.im.to_f64()),
+        n => n.to_f64().abs(),
+    }
+}
+
+/// `v` formatted with `part` formatting the entries, as `[1, 2, 3]`.
+pub fn vector_string(v: &[Number], part: impl Fn(&Number) -> String) -> String {
+    let parts: Vec<String> = v.iter().map(part).collect();
+    format!("[{}]", parts.join(", "))
+}
+
+impl fmt::Display for Matrix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_string_with(Number::to_string))
+    }
+}
@@ -1,8 +1,28 @@
+mod ast;
+mod bigfloat;
+mod bits;
+#[allow(clippy::module_inception)]
 mod calc;
+mod complex;
+mod error;
+mod format;
+mod math;
+mod matrix;
+mod number;
 mod parser;
 mod scanner;
+mod unit;
+mod value;
 
-use self::calc::Function;
-use self::parser::*;
-use self::scanner::*;
-pub use calc::Calculator;
+pub use self::ast::*;
+pub use self::bigfloat::BigFloat;
+pub use self::calc::{AngleMode, Calculator};
+pub use self::complex::Complex;
+pub use self::error::Error;
+pub use self::format::{Format, Radix};
+pub use self::matrix::Matrix;
+pub use self::number::Number;
+pub use self::parser::Parser;
+pub use self::scanner::{Scanner, Span, Token, TokenType, Trivia};
+pub use self::unit::{Dimension, Quantity, Unit};
+pub use self::value::Value;
@@ -0,0 +1,547 @@
+use crate::calc::{math, BigFloat, Complex};
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_rational::BigRational;
+use num_traits::{One, Pow, Signed, ToPrimitive, Zero};
+use std::cmp::Ordering;
+use std::fmt;
+
+/// Largest integer power computed exactly, in bits of the result. Anything
+/// bigger is approximated instead of taking forever to compute and print.
+const MAX_POW_BITS: u64 = 1 << 20;
+
+/// Largest `n` for which `n!` and `n!!` are computed exactly.
+const MAX_EXACT_FACTORIAL: u64 = 10_000;
+
+/// Largest power of ten in a decimal literal, or of two in a hex float, that
+/// is kept exact, `1e400` is exact but `1e100000` is a float.
+const MAX_EXACT_EXPONENT: u64 = 1_000;
+
+/// A scalar. Integers and fractions are exact and stay that way through
+/// `+ - * / % ^`, everything else is a float. Floats are `f64` unless a
+/// precision is set, and a `Big` float makes the other operand big too.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Number {
+    /// An integer of any size.
+    Int(BigInt),
+    /// A fraction in lowest terms whose denominator is never 1.
+    Rational(BigRational),
+    /// A float of machine precision.
+    Float(f64),
+    /// A float of the precision set on the calculator.
+    Big(BigFloat),
+    /// Never has a zero imaginary part, see `Complex::into_number`.
+    Complex(Box<Complex>),
+}
+
+impl Number {
+    /// Exact value of a decimal literal such as `0.1`, `.5` or `1.5e-3`.
+    pub fn parse_decimal(s: &str) -> Option<Number> {
+        let (mantissa, exp) = match s.find(['e', 'E']) {
+            Some(i) => (&s[..i], s[i + 1..].parse::<i64>().ok()?),
+            None => (s, 0),
+        };
+        let (int, frac) = match mantissa.find('.') {
+            Some(i) => (&mantissa[..i], &mantissa[i + 1..]),
+            None => (mantissa, ""),
+        };
+        let digits = format!("{}{}", int, frac);
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+
+        let scale = exp - frac.len() as i64;
+        if scale.unsigned_abs() > MAX_EXACT_EXPONENT {
+            return s.parse::<f64>().ok().map(Number::Float);
+        }
+        let n: BigInt = digits.parse().ok()?;
+        let pow = Pow::pow(BigInt::from(10), scale.unsigned_abs() as u32);
+        Some(if scale < 0 {
+            Number::from(BigRational::new(n, pow))
+        } else {
+            Number::Int(n * pow)
+        })
+    }
+
+    /// Exact value of the digits of a literal in base 2, 8 or 16 after its
+    /// `0x`, `0o` or `0b`, such as `ff` or the hex float `1.8p3`, which is
+    /// 1.5 times 2^3.
+    pub fn parse_radix(s: &str, radix: u32) -> Option<Number> {
+        let (mantissa, exp) = match s.find(['p', 'P']) {
+            Some(i) if radix == 16 => (&s[..i], s[i + 1..].parse::<i64>().ok()?),
+            Some(_) => return None,
+            None => (s, 0),
+        };
+        let (int, frac) = match mantissa.find('.') {
+            Some(i) => (&mantissa[..i], &mantissa[i + 1..]),
+            None => (mantissa, ""),
+        };
+        let digits = format!("{}{}", int, frac);
+        if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+            return None;
+        }
+
+        let n = BigInt::parse_bytes(digits.as_bytes(), radix)?;
+        let scale = exp - frac.len() as i64 * i64::from(radix.trailing_zeros());
+        if scale.unsigned_abs() > MAX_EXACT_EXPONENT {
+            let scale = scale.clamp(i32::MIN.into(), i32::MAX.into()) as i32;
+            return Some(Number::Float(n.to_f64()? * 2f64.powi(scale)));
+        }
+        let pow = BigInt::one() << scale.unsigned_abs() as usize;
+        Some(if scale < 0 {
+            Number::from(BigRational::new(n, pow))
+        } else {
+            Number::Int(n * pow)
+        })
+    }
+
+    /// Nearest `f64`, infinite if the number is out of range.
+    pub fn to_f64(&self) -> f64 {
+        match self {
+            Number::Int(i) => i.to_f64().unwrap_or(f64::NAN),
+            Number::Rational(r) => r.to_f64().unwrap_or(f64::NAN),
+            Number::Float(f) => *f,
+            Number::Big(b) => b.to_f64(),
+            Number::Complex(_) => f64::NAN,
+        }
+    }
+
+    /// Whether the number has an imaginary part.
+    pub fn is_complex(&self) -> bool {
+        matches!(self, Number::Complex(_))
+    }
+
+    /// The number as a complex one, with a zero imaginary part if it is real.
+    pub fn to_complex(&self) -> Complex {
+        match self {
+            Number::Complex(c) => (**c).clone(),
+            n => Complex::real(n.clone()),
+        }
+    }
+
+    /// Both operands as complex numbers, if either of them is one.
+    fn complex_pair(a: &Number, b: &Number) -> Option<(Complex, Complex)> {
+        if a.is_complex() || b.is_complex() {
+            Some((a.to_complex(), b.to_complex()))
+        } else {
+            None
+        }
+    }
+
+    /// Whether the number is zero, `0.0` and `-0.0` included.
+    pub fn is_zero(&self) -> bool {
+        match self {
+            Number::Int(i) => i.is_zero(),
+            Number::Rational(_) => false,
+            Number::Float(f) => *f == 0.0,
+            Number::Big(b) => b.is_zero(),
+            Number::Complex(c) => c.re.is_zero() && c.im.is_zero(),
+        }
+    }
+
+    /// Whether a real number is below zero. Complex numbers are neither
+    /// negative nor positive.
+    pub fn is_negative(&self) -> bool {
+        match self {
+            Number::Int(i) => i.is_negative(),
+            Number::Rational(r) => r.is_negative(),
+            Number::Float(f) => *f < 0.0,
+            Number::Big(b) => b.is_negative(),
+            Number::Complex(_) => false,
+        }
+    }
+
+    /// Whether a real number is above zero.
+    pub fn is_positive(&self) -> bool {
+        !self.is_complex() && !self.is_negative() && !self.is_zero() && !self.is_nan()
+    }
+
+    /// `f` of a real number, or `big` with big floats if `prec` is given or
+    /// the number is big. Falls back to `f` where `big` has no result.
+    pub fn real_fn(
+        &self,
+        prec: Option<u32>,
+        f: fn(f64) -> f64,
+        big: fn(&BigFloat) -> Option<BigFloat>,
+    ) -> Number {
+        Number::with_big(prec, &[self], |p| big(&p[0]))
+            .unwrap_or_else(|| Number::Float(f(self.to_f64())))
+    }
+
+    /// `real_fn` for functions of two real numbers.
+    pub fn real_fn2(
+        a: &Number,
+        b: &Number,
+        prec: Option<u32>,
+        f: fn(f64, f64) -> f64,
+        big: fn(&BigFloat, &BigFloat) -> Option<BigFloat>,
+    ) -> Number {
+        Number::with_big(prec, &[a, b], |p| big(&p[0], &p[1]))
+            .unwrap_or_else(|| Number::Float(f(a.to_f64(), b.to_f64())))
+    }
+
+    /// The value as a big float with at least `prec` bits, `None` for
+    /// infinite and NaN floats.
+    pub fn to_big(&self, prec: u32) -> Option<BigFloat> {
+        match self {
+            Number::Int(i) => Some(BigFloat::from_int(i, prec)),
+            Number::Rational(r) => Some(BigFloat::from_ratio(r, prec)),
+            Number::Float(f) => BigFloat::from_f64(*f, prec),
+            Number::Big(b) => Some(b.with_prec(b.prec().max(prec))),
+            Number::Complex(_) => None,
+        }
+    }
+
+    fn big_prec(&self) -> Option<u32> {
+        match self {
+            Number::Big(b) => Some(b.prec()),
+            _ => None,
+        }
+    }
+
+    /// Both operands as big floats, if either of them is one.
+    fn big_pair(a: &Number, b: &Number) -> Option<(BigFloat, BigFloat)> {
+        let prec = a.big_prec().max(b.big_prec())?;
+        Some((a.to_big(prec)?, b.to_big(prec)?))
+    }
+
+    /// Whether it is a NaN float, or a complex number with a NaN part.
+    pub fn is_nan(&self) -> bool {
+        match self {
+            Number::Float(f) => f.is_nan(),
+            Number::Complex(c) => c.is_nan(),
+            _ => false,
+        }
+    }
+
+    /// Whether it is an integer or a fraction.
+    pub fn is_exact(&self) -> bool {
+        matches!(self, Number::Int(_) | Number::Rational(_))
+    }
+
+    fn to_ratio(&self) -> Option<BigRational> {
+        match self {
+            Number::Int(i) => Some(BigRational::from_integer(i.clone())),
+            Number::Rational(r) => Some(r.clone()),
+            _ => None,
+        }
+    }
+
+    /// `self + other`
+    pub fn add(&self, other: &Number) -> Number {
+        if let Some((a, b)) = Number::complex_pair(self, other) {
+            return a.add(&b).into_number();
+        }
+        match (self, other) {
+            (Number::Int(a), Number::Int(b)) => Number::Int(a + b),
+            (a, b) => match (a.to_ratio(), b.to_ratio()) {
+                (Some(a), Some(b)) => Number::from(a + b),
+                _ => match Number::big_pair(a, b) {
+                    Some((a, b)) => Number::Big(a.add(&b)),
+                    None => Number::Float(a.to_f64() + b.to_f64()),
+                },
+            },
+        }
+    }
+
+    /// `self - other`
+    pub fn sub(&self, other: &Number) -> Number {
+        if let Some((a, b)) = Number::complex_pair(self, other) {
+            return a.sub(&b).into_number();
+        }
+        match (self, other) {
+            (Number::Int(a), Number::Int(b)) => Number::Int(a - b),
+            (a, b) => match (a.to_ratio(), b.to_ratio()) {
+                (Some(a), Some(b)) => Number::from(a - b),
+                _ => match Number::big_pair(a, b) {
+                    Some((a, b)) => Number::Big(a.sub(&b)),
+                    None => Number::Float(a.to_f64() - b.to_f64()),
+                },
+            },
+        }
+    }
+
+    /// `self * other`
+    pub fn mul(&self, other: &Number) -> Number {
+        if let Some((a, b)) = Number::complex_pair(self, other) {
+            return a.mul(&b).into_number();
+        }
+        match (self, other) {
+            (Number::Int(a), Number::Int(b)) => Number::Int(a * b),
+            (a, b) => match (a.to_ratio(), b.to_ratio()) {
+                (Some(a), Some(b)) => Number::from(a * b),
+                _ => match Number::big_pair(a, b) {
+                    Some((a, b)) => Number::Big(a.mul(&b)),
+                    None => Number::Float(a.to_f64() * b.to_f64()),
+                },
+            },
+        }
+    }
+
+    /// Exact unless either side is a float. Division by zero follows `f64`.
+    pub fn div(&self, other: &Number) -> Number {
+        if let Some((a, b)) = Number::complex_pair(self, other) {
+            return a.div(&b).into_number();
+        }
+        match (self.to_ratio(), other.to_ratio()) {
+            (Some(a), Some(b)) if !b.is_zero() => Number::from(a / b),
+            _ => match Number::big_pair(self, other).and_then(|(a, b)| a.div(&b)) {
+                Some(q) => Number::Big(q),
+                None => Number::Float(self.to_f64() / other.to_f64()),
+            },
+        }
+    }
+
+    /// Remainder with the sign of the dividend, like `f64`'s `%`.
+    pub fn rem(&self, other: &Number) -> Number {
+        match (self, other) {
+            (Number::Int(a), Number::Int(b)) if !b.is_zero() => Number::Int(a % b),
+            (a, b) => match (a.to_ratio(), b.to_ratio()) {
+                (Some(a), Some(b)) if !b.is_zero() => Number::from(&a - (&a / &b).trunc() * &b),
+                _ => match Number::big_pair(a, b)
+                    .and_then(|(a, b)| Some(a.sub(&a.div(&b)?.trunc().mul(&b))))
+                {
+                    Some(r) => Number::Big(r),
+                    None => Number::Float(a.to_f64() % b.to_f64()),
+                },
+            },
+        }
+    }
+
+    /// Exact for integers and fractions raised to integers, unless the
+    /// result would be enormous. Inexact results are computed with `prec`
+    /// bits if given.
+    pub fn pow(&self, other: &Number, prec: Option<u32>) -> Number {
+        if let Some((a, b)) = Number::complex_pair(self, other) {
+            return a
+                .pow(&b, prec)
+                .map_or(Number::Float(f64::NAN), Complex::into_number);
+        }
+        if let (Some(a), Number::Int(b)) = (self.to_ratio(), other) {
+            if let Some(exp) = b.abs().to_u64() {
+                let bits = a.numer().bits().max(a.denom().bits()).max(1);
+                let trivial = a.is_integer() && a.numer().abs() <= BigInt::one();
+                if (trivial || bits.saturating_mul(exp) <= MAX_POW_BITS)
+                    && !(a.is_zero() && b.is_negative())
+                {
+                    let p = Pow::pow(&a, small_exp(&a, exp));
+                    return Number::from(if b.is_negative() { p.recip() } else { p });
+                }
+            }
+        }
+        let r = Number::with_big(prec, &[self, other], |p| p[0].pow(&p[1]))
+            .unwrap_or_else(|| Number::Float(self.to_f64().powf(other.to_f64())));
+        // Negative numbers have complex roots, (-8)^(1/3) = 1 + 1.732i.
+        if r.is_nan() && !self.is_nan() && !other.is_nan() {
+            let (a, b) = (self.to_complex(), other.to_complex());
+            if let Some(c) = a.pow(&b, prec) {
+                return c.into_number();
+            }
+        }
+        r
+    }
+
+    /// Roughly the work `pow` does for `self^other`: the bits of an exact
+    /// result, or 1 where it is approximated.
+    pub fn pow_cost(&self, other: &Number) -> u64 {
+        match (self.to_ratio(), other) {
+            (Some(a), Number::Int(b)) => {
+                let bits = a.numer().bits().max(a.denom().bits()).max(1);
+                match b.abs().to_u64().map(|exp| bits.saturating_mul(exp)) {
+                    Some(bits) if bits <= MAX_POW_BITS => bits,
+                    _ => 1,
+                }
+            }
+            _ => 1,
+        }
+    }
+
+    /// `pow`, or `None` for a power of exact numbers too large to compute
+    /// exactly whose approximation overflows to infinity or zero, as in
+    /// `3^1000000`. Zero to a negative power is a division by zero and
+    /// follows `f64`, like `div`.
+    pub fn checked_pow(&self, other: &Number, prec: Option<u32>) -> Option<Number> {
+        let r = self.pow(other, prec);
+        let lost = matches!(r, Number::Float(f) if f.is_infinite() || f == 0.0);
+        if lost && !self.is_zero() && self.is_exact() && other.is_exact() {
+            None
+        } else {
+            Some(r)
+        }
+    }
+
+    /// `f` applied to `args` as big floats, with `prec` bits or the
+    /// precision of the biggest argument. `None` if there is no precision to
+    /// work with or `f` has no finite result.
+    pub fn with_big(
+        prec: Option<u32>,
+        args: &[&Number],
+        f: impl Fn(&[BigFloat]) -> Option<BigFloat>,
+    ) -> Option<Number> {
+        let prec = args.iter().map(|a| a.big_prec()).fold(prec, Option::max)?;
+        let args = args
+            .iter()
+            .map(|a| a.to_big(prec).map(|b| b.with_prec(prec)))
+            .collect::<Option<Vec<_>>>()?;
+        f(&args).map(Number::Big)
+    }
+
+    /// `-self`
+    pub fn neg(&self) -> Number {
+        match self {
+            Number::Int(i) => Number::Int(-i),
+            Number::Rational(r) => Number::Rational(-r),
+            Number::Float(f) => Number::Float(-f),
+            Number::Big(b) => Number::Big(b.neg()),
+            Number::Complex(c) => Number::Complex(Box::new(c.neg())),
+        }
+    }
+
+    /// `n!`, exact for integers that are not too large and `gamma(n + 1)`
+    /// otherwise.
+    pub fn factorial(&self, prec: Option<u32>) -> Number {
+        match self.exact_factorial_arg() {
+            Some(n) => Number::Int((2..=n).map(BigInt::from).product()),
+            None => Number::with_big(prec, &[self], |p| math::big_factorial(&p[0]))
+                .unwrap_or_else(|| Number::Float(math::factorial(self.to_f64()))),
+        }
+    }
+
+    /// `n!!`, exact for integers that are not too large.
+    pub fn double_factorial(&self, prec: Option<u32>) -> Number {
+        match self.exact_factorial_arg() {
+            Some(n) => Number::Int((1..=n).rev().step_by(2).map(BigInt::from).product()),
+            None => Number::with_big(prec, &[self], |p| math::big_double_factorial(&p[0]))
+                .unwrap_or_else(|| Number::Float(math::double_factorial(self.to_f64()))),
+        }
+    }
+
+    /// Roughly the work `factorial` and `double_factorial` do: the number
+    /// of factors of an exact result, or 1 where it is approximated.
+    pub fn factorial_cost(&self) -> u64 {
+        self.exact_factorial_arg().unwrap_or(1).max(1)
+    }
+
+    fn exact_factorial_arg(&self) -> Option<u64> {
+        match self {
+            Number::Int(i) => i.to_u64().filter(|&n| n <= MAX_EXACT_FACTORIAL),
+            _ => None,
+        }
+    }
+
+    /// Numeric equality, so `2 == 2.0`.
+    pub fn num_eq(&self, other: &Number) -> bool {
+        match Number::complex_pair(self, other) {
+            Some((a, b)) => a.re.num_eq(&b.re) && a.im.num_eq(&b.im),
+            None => self.num_cmp(other) == Some(Ordering::Equal),
+        }
+    }
+
+    /// Numeric ordering, `None` if either side is NaN or complex.
+    pub fn num_cmp(&self, other: &Number) -> Option<Ordering> {
+        if self.is_complex() || other.is_complex() {
+            return None;
+        }
+        match (self.to_ratio(), other.to_ratio()) {
+            (Some(a), Some(b)) => Some(a.cmp(&b)),
+            _ => match Number::big_pair(self, other) {
+                Some((a, b)) => Some(a.compare(&b)),
+                None => self.to_f64().partial_cmp(&other.to_f64()),
+            },
+        }
+    }
+
+    /// Like `Display`, but fractions are shown as the nearest float, or
+    /// with `prec` bits if given.
+    pub fn to_approx_string(&self, prec: Option<u32>) -> String {
+        match (self, prec) {
+            (Number::Rational(r), Some(prec)) => BigFloat::from_ratio(r, prec).to_string(),
+            (Number::Rational(r), None) => r.to_f64().unwrap_or(f64::NAN).to_string(),
+            (Number::Complex(c), _) => c.to_string_with(|n| n.to_approx_string(prec)),
+            (n, _) => n.to_string(),
+        }
+    }
+}
+
+/// `exp` for `base^exp`, where powers of 0 and ±1 only depend on the parity
+/// of the exponent and can be reduced to keep `pow` cheap.
+fn small_exp(base: &BigRational, exp: u64) -> u32 {
+    if base.is_integer() && base.numer().abs() <= BigInt::one() && exp > 2 {
+        (2 - exp % 2) as u32
+    } else {
+        exp as u32
+    }
+}
+
+impl From<f64> for Number {
+    fn from(f: f64) -> Self {
+        Number::Float(f)
+    }
+}
+
+impl From<i64> for Number {
+    fn from(i: i64) -> Self {
+        Number::Int(BigInt::from(i))
+    }
+}
+
+impl From<BigInt> for Number {
+    fn from(i: BigInt) -> Self {
+        Number::Int(i)
+    }
+}
+
+/// Whole fractions become integers.
+impl From<BigRational> for Number {
+    fn from(r: BigRational) -> Self {
+        if r.is_integer() {
+            Number::Int(r.to_integer())
+        } else {
+            Number::Rational(r)
+        }
+    }
+}
+
+/// Fractions with a terminating decimal expansion print as decimals, the
+/// rest as `n/d`.
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Number::Int(i) => write!(f, "{}", i),
+            Number::Rational(r) => match decimal_places(r.denom()) {
+                Some(places) => {
+                    let scaled = r.numer().abs() * Pow::pow(BigInt::from(10), places) / r.denom();
+                    let digits = format!("{:0>width$}", scaled, width = places as usize + 1);
+                    let (int, frac) = digits.split_at(digits.len() - places as usize);
+                    let sign = if r.is_negative() { "-" } else { "" };
+                    write!(f, "{}{}.{}", sign, int, frac)
+                }
+                None => write!(f, "{}/{}", r.numer(), r.denom()),
+            },
+            Number::Float(v) => write!(f, "{}", v),
+            Number::Big(b) => write!(f, "{}", b),
+            Number::Complex(c) => write!(f, "{}", c),
+        }
+    }
+}
+
+/// Number of decimal places `1/denom` needs, if it terminates at all.
+fn decimal_places(denom: &BigInt) -> Option<u32> {
+    let mut d = denom.clone();
+    let mut twos = 0;
+    let mut fives = 0;
+    let (two, five) = (BigInt::from(2), BigInt::from(5));
+    while d.is_multiple_of(&two) {
+        d /= &two;
+        twos += 1;
+    }
+    while d.is_multiple_of(&five) {
+        d /= &five;
+        fives += 1;
+    }
+    if d.is_one() {
+        Some(twos.max(fives))
+    } else {
+        None
+    }
+}
@@ -1,64 +1,117 @@
-use crate::calc::{Function, Scanner, Token, TokenType};
-use std::cell::RefCell;
+use crate::calc::{
+    BinaryOp, Complex, Error, Expr, ExprKind, FunctionDef, Number, PostfixOp, Scanner, Span, Stmt,
+    Token, TokenType, UnaryOp, Unit,
+};
+use num_bigint::BigInt;
 use std::collections::{HashMap, HashSet};
-use std::rc::Rc;
 
 /// Grammar
-/// Input   = [ Identifier "=" ] Exp.           // "=" requires look-ahead
-/// Exp     = Addend { Op0 Addend }.
-/// Addend  = Factor { Op1 Factor }.
+/// Block   = [ Input ] { Separator [ Input ] }.
+/// Input   = [ Identifier "=" ] Exp            // "=" requires look-ahead
+///         | Call "=" Exp.                     // Call arguments must be identifiers
+/// Exp     = Convert [ "?" Exp ":" Exp ].
+/// Convert = Or { ( "to" | "in" ) Addend }.
+/// Or      = And { "||" And }.
+/// And     = Not { "&&" Not }.
+/// Not     = "!" Not | "!!" Not | Cmp.
+/// Cmp     = BitOr [ Op3 BitOr ].
+/// BitOr   = BitXor { "|" BitXor }.
+/// BitXor  = BitAnd { "xor" BitAnd }.
+/// BitAnd  = Shift { "&" Shift }.
+/// Shift   = Sum { ( "<<" | ">>" | ">>>" ) Sum }.
+/// Sum     = Addend { Op0 Addend }.
+/// Addend  = Quantity { Op1 Quantity | Juxtaposed }.
+/// Juxtaposed = Factor.                        // starting with Identifier or "("
+/// Quantity = Factor { Factor }.               // a number, then unit names
 /// Factor  = Power [ Op2 Factor ].
-/// Power   = Literal | Call | "(" Exp ")" | Op0 Power.
-/// Literal = Float | Integer | Exponent.
+/// Power   = Primary { Postfix | Index } | ( Op0 | "~" ) Power.
+/// Primary = Literal | Call | "(" Exp ")" | "[" ExpList "]".
+/// Postfix = "!" | "!!" | "%".               // "%" only if no operand or sign follows
+/// Index   = "[" Exp [ "," Exp ] "]".
+/// Literal = Float | Integer | Exponent | Imaginary
+///         | HexInteger | OctalInteger | BinaryInteger | HexFloat.
 /// Call    = Identifier [ "(" ExpList ")" ].
 /// ExpList = [ Exp { "," Exp } ].
 /// Op0     = "+" | "-".
 /// Op1     = "*" | "/" | "%".
 /// Op2     = "^".
+/// Op3     = "==" | "!=" | "<" | "<=" | ">" | ">=".
+/// Separator = ";" | line break.             // see `Scanner`
+///
+/// Comments, from "#" to the end of the line or between "/*" and "*/", may
+/// go anywhere whitespace can.
+///
+/// `if(cond, a, b)` is parsed like `cond ? a : b`, so only the chosen branch
+/// is evaluated.
+///
+/// With implicit multiplication enabled, a factor directly followed by an
+/// identifier or "(" is multiplied by it, at the same precedence as "*":
+/// `2x^2` is `2 * x^2`, `-2x` is `(-2) * x` and `1/2x` is `(1/2) * x`. An
+/// identifier directly followed by "(" is always a function call, and a
+/// number may only start a product, so `2 3` remains an error.
+///
+/// Unit names after a number are multiplied by it even without implicit
+/// multiplication, and bind tighter than "*" and "/", so that units can be
+/// written as usual: `5 km/h` is `(5 * km) / h` and `1 km / 1 m` is
+/// `(1 * km) / (1 * m)`. `to` and `in` are
+/// conversion operators, except that `in` followed by anything but a unit
+/// is the inch: `3 ft + 2 in` is a length and `12 in in ft` a conversion.
+///
+/// The bitwise operators bind as in Python, tighter than comparisons but
+/// looser than "+": `1 << 2 + 1` is 8 and `x & 0xf == 3` compares `x & 0xf`.
+/// `xor` is an operator only between operands, elsewhere it is a name.
+///
+/// Postfix operators bind tighter than anything else: `-3!` is `-(3!)` and
+/// `2^3!` is `2^(3!)`. A "%" is modulo when an operand or a sign follows
+/// it, and a percent sign otherwise, at the end or before ")" or another
+/// operator: `26%-12` is 2, `15% * 200` is 30 and `(50%) + 1` is 1.5.
 
 #[derive(Debug, PartialEq, Eq, Hash)]
 enum NonTerminal {
     Op0,
     Op1,
     Op2,
+    Op3,
     Line,
     Exp,
+    Sum,
     Addend,
     Factor,
+    Juxtaposed,
     Power,
     Call,
     ExpList,
     Literal,
 }
 
-#[derive(Debug)]
-pub struct PResult {
-    pub var: Option<String>,
-    pub val: f64,
-}
-
+/// Recursive-descent parser for the grammar above.
 pub struct Parser {
     scanner: Option<Scanner>,
     token: Token,
     first_sets: HashMap<NonTerminal, HashSet<TokenType>>,
+    implicit_mul: bool,
+}
 
-    functions: Rc<RefCell<HashMap<&'static str, Function>>>,
-    constants: Rc<HashMap<&'static str, f64>>,
-    memory: Rc<RefCell<HashMap<String, f64>>>,
+impl Default for Parser {
+    fn default() -> Self {
+        Parser::new()
+    }
 }
 
 impl Parser {
-    pub fn new(
-        functions: Rc<RefCell<HashMap<&'static str, Function>>>,
-        constants: Rc<HashMap<&'static str, f64>>,
-        memory: Rc<RefCell<HashMap<String, f64>>>,
-    ) -> Self {
+    /// A parser with implicit multiplication off.
+    pub fn new() -> Self {
         let mut first_sets = HashMap::new();
 
         let mut fs = HashSet::new();
         fs.insert(TokenType::Integer);
         fs.insert(TokenType::Float);
         fs.insert(TokenType::Exponent);
+        fs.insert(TokenType::Imaginary);
+        fs.insert(TokenType::HexInteger);
+        fs.insert(TokenType::OctalInteger);
+        fs.insert(TokenType::BinaryInteger);
+        fs.insert(TokenType::HexFloat);
         first_sets.insert(NonTerminal::Literal, fs);
 
         let mut fs = HashSet::new();
@@ -76,67 +129,97 @@ impl Parser {
         fs.insert(TokenType::Pow);
         first_sets.insert(NonTerminal::Op2, fs);
 
+        let mut fs = HashSet::new();
+        fs.insert(TokenType::EqualEqual);
+        fs.insert(TokenType::NotEqual);
+        fs.insert(TokenType::Less);
+        fs.insert(TokenType::LessEqual);
+        fs.insert(TokenType::Greater);
+        fs.insert(TokenType::GreaterEqual);
+        first_sets.insert(NonTerminal::Op3, fs);
+
         let mut fs = HashSet::new();
         fs.insert(TokenType::Identifier);
         first_sets.insert(NonTerminal::Call, fs);
 
         let mut fs = HashSet::new();
         for i in first_sets.get(&NonTerminal::Literal).unwrap() {
-            fs.insert(i.clone());
+            fs.insert(*i);
         }
         for i in first_sets.get(&NonTerminal::Call).unwrap() {
-            fs.insert(i.clone());
+            fs.insert(*i);
         }
         fs.insert(TokenType::OpenParen);
+        fs.insert(TokenType::OpenBracket);
         for i in first_sets.get(&NonTerminal::Op0).unwrap() {
-            fs.insert(i.clone());
+            fs.insert(*i);
         }
+        fs.insert(TokenType::Tilde);
         first_sets.insert(NonTerminal::Power, fs);
 
         let mut fs = HashSet::new();
         for i in first_sets.get(&NonTerminal::Power).unwrap() {
-            fs.insert(i.clone());
+            fs.insert(*i);
         }
         first_sets.insert(NonTerminal::Factor, fs);
 
+        let mut fs = HashSet::new();
+        fs.insert(TokenType::Identifier);
+        fs.insert(TokenType::OpenParen);
+        first_sets.insert(NonTerminal::Juxtaposed, fs);
+
         let mut fs = HashSet::new();
         for i in first_sets.get(&NonTerminal::Factor).unwrap() {
-            fs.insert(i.clone());
+            fs.insert(*i);
         }
         first_sets.insert(NonTerminal::Addend, fs);
 
         let mut fs = HashSet::new();
         for i in first_sets.get(&NonTerminal::Addend).unwrap() {
-            fs.insert(i.clone());
+            fs.insert(*i);
         }
+        first_sets.insert(NonTerminal::Sum, fs);
+
+        let mut fs = HashSet::new();
+        for i in first_sets.get(&NonTerminal::Sum).unwrap() {
+            fs.insert(*i);
+        }
+        fs.insert(TokenType::Bang);
+        fs.insert(TokenType::DoubleBang);
         first_sets.insert(NonTerminal::Exp, fs);
 
         let mut fs = HashSet::new();
         for i in first_sets.get(&NonTerminal::Exp).unwrap() {
-            fs.insert(i.clone());
+            fs.insert(*i);
         }
         first_sets.insert(NonTerminal::ExpList, fs);
 
         let mut fs = HashSet::new();
         for i in first_sets.get(&NonTerminal::Exp).unwrap() {
-            fs.insert(i.clone());
+            fs.insert(*i);
         }
         fs.insert(TokenType::Identifier);
         first_sets.insert(NonTerminal::Line, fs);
 
         Parser {
             scanner: None,
-            token: Token {
-                token_type: TokenType::EOL,
-                lexeme: "".to_owned(),
-            },
+            token: Token::new(TokenType::EOL, "".to_owned(), Span::new(0, 0)),
             first_sets,
-            functions,
-            constants,
-            memory,
+            implicit_mul: false,
         }
     }
 
+    /// Enables or disables implicit multiplication by juxtaposition, as in
+    /// `2pi` or `3(x+1)`.
+    pub fn set_implicit_multiplication(&mut self, enabled: bool) {
+        self.implicit_mul = enabled;
+    }
+
+    /// Whether implicit multiplication is enabled.
+    pub fn implicit_multiplication(&self) -> bool {
+        self.implicit_mul
+    }
+
     fn have_nt(&mut self, nt: NonTerminal) -> bool {
         self.first_sets
             .get(&nt)
@@ -170,125 +253,478 @@ impl Parser {
         }
     }
 
-    fn expect(&mut self, token_type: TokenType) -> Result<(), String> {
+    /// Describes the current token for use in error messages.
+    fn found(&self) -> String {
+        match self.token.token_type {
+            TokenType::EOL => "end of input".to_owned(),
+            _ => format!("'{}'", self.token.lexeme),
+        }
+    }
+
+    /// Error pointing at the current token, reporting what was expected there.
+    fn expected(&self, what: &str) -> Error {
+        Error::syntax(
+            format!("expected {}, found {}", what, self.found()),
+            self.token.span,
+        )
+    }
+
+    /// Error pointing at the current token, which cannot appear where it is.
+    fn unexpected(&self) -> Error {
+        let message = match self.token.token_type {
+            TokenType::EOL => "unexpected end of input".to_owned(),
+            TokenType::Separator if self.token.lexeme == "\n" => "unexpected line break".to_owned(),
+            TokenType::Error if self.token.lexeme == "/*" => "unterminated comment".to_owned(),
+            TokenType::Error => format!("invalid character '{}'", self.token.lexeme),
+            _ => format!("unexpected '{}'", self.token.lexeme),
+        };
+        Error::syntax(message, self.token.span)
+    }
+
+    fn expect(&mut self, token_type: TokenType) -> Result<(), Error> {
         if self.accept(token_type) {
             Ok(())
         } else {
-            Err("invalid syntax".to_owned())
+            Err(self.unexpected())
         }
     }
 
-    fn expect_retrieve(&mut self, token_type: TokenType) -> Result<Token, String> {
+    /// Expects the ")" or "]" matching the already accepted `open` one.
+    fn expect_close(&mut self, open: &Token) -> Result<Token, Error> {
+        let (close, lexeme) = match open.token_type {
+            TokenType::OpenBracket => (TokenType::CloseBracket, ']'),
+            _ => (TokenType::CloseParen, ')'),
+        };
+        let token = self.token.clone();
+        if self.accept(close) {
+            Ok(token)
+        } else {
+            Err(Error::syntax(
+                format!(
+                    "expected '{}' to close '{}' at column {}",
+                    lexeme,
+                    open.lexeme,
+                    open.span.column()
+                ),
+                self.token.span,
+            ))
+        }
+    }
+
+    fn expect_retrieve(&mut self, token_type: TokenType) -> Result<Token, Error> {
         let token = self.token.clone();
         if self.accept(token_type) {
             Ok(token)
         } else {
-            Err("invalid syntax".to_owned())
+            Err(self.unexpected())
         }
     }
 
-    fn expect_nt_retrieve(&mut self, nt: NonTerminal) -> Result<Token, String> {
+    fn expect_nt_retrieve(&mut self, nt: NonTerminal) -> Result<Token, Error> {
         let token = self.token.clone();
         if self.accept_nt(nt) {
             Ok(token)
         } else {
-            Err("invalid syntax".to_owned())
+            Err(self.unexpected())
         }
     }
 
-    fn op0(&mut self) -> Result<Token, String> {
+    fn op0(&mut self) -> Result<Token, Error> {
         self.expect_nt_retrieve(NonTerminal::Op0)
     }
 
-    fn op1(&mut self) -> Result<Token, String> {
+    fn op1(&mut self) -> Result<Token, Error> {
         self.expect_nt_retrieve(NonTerminal::Op1)
     }
 
-    fn op2(&mut self) -> Result<Token, String> {
+    fn op2(&mut self) -> Result<Token, Error> {
         self.expect_nt_retrieve(NonTerminal::Op2)
     }
 
-    fn literal(&mut self) -> Result<f64, String> {
+    fn op3(&mut self) -> Result<Token, Error> {
+        self.expect_nt_retrieve(NonTerminal::Op3)
+    }
+
+    fn literal(&mut self) -> Result<Expr, Error> {
         let token = self.expect_nt_retrieve(NonTerminal::Literal)?;
-        match token.lexeme.parse::<f64>() {
-            Ok(v) => Ok(v),
-            Err(e) => Err(format!("{}", e)),
+        // Digit separators, as in `1_000`, carry no meaning.
+        let lexeme = token.lexeme.replace('_', "");
+        let number = match token.token_type {
+            TokenType::Integer => lexeme.parse::<BigInt>().map(Number::Int).ok(),
+            TokenType::HexInteger | TokenType::HexFloat => Number::parse_radix(&lexeme[2..], 16),
+            TokenType::OctalInteger => Number::parse_radix(&lexeme[2..], 8),
+            TokenType::BinaryInteger => Number::parse_radix(&lexeme[2..], 2),
+            TokenType::Imaginary => {
+                let im = lexeme.trim_end_matches('i');
+                im.parse::<BigInt>()
+                    .map(Number::Int)
+                    .ok()
+                    .or_else(|| Number::parse_decimal(im))
+                    .map(|im| Complex::new(Number::from(0), im).into_number())
+            }
+            _ => Number::parse_decimal(&lexeme),
+        };
+        match number {
+            Some(n) => Ok(Expr::new(ExprKind::Literal(n), token.span)),
+            None => Err(Error::syntax(
+                format!("invalid number '{}'", token.lexeme),
+                token.span,
+            )),
         }
     }
 
-    fn power(&mut self) -> Result<f64, String> {
+    fn primary(&mut self) -> Result<Expr, Error> {
         if self.have_nt(NonTerminal::Literal) {
             Ok(self.literal()?)
         } else if self.have_nt(NonTerminal::Call) {
             Ok(self.call()?)
-        } else if self.accept(TokenType::OpenParen) {
-            let v = self.exp()?;
-            self.expect(TokenType::CloseParen)?;
-            Ok(v)
-        } else if self.have_nt(NonTerminal::Op0) {
+        } else if self.have(TokenType::OpenParen) {
+            let open = self.expect_retrieve(TokenType::OpenParen)?;
+            let mut e = self.exp()?;
+            let close = self.expect_close(&open)?;
+            e.span = open.span.to(close.span);
+            Ok(e)
+        } else if self.have(TokenType::OpenBracket) {
+            let open = self.expect_retrieve(TokenType::OpenBracket)?;
+            let elements = self.exp_list()?;
+            let close = self.expect_close(&open)?;
+            let span = open.span.to(close.span);
+            Ok(Expr::new(ExprKind::Vector(elements), span))
+        } else if self.have(TokenType::Error) {
+            Err(self.unexpected())
+        } else {
+            Err(self.expected("a number, variable, function call, '(' or '['"))
+        }
+    }
+
+    /// The postfix operator at the current token, if any. "%" is a percent
+    /// sign unless an operand, or a sign starting one, follows, in which
+    /// case it is left for `addend` as modulo.
+    fn postfix_op(&mut self) -> Option<PostfixOp> {
+        match self.token.token_type {
+            TokenType::Bang => Some(PostfixOp::Factorial),
+            TokenType::DoubleBang => Some(PostfixOp::DoubleFactorial),
+            TokenType::Mod => {
+                let next = self.scanner.as_mut().unwrap().look_ahead().token_type;
+                match next {
+                    TokenType::Integer
+                    | TokenType::Float
+                    | TokenType::Exponent
+                    | TokenType::Imaginary
+                    | TokenType::HexInteger
+                    | TokenType::OctalInteger
+                    | TokenType::BinaryInteger
+                    | TokenType::HexFloat
+                    | TokenType::Identifier
+                    | TokenType::OpenParen
+                    | TokenType::OpenBracket
+                    | TokenType::Tilde
+                    | TokenType::Add
+                    | TokenType::Sub => None,
+                    _ => Some(PostfixOp::Percent),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn power(&mut self) -> Result<Expr, Error> {
+        if self.have_nt(NonTerminal::Op0) {
             let op0 = self.op0()?;
-            let val = self.power()?;
-            match op0.lexeme.as_ref() {
-                "-" => Ok(-val),
-                "+" => Ok(val),
+            let e = self.power()?;
+            let op = match op0.lexeme.as_ref() {
+                "-" => UnaryOp::Neg,
+                "+" => UnaryOp::Pos,
                 _ => unreachable!("BUG ALERT"),
-            }
+            };
+            let span = op0.span.to(e.span);
+            Ok(Expr::new(ExprKind::Unary(op, Box::new(e)), span))
+        } else if self.have(TokenType::Tilde) {
+            let tilde = self.expect_retrieve(TokenType::Tilde)?;
+            let e = self.power()?;
+            let span = tilde.span.to(e.span);
+            Ok(Expr::new(
+                ExprKind::Unary(UnaryOp::BitNot, Box::new(e)),
+                span,
+            ))
         } else {
-            Err("invalid syntax".to_owned())
+            let mut e = self.primary()?;
+            loop {
+                if let Some(op) = self.postfix_op() {
+                    let span = e.span.to(self.token.span);
+                    self.token = self.scanner.as_mut().unwrap().next_token();
+                    e = Expr::new(ExprKind::Postfix(op, Box::new(e)), span);
+                } else if self.have(TokenType::OpenBracket) {
+                    e = self.index(e)?;
+                } else {
+                    break;
+                }
+            }
+            Ok(e)
         }
     }
 
-    fn factor(&mut self) -> Result<f64, String> {
-        let mut a = self.power()?;
+    fn index(&mut self, e: Expr) -> Result<Expr, Error> {
+        let open = self.expect_retrieve(TokenType::OpenBracket)?;
+        let mut indices = vec![self.exp()?];
+        if self.accept(TokenType::Comma) {
+            indices.push(self.exp()?);
+        }
+        let close = self.expect_close(&open)?;
+        let span = e.span.to(close.span);
+        Ok(Expr::new(ExprKind::Index(Box::new(e), indices), span))
+    }
+
+    fn binary(op: BinaryOp, a: Expr, b: Expr) -> Expr {
+        let span = a.span.to(b.span);
+        Expr::new(ExprKind::Binary(op, Box::new(a), Box::new(b)), span)
+    }
+
+    fn factor(&mut self) -> Result<Expr, Error> {
+        let a = self.power()?;
         if self.have_nt(NonTerminal::Op2) {
             let op2 = self.op2()?;
             let b = self.factor()?;
 
-            match op2.lexeme.as_ref() {
-                "^" => a = a.powf(b),
+            let op = match op2.lexeme.as_ref() {
+                "^" => BinaryOp::Pow,
                 _ => unreachable!("BUG ALERT"),
-            }
+            };
+            return Ok(Self::binary(op, a, b));
         }
 
         Ok(a)
     }
 
-    fn addend(&mut self) -> Result<f64, String> {
-        let mut a = self.factor()?;
+    /// Whether the current token is the conversion operator `to` or `in`.
+    fn at_conversion(&mut self) -> bool {
+        if !self.have(TokenType::Identifier) {
+            return false;
+        }
+        match self.token.lexeme.as_str() {
+            "to" => true,
+            "in" => {
+                let next = self.scanner.as_mut().unwrap().look_ahead();
+                match next.token_type {
+                    TokenType::Identifier => next.lexeme != "to" && next.lexeme != "in",
+                    TokenType::OpenParen => true,
+                    _ => false,
+                }
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether the current token is the bitwise operator `xor`.
+    fn at_xor(&mut self) -> bool {
+        self.have(TokenType::Identifier) && self.token.lexeme == "xor"
+    }
 
-        while self.have_nt(NonTerminal::Op1) {
-            let op1 = self.op1()?;
+    /// A factor, and if it is a number the built-in units following it.
+    fn quantity(&mut self) -> Result<Expr, Error> {
+        let mut a = self.factor()?;
+        let number = match &a.kind {
+            ExprKind::Literal(_) => true,
+            ExprKind::Unary(UnaryOp::Neg | UnaryOp::Pos, e) => {
+                matches!(e.kind, ExprKind::Literal(_))
+            }
+            _ => false,
+        };
+        while number
+            && self.have(TokenType::Identifier)
+            && Unit::lookup(&self.token.lexeme).is_some()
+            && !self.have_next(TokenType::OpenParen)
+            && !self.at_conversion()
+        {
             let b = self.factor()?;
+            a = Self::binary(BinaryOp::Mul, a, b);
+        }
+
+        Ok(a)
+    }
+
+    fn addend(&mut self) -> Result<Expr, Error> {
+        let mut a = self.quantity()?;
+
+        loop {
+            if self.have_nt(NonTerminal::Op1) {
+                let op1 = self.op1()?;
+                let b = self.quantity()?;
+
+                let op = match op1.lexeme.as_ref() {
+                    "*" => BinaryOp::Mul,
+                    "/" => BinaryOp::Div,
+                    "%" => BinaryOp::Mod,
+                    _ => unreachable!("BUG ALERT!"),
+                };
+                a = Self::binary(op, a, b);
+            } else if self.implicit_mul
+                && self.have_nt(NonTerminal::Juxtaposed)
+                && !self.at_conversion()
+                && !self.at_xor()
+            {
+                let b = self.factor()?;
+                a = Self::binary(BinaryOp::Mul, a, b);
+            } else {
+                break;
+            }
+        }
+
+        Ok(a)
+    }
+
+    fn exp(&mut self) -> Result<Expr, Error> {
+        let c = self.convert()?;
+        if self.accept(TokenType::Question) {
+            let a = self.exp()?;
+            self.expect(TokenType::Colon)?;
+            let b = self.exp()?;
+            let span = c.span.to(b.span);
+            return Ok(Expr::new(
+                ExprKind::Cond(Box::new(c), Box::new(a), Box::new(b)),
+                span,
+            ));
+        }
+
+        Ok(c)
+    }
+
+    fn convert(&mut self) -> Result<Expr, Error> {
+        let mut a = self.or()?;
+        while self.at_conversion() {
+            self.token = self.scanner.as_mut().unwrap().next_token();
+            let unit = self.addend()?;
+            let span = a.span.to(unit.span);
+            a = Expr::new(ExprKind::Convert(Box::new(a), Box::new(unit)), span);
+        }
+
+        Ok(a)
+    }
+
+    fn or(&mut self) -> Result<Expr, Error> {
+        let mut a = self.and()?;
+        while self.accept(TokenType::OrOr) {
+            let b = self.and()?;
+            a = Self::binary(BinaryOp::Or, a, b);
+        }
+
+        Ok(a)
+    }
+
+    fn and(&mut self) -> Result<Expr, Error> {
+        let mut a = self.not()?;
+        while self.accept(TokenType::AndAnd) {
+            let b = self.not()?;
+            a = Self::binary(BinaryOp::And, a, b);
+        }
+
+        Ok(a)
+    }
 
-            match op1.lexeme.as_ref() {
-                "*" => a *= b,
-                "/" => a /= b,
-                "%" => a %= b,
-                _ => unreachable!("BUG ALERT!"),
+    fn not(&mut self) -> Result<Expr, Error> {
+        if self.have(TokenType::Bang) || self.have(TokenType::DoubleBang) {
+            let token = self.token.clone();
+            self.token = self.scanner.as_mut().unwrap().next_token();
+            let mut e = self.not()?;
+            let span = token.span.to(e.span);
+            e = Expr::new(ExprKind::Unary(UnaryOp::Not, Box::new(e)), span);
+            if token.token_type == TokenType::DoubleBang {
+                e = Expr::new(ExprKind::Unary(UnaryOp::Not, Box::new(e)), span);
             }
+            return Ok(e);
+        }
+
+        self.cmp()
+    }
+
+    fn cmp(&mut self) -> Result<Expr, Error> {
+        let a = self.bit_or()?;
+        if self.have_nt(NonTerminal::Op3) {
+            let op3 = self.op3()?;
+            let b = self.bit_or()?;
+
+            let op = match op3.token_type {
+                TokenType::EqualEqual => BinaryOp::Eq,
+                TokenType::NotEqual => BinaryOp::Ne,
+                TokenType::Less => BinaryOp::Lt,
+                TokenType::LessEqual => BinaryOp::Le,
+                TokenType::Greater => BinaryOp::Gt,
+                TokenType::GreaterEqual => BinaryOp::Ge,
+                _ => unreachable!("BUG ALERT"),
+            };
+            return Ok(Self::binary(op, a, b));
+        }
+
+        Ok(a)
+    }
+
+    fn bit_or(&mut self) -> Result<Expr, Error> {
+        let mut a = self.bit_xor()?;
+        while self.accept(TokenType::Pipe) {
+            let b = self.bit_xor()?;
+            a = Self::binary(BinaryOp::BitOr, a, b);
+        }
+
+        Ok(a)
+    }
+
+    fn bit_xor(&mut self) -> Result<Expr, Error> {
+        let mut a = self.bit_and()?;
+        while self.at_xor() {
+            self.token = self.scanner.as_mut().unwrap().next_token();
+            let b = self.bit_and()?;
+            a = Self::binary(BinaryOp::BitXor, a, b);
+        }
+
+        Ok(a)
+    }
+
+    fn bit_and(&mut self) -> Result<Expr, Error> {
+        let mut a = self.shift()?;
+        while self.accept(TokenType::Ampersand) {
+            let b = self.shift()?;
+            a = Self::binary(BinaryOp::BitAnd, a, b);
+        }
+
+        Ok(a)
+    }
+
+    fn shift(&mut self) -> Result<Expr, Error> {
+        let mut a = self.sum()?;
+        loop {
+            let op = match self.token.token_type {
+                TokenType::LessLess => BinaryOp::Shl,
+                TokenType::GreaterGreater => BinaryOp::Shr,
+                TokenType::GreaterGreaterGreater => BinaryOp::LogicalShr,
+                _ => break,
+            };
+            self.token = self.scanner.as_mut().unwrap().next_token();
+            let b = self.sum()?;
+            a = Self::binary(op, a, b);
         }
 
         Ok(a)
     }
 
-    fn exp(&mut self) -> Result<f64, String> {
+    fn sum(&mut self) -> Result<Expr, Error> {
         let mut a = self.addend()?;
 
         while self.have_nt(NonTerminal::Op0) {
             let op0 = self.op0()?;
             let b = self.addend()?;
 
-            match op0.lexeme.as_ref() {
-                "-" => a -= b,
-                "+" => a += b,
+            let op = match op0.lexeme.as_ref() {
+                "-" => BinaryOp::Sub,
+                "+" => BinaryOp::Add,
                 _ => unreachable!("BUG ALERT"),
-            }
+            };
+            a = Self::binary(op, a, b);
         }
 
         Ok(a)
     }
 
-    fn exp_list(&mut self) -> Result<Vec<f64>, String> {
+    fn exp_list(&mut self) -> Result<Vec<Expr>, Error> {
         let mut exp_list = Vec::new();
         if self.have_nt(NonTerminal::Exp) {
             exp_list.push(self.exp()?);
@@ -301,88 +737,111 @@ impl Parser {
         Ok(exp_list)
     }
 
-    fn call(&mut self) -> Result<f64, String> {
-        let lexeme = self.expect_retrieve(TokenType::Identifier)?.lexeme;
+    fn call(&mut self) -> Result<Expr, Error> {
+        let ident = self.expect_retrieve(TokenType::Identifier)?;
 
-        if self.accept(TokenType::OpenParen) {
+        if self.have(TokenType::OpenParen) {
+            let open = self.expect_retrieve(TokenType::OpenParen)?;
             let params = self.exp_list()?;
-            self.expect(TokenType::CloseParen)?;
-            match self.functions.borrow().get(&lexeme.as_ref()) {
-                Some(function) => {
-                    if (function.param_count < 0
-                        && params.len() >= function.param_count.abs() as usize)
-                        || (params.len() == function.param_count as usize)
-                    {
-                        let f = function.f;
-                        Ok(f(&params))
-                    } else if function.param_count < 0 {
-                        Err(format!(
-                            "{} requires {} or more arguments",
-                            lexeme,
-                            function.param_count.abs()
-                        ))
-                    } else {
-                        Err(format!(
-                            "{} requires {} arguments",
-                            lexeme, function.param_count
-                        ))
-                    }
-                }
-                None => Err(format!("unknown function: {}", lexeme)),
+            let close = self.expect_close(&open)?;
+            let span = ident.span.to(close.span);
+            if ident.lexeme == "if" {
+                return Self::cond(params, span);
             }
+            Ok(Expr::new(ExprKind::Call(ident.lexeme, params), span))
         } else {
-            match self.constants.get(&lexeme.as_ref()) {
-                Some(v) => Ok(*v),
-                None => match self.memory.borrow().get(&lexeme) {
-                    Some(v) => Ok(*v),
-                    None => Err(format!("unknown variable: {}", lexeme)),
-                },
-            }
+            Ok(Expr::new(ExprKind::Var(ident.lexeme), ident.span))
         }
     }
 
-    fn is_valid_assignment(&self, var: &str) -> bool {
-        match var {
-            "ans" => false,
-            v => {
-                if self.constants.get(v).is_some() {
-                    return false;
-                }
+    /// Builds `cond ? a : b` from the arguments of `if(cond, a, b)`.
+    fn cond(params: Vec<Expr>, span: Span) -> Result<Expr, Error> {
+        if params.len() != 3 {
+            return Err(Error::Arity {
+                name: "if".to_owned(),
+                expected: 3,
+                variadic: false,
+                found: params.len(),
+                span,
+            });
+        }
 
-                if self.functions.borrow().get(v).is_some() {
-                    return false;
-                }
+        let mut params = params.into_iter();
+        let (c, a, b) = (
+            params.next().unwrap(),
+            params.next().unwrap(),
+            params.next().unwrap(),
+        );
+        Ok(Expr::new(
+            ExprKind::Cond(Box::new(c), Box::new(a), Box::new(b)),
+            span,
+        ))
+    }
 
-                true
-            }
+    fn input(&mut self) -> Result<Stmt, Error> {
+        if self.have(TokenType::Identifier) && self.have_next(TokenType::Equal) {
+            let var = self.expect_retrieve(TokenType::Identifier)?;
+            self.accept(TokenType::Equal);
+            let e = self.exp()?;
+            let span = var.span.to(e.span);
+            return Ok(Stmt::Expr(Expr::new(
+                ExprKind::Assign(var.lexeme, Box::new(e)),
+                span,
+            )));
+        }
+
+        let e = self.exp()?;
+        match e.kind {
+            ExprKind::Call(..) if self.have(TokenType::Equal) => self.function(e),
+            _ => Ok(Stmt::Expr(e)),
         }
     }
 
-    fn input(&mut self) -> Result<PResult, String> {
-        let mut result = PResult {
-            var: None,
-            val: 0.0,
+    /// Turns an already parsed call `name(a, b)` followed by "=" into a
+    /// function definition.
+    fn function(&mut self, head: Expr) -> Result<Stmt, Error> {
+        self.expect(TokenType::Equal)?;
+
+        let (name, args) = match head.kind {
+            ExprKind::Call(name, args) => (name, args),
+            _ => unreachable!("BUG ALERT"),
         };
-        if self.have(TokenType::Identifier) && self.have_next(TokenType::Equal) {
-            if !self.is_valid_assignment(&self.token.lexeme) {
-                return Err("cannot assign to built-in vars, constants or functions".into());
-            }
 
-            result.var = Some(self.token.lexeme.clone());
-            self.accept(TokenType::Identifier);
-            self.accept(TokenType::Equal);
+        let mut params: Vec<String> = Vec::with_capacity(args.len());
+        for arg in args {
+            match arg.kind {
+                ExprKind::Var(ref p) if params.contains(p) => {
+                    return Err(Error::syntax(
+                        format!("duplicate parameter '{}'", p),
+                        arg.span,
+                    ));
+                }
+                ExprKind::Var(p) => params.push(p),
+                _ => {
+                    return Err(Error::syntax(
+                        "expected a parameter name".to_owned(),
+                        arg.span,
+                    ));
+                }
+            }
         }
 
-        result.val = self.exp()?;
-
-        Ok(result)
+        let body = self.exp()?;
+        let span = head.span.to(body.span);
+        Ok(Stmt::Function(FunctionDef {
+            name,
+            params,
+            body,
+            span,
+        }))
     }
 
-    pub fn parse(&mut self, scanner: Scanner) -> Result<Option<PResult>, String> {
+    /// Parses a single statement. Returns `None` if there is no input.
+    pub fn parse(&mut self, scanner: Scanner) -> Result<Option<Stmt>, Error> {
         self.scanner = Some(scanner);
         self.token = self.scanner.as_mut().unwrap().next_token();
 
-        let result: Option<PResult> = if self.have_nt(NonTerminal::Line) {
+        let result: Option<Stmt> = if self.have_nt(NonTerminal::Line) {
             Some(self.input()?)
         } else {
             None
@@ -391,4 +850,22 @@ impl Parser {
         self.expect(TokenType::EOL)?;
         Ok(result)
     }
+
+    /// Parses statements separated by ";" or line breaks, skipping empty
+    /// ones.
+    pub fn parse_all(&mut self, scanner: Scanner) -> Result<Vec<Stmt>, Error> {
+        self.scanner = Some(scanner);
+        self.token = self.scanner.as_mut().unwrap().next_token();
+
+        let mut stmts = Vec::new();
+        loop {
+            if self.have_nt(NonTerminal::Line) {
+                stmts.push(self.input()?);
+            }
+            if self.accept(TokenType::EOL) {
+                return Ok(stmts);
+            }
+            self.expect(TokenType::Separator)?;
+        }
+    }
 }
@@ -1,23 +1,93 @@
+/// The classes of token the scanner produces.
 #[derive(Copy, Debug, PartialEq, Eq, Hash)]
 pub enum TokenType {
+    /// `+`
     Add,
+    /// `-`
     Sub,
+    /// `*`
     Mul,
+    /// `/`
     Div,
+    /// `%`
     Mod,
+    /// `^`
     Pow,
+    /// `!`
+    Bang,
+    /// `!!`
+    DoubleBang,
 
+    /// `==`
+    EqualEqual,
+    /// `!=`
+    NotEqual,
+    /// `<`
+    Less,
+    /// `<=`
+    LessEqual,
+    /// `>`
+    Greater,
+    /// `>=`
+    GreaterEqual,
+    /// `&&`
+    AndAnd,
+    /// `||`
+    OrOr,
+    /// `&`
+    Ampersand,
+    /// `|`
+    Pipe,
+    /// `~`
+    Tilde,
+    /// `<<`
+    LessLess,
+    /// `>>`
+    GreaterGreater,
+    /// `>>>`
+    GreaterGreaterGreater,
+    /// `?`
+    Question,
+    /// `:`
+    Colon,
+    /// `;`, or a line break that ends a statement.
+    Separator,
+
+    /// `=`
     Equal,
+    /// `,`
     Comma,
+    /// `(`
     OpenParen,
+    /// `)`
     CloseParen,
+    /// `[`
+    OpenBracket,
+    /// `]`
+    CloseBracket,
 
+    /// A name, like `x` or `sin`.
     Identifier,
+    /// `42`
     Integer,
+    /// `1.5`
     Float,
+    /// A number with an exponent, like `1e3` or `2.5e-3`.
     Exponent,
+    /// A number with an `i` suffix, like `2i` or `1.5e3i`.
+    Imaginary,
+    /// `0xff`
+    HexInteger,
+    /// `0o755`
+    OctalInteger,
+    /// `0b1010`
+    BinaryInteger,
+    /// `0x1.8p3`, 1.5 times 2^3.
+    HexFloat,
 
+    /// Input that is not a token, like an unknown character.
     Error,
+    /// The end of the input.
     EOL,
 }
 
@@ -27,10 +97,67 @@ impl Clone for TokenType {
     }
 }
 
+/// Half-open range of character offsets into the scanned input.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Span {
+    /// Offset of the first character.
+    pub start: usize,
+    /// Offset just past the last character.
+    pub end: usize,
+}
+
+impl Span {
+    /// The characters from `start` up to, but not including, `end`.
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    /// Smallest span covering both `self` and `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+
+    /// One-based column of the first character, as shown to the user.
+    pub fn column(&self) -> usize {
+        self.start + 1
+    }
+}
+
+/// A comment, `# ...` up to the end of the line or `/* ... */`, which the
+/// scanner skips but keeps with the token after it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Trivia {
+    /// The comment including its delimiters.
+    pub text: String,
+    /// Where the comment is in the input.
+    pub span: Span,
+}
+
+/// A lexeme together with its classification and location.
 #[derive(Debug, PartialEq, Eq)]
 pub struct Token {
+    /// The text of the token.
     pub lexeme: String,
+    /// What kind of token it is.
     pub token_type: TokenType,
+    /// Where the token is in the input.
+    pub span: Span,
+    /// The comments between the previous token and this one, so that a
+    /// formatter can put them back. Comments at the end of the input go
+    /// with `EOL`.
+    pub trivia: Vec<Trivia>,
+}
+
+impl Token {
+    /// A token without comments before it; only the scanner attaches those.
+    pub fn new(token_type: TokenType, lexeme: String, span: Span) -> Self {
+        Token {
+            lexeme,
+            token_type,
+            span,
+            trivia: Vec::new(),
+        }
+    }
 }
 
 impl Clone for Token {
@@ -38,6 +165,8 @@ impl Clone for Token {
         Token {
             lexeme: self.lexeme.clone(),
             token_type: self.token_type,
+            span: self.span,
+            trivia: self.trivia.clone(),
         }
     }
 }
@@ -50,6 +179,10 @@ enum State {
     Float,
     Identifier,
     Exponent,
+    /// The digits of an integer in base 2, 8 or 16 after its prefix.
+    Radix(u32),
+    /// The digits of a hex float after the point.
+    HexFloat,
 
     Finish,
 }
@@ -60,20 +193,42 @@ impl Clone for State {
     }
 }
 
+/// Splits input into tokens. Iterating yields every token up to and
+/// including the final `TokenType::EOL`.
+///
+/// A line break ends the statement before it, unless brackets are open or
+/// the line ends with an operator that needs another operand, in which case
+/// the statement goes on in the next line. Comments are skipped like
+/// whitespace and kept as `Trivia` on the token after them.
 pub struct Scanner {
     chars: Vec<char>,
     char_pos: usize,
+    next_pos: usize,
     next_char: char,
     next_state: State,
+    /// Brackets opened and not yet closed.
+    depth: usize,
+    /// The type of the last token returned other than `EOL`.
+    last: TokenType,
+    /// Comments skipped since the last token.
+    trivia: Vec<Trivia>,
+    /// Whether the input ends inside a `/* */` comment.
+    open_comment: bool,
 }
 
 impl Scanner {
+    /// A scanner positioned at the start of `s`.
     pub fn new(s: &str) -> Self {
         let mut scanner = Scanner {
             chars: s.chars().collect(),
             char_pos: 0,
+            next_pos: 0,
             next_char: '\0',
             next_state: State::Start,
+            depth: 0,
+            last: TokenType::Separator,
+            trivia: Vec::new(),
+            open_comment: false,
         };
         scanner.read_next_char();
         scanner
@@ -82,9 +237,11 @@ impl Scanner {
     fn read_next_char(&mut self) {
         if self.char_pos < self.chars.len() {
             self.next_char = self.chars[self.char_pos];
+            self.next_pos = self.char_pos;
             self.char_pos += 1;
         } else {
             self.next_char = '\0';
+            self.next_pos = self.chars.len();
         }
     }
 
@@ -97,97 +254,447 @@ impl Scanner {
         }
     }
 
+    /// Returns the character `n` places after `next_char`, or '\0'.
+    fn peek_char(&self, n: usize) -> char {
+        match self.chars.get(self.char_pos + n - 1) {
+            Some(c) => *c,
+            None => '\0',
+        }
+    }
+
+    /// Whether the 'e' in `next_char` starts an exponent rather than an
+    /// identifier following the number, as in `2e`.
+    fn exponent_follows(&self) -> bool {
+        match self.peek_char(1) {
+            '+' | '-' => Self::is_digit(self.peek_char(2)),
+            c => Self::is_digit(c),
+        }
+    }
+
+    /// Consumes digits in `radix` and the `_` separators between them, as
+    /// in `1_000_000`.
+    fn digits(&mut self, lexeme: &mut String, radix: u32) {
+        loop {
+            let separator = self.next_char == '_'
+                && lexeme.ends_with(|c: char| c.is_digit(radix))
+                && self.peek_char(1).is_digit(radix);
+            if !(self.next_char.is_digit(radix) || separator) {
+                break;
+            }
+            lexeme.push(self.next_char);
+            self.read_next_char();
+        }
+    }
+
+    /// The base of the integer whose prefix, `0x`, `0o` or `0b`, ends at
+    /// `next_char`, if a digit in that base follows.
+    fn radix_prefix(&self, lexeme: &str) -> Option<u32> {
+        let radix = match (lexeme, self.next_char) {
+            ("0", 'x') | ("0", 'X') => 16,
+            ("0", 'o') | ("0", 'O') => 8,
+            ("0", 'b') | ("0", 'B') => 2,
+            _ => return None,
+        };
+        Some(radix).filter(|&r| self.peek_char(1).is_digit(r))
+    }
+
+    /// The `p` exponent of a hex float, if `next_char` starts one.
+    fn binary_exponent(&mut self, lexeme: &mut String) -> bool {
+        if !matches!(self.next_char, 'p' | 'P') || !self.exponent_follows() {
+            return false;
+        }
+        lexeme.push(self.next_char);
+        self.read_next_char();
+        if let '+' | '-' = self.next_char {
+            lexeme.push(self.next_char);
+            self.read_next_char();
+        }
+        self.digits(lexeme, 10);
+        true
+    }
+
+    /// The number token ending before `next_char`, or an `Imaginary` one if
+    /// an `i` follows that does not start an identifier, as in `2in`.
+    fn number(&mut self, mut lexeme: String, token_type: TokenType, start: usize) -> Token {
+        let c = self.peek_char(1);
+        let token_type =
+            if self.next_char == 'i' && !(Self::is_alpha(c) || Self::is_digit(c) || c == '_') {
+                lexeme.push(self.next_char);
+                self.read_next_char();
+                TokenType::Imaginary
+            } else {
+                token_type
+            };
+        Token::new(token_type, lexeme, Span::new(start, self.next_pos))
+    }
+
     fn is_digit(c: char) -> bool {
-        c >= '0' && c <= '9'
+        c.is_ascii_digit()
     }
 
     fn is_alpha(c: char) -> bool {
-        (c >= 'a' && c <= 'z') || (c >= 'A' && c <= 'Z')
+        c.is_ascii_alphabetic()
     }
 
+    /// Skips the comment starting at `next_char`, which ends before the
+    /// next line break, or with `*/` if `block`. Returns `false` if a block
+    /// comment is not closed.
+    fn comment(&mut self, block: bool) -> bool {
+        let start = self.next_pos;
+        let mut text = String::new();
+        let closed = loop {
+            match self.next_char {
+                '\0' => break !block,
+                '\n' if !block => break true,
+                '/' if block && text.len() > 2 && text.ends_with('*') => {
+                    text.push('/');
+                    self.read_next_char();
+                    break true;
+                }
+                c => {
+                    text.push(c);
+                    self.read_next_char();
+                }
+            }
+        };
+        self.trivia.push(Trivia {
+            text,
+            span: Span::new(start, self.next_pos),
+        });
+        closed
+    }
+
+    /// Whether the input scanned so far ends inside brackets, a comment or
+    /// after an operator, so that a line break does not end the statement.
+    pub fn continues(&self) -> bool {
+        self.depth > 0
+            || self.open_comment
+            || matches!(
+                self.last,
+                TokenType::Add
+                    | TokenType::Sub
+                    | TokenType::Mul
+                    | TokenType::Div
+                    | TokenType::Pow
+                    | TokenType::EqualEqual
+                    | TokenType::NotEqual
+                    | TokenType::Less
+                    | TokenType::LessEqual
+                    | TokenType::Greater
+                    | TokenType::GreaterEqual
+                    | TokenType::AndAnd
+                    | TokenType::OrOr
+                    | TokenType::Ampersand
+                    | TokenType::Pipe
+                    | TokenType::Tilde
+                    | TokenType::LessLess
+                    | TokenType::GreaterGreater
+                    | TokenType::GreaterGreaterGreater
+                    | TokenType::Question
+                    | TokenType::Colon
+                    | TokenType::Equal
+                    | TokenType::Comma
+            )
+    }
+
+    /// Scans the next token, `EOL` at the end of the input.
     pub fn next_token(&mut self) -> Token {
+        let mut token = self.scan();
+        token.trivia = std::mem::take(&mut self.trivia);
+        match token.token_type {
+            TokenType::OpenParen | TokenType::OpenBracket => self.depth += 1,
+            TokenType::CloseParen | TokenType::CloseBracket => {
+                self.depth = self.depth.saturating_sub(1)
+            }
+            _ => {}
+        }
+        if token.token_type != TokenType::EOL {
+            self.last = token.token_type;
+        }
+        token
+    }
+
+    fn scan(&mut self) -> Token {
         let mut lexeme = String::new();
+        let mut start = self.next_pos;
 
         loop {
             match self.next_state {
                 State::Start => {
                     lexeme.clear();
+                    start = self.next_pos;
                     match self.next_char {
-                        '\0' | '\n' => {
+                        '\0' => {
                             self.next_state = State::Finish;
                         }
-                        c @ '\t' | c @ ' ' => {
+                        '\n' if !self.continues() => {
+                            self.read_next_char();
+                            return Token::new(
+                                TokenType::Separator,
+                                "\n".to_owned(),
+                                Span::new(start, self.next_pos),
+                            );
+                        }
+                        c @ '\t' | c @ ' ' | c @ '\r' | c @ '\n' => {
                             self.eat(c);
                         }
+                        '#' => {
+                            self.comment(false);
+                        }
+                        '/' if self.peek_char(1) == '*' => {
+                            if !self.comment(true) {
+                                self.open_comment = true;
+                                let comment = self.trivia.pop().unwrap();
+                                return Token::new(TokenType::Error, "/*".to_owned(), comment.span);
+                            }
+                        }
+                        ';' => {
+                            self.read_next_char();
+                            return Token::new(
+                                TokenType::Separator,
+                                ";".to_owned(),
+                                Span::new(start, self.next_pos),
+                            );
+                        }
                         '=' => {
                             self.read_next_char();
-                            return Token {
-                                lexeme: "=".to_owned(),
-                                token_type: TokenType::Equal,
-                            };
+                            if self.next_char == '=' {
+                                self.read_next_char();
+                                return Token::new(
+                                    TokenType::EqualEqual,
+                                    "==".to_owned(),
+                                    Span::new(start, self.next_pos),
+                                );
+                            }
+                            return Token::new(
+                                TokenType::Equal,
+                                "=".to_owned(),
+                                Span::new(start, self.next_pos),
+                            );
                         }
                         '+' => {
                             self.read_next_char();
-                            return Token {
-                                lexeme: "+".to_owned(),
-                                token_type: TokenType::Add,
-                            };
+                            return Token::new(
+                                TokenType::Add,
+                                "+".to_owned(),
+                                Span::new(start, self.next_pos),
+                            );
                         }
                         '-' => {
                             self.read_next_char();
-                            return Token {
-                                lexeme: "-".to_owned(),
-                                token_type: TokenType::Sub,
-                            };
+                            return Token::new(
+                                TokenType::Sub,
+                                "-".to_owned(),
+                                Span::new(start, self.next_pos),
+                            );
                         }
                         '*' => {
                             self.read_next_char();
-                            return Token {
-                                lexeme: "*".to_owned(),
-                                token_type: TokenType::Mul,
-                            };
+                            return Token::new(
+                                TokenType::Mul,
+                                "*".to_owned(),
+                                Span::new(start, self.next_pos),
+                            );
                         }
                         '/' => {
                             self.read_next_char();
-                            return Token {
-                                lexeme: "/".to_owned(),
-                                token_type: TokenType::Div,
-                            };
+                            return Token::new(
+                                TokenType::Div,
+                                "/".to_owned(),
+                                Span::new(start, self.next_pos),
+                            );
                         }
                         '%' => {
                             self.read_next_char();
-                            return Token {
-                                lexeme: "%".to_owned(),
-                                token_type: TokenType::Mod,
-                            };
+                            return Token::new(
+                                TokenType::Mod,
+                                "%".to_owned(),
+                                Span::new(start, self.next_pos),
+                            );
                         }
                         '^' => {
                             self.read_next_char();
-                            return Token {
-                                lexeme: "^".to_owned(),
-                                token_type: TokenType::Pow,
-                            };
+                            return Token::new(
+                                TokenType::Pow,
+                                "^".to_owned(),
+                                Span::new(start, self.next_pos),
+                            );
+                        }
+                        '!' => {
+                            self.read_next_char();
+                            if self.next_char == '!' {
+                                self.read_next_char();
+                                return Token::new(
+                                    TokenType::DoubleBang,
+                                    "!!".to_owned(),
+                                    Span::new(start, self.next_pos),
+                                );
+                            }
+                            if self.next_char == '=' {
+                                self.read_next_char();
+                                return Token::new(
+                                    TokenType::NotEqual,
+                                    "!=".to_owned(),
+                                    Span::new(start, self.next_pos),
+                                );
+                            }
+                            return Token::new(
+                                TokenType::Bang,
+                                "!".to_owned(),
+                                Span::new(start, self.next_pos),
+                            );
+                        }
+                        '<' => {
+                            self.read_next_char();
+                            if self.next_char == '<' {
+                                self.read_next_char();
+                                return Token::new(
+                                    TokenType::LessLess,
+                                    "<<".to_owned(),
+                                    Span::new(start, self.next_pos),
+                                );
+                            }
+                            if self.next_char == '=' {
+                                self.read_next_char();
+                                return Token::new(
+                                    TokenType::LessEqual,
+                                    "<=".to_owned(),
+                                    Span::new(start, self.next_pos),
+                                );
+                            }
+                            return Token::new(
+                                TokenType::Less,
+                                "<".to_owned(),
+                                Span::new(start, self.next_pos),
+                            );
+                        }
+                        '>' => {
+                            self.read_next_char();
+                            if self.next_char == '>' {
+                                self.read_next_char();
+                                if self.next_char == '>' {
+                                    self.read_next_char();
+                                    return Token::new(
+                                        TokenType::GreaterGreaterGreater,
+                                        ">>>".to_owned(),
+                                        Span::new(start, self.next_pos),
+                                    );
+                                }
+                                return Token::new(
+                                    TokenType::GreaterGreater,
+                                    ">>".to_owned(),
+                                    Span::new(start, self.next_pos),
+                                );
+                            }
+                            if self.next_char == '=' {
+                                self.read_next_char();
+                                return Token::new(
+                                    TokenType::GreaterEqual,
+                                    ">=".to_owned(),
+                                    Span::new(start, self.next_pos),
+                                );
+                            }
+                            return Token::new(
+                                TokenType::Greater,
+                                ">".to_owned(),
+                                Span::new(start, self.next_pos),
+                            );
+                        }
+                        '&' => {
+                            self.read_next_char();
+                            if self.next_char == '&' {
+                                self.read_next_char();
+                                return Token::new(
+                                    TokenType::AndAnd,
+                                    "&&".to_owned(),
+                                    Span::new(start, self.next_pos),
+                                );
+                            }
+                            return Token::new(
+                                TokenType::Ampersand,
+                                "&".to_owned(),
+                                Span::new(start, self.next_pos),
+                            );
+                        }
+                        '|' => {
+                            self.read_next_char();
+                            if self.next_char == '|' {
+                                self.read_next_char();
+                                return Token::new(
+                                    TokenType::OrOr,
+                                    "||".to_owned(),
+                                    Span::new(start, self.next_pos),
+                                );
+                            }
+                            return Token::new(
+                                TokenType::Pipe,
+                                "|".to_owned(),
+                                Span::new(start, self.next_pos),
+                            );
+                        }
+                        '~' => {
+                            self.read_next_char();
+                            return Token::new(
+                                TokenType::Tilde,
+                                "~".to_owned(),
+                                Span::new(start, self.next_pos),
+                            );
+                        }
+                        '?' => {
+                            self.read_next_char();
+                            return Token::new(
+                                TokenType::Question,
+                                "?".to_owned(),
+                                Span::new(start, self.next_pos),
+                            );
+                        }
+                        ':' => {
+                            self.read_next_char();
+                            return Token::new(
+                                TokenType::Colon,
+                                ":".to_owned(),
+                                Span::new(start, self.next_pos),
+                            );
                         }
                         ',' => {
                             self.read_next_char();
-                            return Token {
-                                lexeme: ",".to_owned(),
-                                token_type: TokenType::Comma,
-                            };
+                            return Token::new(
+                                TokenType::Comma,
+                                ",".to_owned(),
+                                Span::new(start, self.next_pos),
+                            );
                         }
                         '(' => {
                             self.read_next_char();
-                            return Token {
-                                lexeme: "(".to_owned(),
-                                token_type: TokenType::OpenParen,
-                            };
+                            return Token::new(
+                                TokenType::OpenParen,
+                                "(".to_owned(),
+                                Span::new(start, self.next_pos),
+                            );
                         }
                         ')' => {
                             self.read_next_char();
-                            return Token {
-                                lexeme: ")".to_owned(),
-                                token_type: TokenType::CloseParen,
-                            };
+                            return Token::new(
+                                TokenType::CloseParen,
+                                ")".to_owned(),
+                                Span::new(start, self.next_pos),
+                            );
+                        }
+                        '[' => {
+                            self.read_next_char();
+                            return Token::new(
+                                TokenType::OpenBracket,
+                                "[".to_owned(),
+                                Span::new(start, self.next_pos),
+                            );
+                        }
+                        ']' => {
+                            self.read_next_char();
+                            return Token::new(
+                                TokenType::CloseBracket,
+                                "]".to_owned(),
+                                Span::new(start, self.next_pos),
+                            );
                         }
                         c @ '.' => {
                             self.read_next_char();
@@ -203,10 +710,11 @@ impl Scanner {
                                 self.next_state = State::Integer;
                             } else {
                                 self.next_state = State::Start;
-                                return Token {
+                                return Token::new(
+                                    TokenType::Error,
                                     lexeme,
-                                    token_type: TokenType::Error,
-                                };
+                                    Span::new(start, self.next_pos),
+                                );
                             }
                         }
                     }
@@ -221,49 +729,45 @@ impl Scanner {
                     }
 
                     self.next_state = State::Start;
-                    return Token {
+                    return Token::new(
+                        TokenType::Identifier,
                         lexeme,
-                        token_type: TokenType::Identifier,
-                    };
+                        Span::new(start, self.next_pos),
+                    );
                 }
                 State::Integer => {
-                    while Self::is_digit(self.next_char) {
+                    if let Some(radix) = self.radix_prefix(&lexeme) {
                         lexeme.push(self.next_char);
                         self.read_next_char();
+                        self.next_state = State::Radix(radix);
+                        continue;
                     }
 
+                    self.digits(&mut lexeme, 10);
+
                     if self.next_char == '.' {
                         lexeme.push(self.next_char);
                         self.read_next_char();
                         self.next_state = State::Float;
-                    } else if self.next_char == 'e' {
+                    } else if self.next_char == 'e' && self.exponent_follows() {
                         lexeme.push(self.next_char);
                         self.read_next_char();
                         self.next_state = State::Exponent;
                     } else {
                         self.next_state = State::Start;
-                        return Token {
-                            lexeme,
-                            token_type: TokenType::Integer,
-                        };
+                        return self.number(lexeme, TokenType::Integer, start);
                     }
                 }
                 State::Float => {
-                    while Self::is_digit(self.next_char) {
-                        lexeme.push(self.next_char);
-                        self.read_next_char();
-                    }
+                    self.digits(&mut lexeme, 10);
 
-                    if self.next_char == 'e' {
+                    if self.next_char == 'e' && self.exponent_follows() {
                         lexeme.push(self.next_char);
                         self.read_next_char();
                         self.next_state = State::Exponent;
                     } else {
                         self.next_state = State::Start;
-                        return Token {
-                            lexeme,
-                            token_type: TokenType::Float,
-                        };
+                        return self.number(lexeme, TokenType::Float, start);
                     }
                 }
                 State::Exponent => {
@@ -275,37 +779,73 @@ impl Scanner {
                         _ => {}
                     }
 
-                    while Self::is_digit(self.next_char) {
+                    self.digits(&mut lexeme, 10);
+
+                    self.next_state = State::Start;
+                    return self.number(lexeme, TokenType::Exponent, start);
+                }
+                State::Radix(radix) => {
+                    self.digits(&mut lexeme, radix);
+
+                    if radix == 16 && self.next_char == '.' {
                         lexeme.push(self.next_char);
                         self.read_next_char();
+                        self.next_state = State::HexFloat;
+                        continue;
                     }
 
+                    let token_type = match radix {
+                        16 if self.binary_exponent(&mut lexeme) => TokenType::HexFloat,
+                        16 => TokenType::HexInteger,
+                        8 => TokenType::OctalInteger,
+                        _ => TokenType::BinaryInteger,
+                    };
+                    self.next_state = State::Start;
+                    return Token::new(token_type, lexeme, Span::new(start, self.next_pos));
+                }
+                State::HexFloat => {
+                    self.digits(&mut lexeme, 16);
+                    self.binary_exponent(&mut lexeme);
+
                     self.next_state = State::Start;
-                    return Token {
+                    return Token::new(
+                        TokenType::HexFloat,
                         lexeme,
-                        token_type: TokenType::Exponent,
-                    };
+                        Span::new(start, self.next_pos),
+                    );
                 }
                 State::Finish => {
-                    return Token {
-                        lexeme: "".to_owned(),
-                        token_type: TokenType::EOL,
-                    }
+                    return Token::new(
+                        TokenType::EOL,
+                        "".to_owned(),
+                        Span::new(self.next_pos, self.next_pos),
+                    )
                 }
             }
         }
     }
 
+    /// Returns the next token without consuming it.
     pub fn look_ahead(&mut self) -> Token {
         let char_pos = self.char_pos;
+        let next_pos = self.next_pos;
         let next_char = self.next_char;
         let next_state = self.next_state;
+        let depth = self.depth;
+        let last = self.last;
+        let trivia = self.trivia.clone();
+        let open_comment = self.open_comment;
 
         let token = self.next_token();
 
         self.char_pos = char_pos;
+        self.next_pos = next_pos;
         self.next_char = next_char;
         self.next_state = next_state;
+        self.depth = depth;
+        self.last = last;
+        self.trivia = trivia;
+        self.open_comment = open_comment;
 
         token
     }
@@ -323,4 +863,114 @@ impl Iterator for Scanner {
 }
 
 #[cfg(test)]
-mod test {}
+mod test {
+    use super::*;
+
+    #[test]
+    fn spans() {
+        let spans: Vec<(TokenType, Span)> = Scanner::new(" ab = 1.5e3*(x) ")
+            .map(|t| (t.token_type, t.span))
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                (TokenType::Identifier, Span::new(1, 3)),
+                (TokenType::Equal, Span::new(4, 5)),
+                (TokenType::Exponent, Span::new(6, 11)),
+                (TokenType::Mul, Span::new(11, 12)),
+                (TokenType::OpenParen, Span::new(12, 13)),
+                (TokenType::Identifier, Span::new(13, 14)),
+                (TokenType::CloseParen, Span::new(14, 15)),
+                (TokenType::EOL, Span::new(16, 16)),
+            ]
+        );
+    }
+
+    #[test]
+    fn radix_literals() {
+        let tokens: Vec<(TokenType, String)> =
+            Scanner::new("0xFF 0o7_55 0b1x 0x1.8p-3 1_000 0b2 2_x")
+                .map(|t| (t.token_type, t.lexeme))
+                .collect();
+        let expected = [
+            (TokenType::HexInteger, "0xFF"),
+            (TokenType::OctalInteger, "0o7_55"),
+            (TokenType::BinaryInteger, "0b1"),
+            (TokenType::Identifier, "x"),
+            (TokenType::HexFloat, "0x1.8p-3"),
+            (TokenType::Integer, "1_000"),
+            (TokenType::Integer, "0"),
+            (TokenType::Identifier, "b2"),
+            (TokenType::Integer, "2"),
+            (TokenType::Identifier, "_x"),
+            (TokenType::EOL, ""),
+        ];
+        let expected: Vec<(TokenType, String)> =
+            expected.iter().map(|(t, l)| (*t, l.to_string())).collect();
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn separators() {
+        let types: Vec<TokenType> = Scanner::new("a = 1; b = (2,\n 3)\r\n\nc = a +\n  b\n")
+            .map(|t| t.token_type)
+            .collect();
+        use TokenType::*;
+        assert_eq!(
+            types,
+            vec![
+                Identifier, Equal, Integer, Separator, Identifier, Equal, OpenParen, Integer,
+                Comma, Integer, CloseParen, Separator, Separator, Identifier, Equal, Identifier,
+                Add, Identifier, Separator, EOL,
+            ]
+        );
+
+        let mut scanner = Scanner::new("f(1, [2");
+        while scanner.next_token().token_type != EOL {}
+        assert!(scanner.continues());
+        let mut scanner = Scanner::new("2 ^");
+        while scanner.next_token().token_type != EOL {}
+        assert!(scanner.continues());
+        let mut scanner = Scanner::new("(2)! * 50%");
+        while scanner.next_token().token_type != EOL {}
+        assert!(!scanner.continues());
+    }
+
+    #[test]
+    fn comments() {
+        let tokens: Vec<Token> =
+            Scanner::new("1 /* one */ + 2 # two\n3/*a\nb*/*4 /**/ # end").collect();
+        let summary: Vec<(TokenType, &str, Vec<&str>)> = tokens
+            .iter()
+            .map(|t| {
+                let trivia = t.trivia.iter().map(|c| c.text.as_str()).collect();
+                (t.token_type, t.lexeme.as_str(), trivia)
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (TokenType::Integer, "1", vec![]),
+                (TokenType::Add, "+", vec!["/* one */"]),
+                (TokenType::Integer, "2", vec![]),
+                (TokenType::Separator, "\n", vec!["# two"]),
+                (TokenType::Integer, "3", vec![]),
+                (TokenType::Mul, "*", vec!["/*a\nb*/"]),
+                (TokenType::Integer, "4", vec![]),
+                (TokenType::EOL, "", vec!["/**/", "# end"]),
+            ]
+        );
+        assert_eq!(tokens[1].trivia[0].span, Span::new(2, 11));
+
+        // A comment keeps the statement open, as whitespace would.
+        let mut scanner = Scanner::new("2 + # more to come");
+        while scanner.next_token().token_type != TokenType::EOL {}
+        assert!(scanner.continues());
+
+        let mut scanner = Scanner::new("1 /*/ 2");
+        scanner.next_token();
+        let t = scanner.next_token();
+        assert_eq!((t.token_type, t.span), (TokenType::Error, Span::new(2, 7)));
+        assert!(scanner.continues());
+    }
+}
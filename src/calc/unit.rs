@@ -0,0 +1,386 @@
+use crate::calc::{Number, Value};
+use num_traits::ToPrimitive;
+use std::fmt;
+
+/// Exponents of the base dimensions: length, mass, time, electric current,
+/// temperature, amount of substance, luminous intensity and information.
+pub type Dimension = [i8; 8];
+
+const NONE: Dimension = [0; 8];
+const LENGTH: Dimension = [1, 0, 0, 0, 0, 0, 0, 0];
+const AREA: Dimension = [2, 0, 0, 0, 0, 0, 0, 0];
+const VOLUME: Dimension = [3, 0, 0, 0, 0, 0, 0, 0];
+const MASS: Dimension = [0, 1, 0, 0, 0, 0, 0, 0];
+const TIME: Dimension = [0, 0, 1, 0, 0, 0, 0, 0];
+const CURRENT: Dimension = [0, 0, 0, 1, 0, 0, 0, 0];
+const TEMPERATURE: Dimension = [0, 0, 0, 0, 1, 0, 0, 0];
+const AMOUNT: Dimension = [0, 0, 0, 0, 0, 1, 0, 0];
+const LUMINOSITY: Dimension = [0, 0, 0, 0, 0, 0, 1, 0];
+const INFORMATION: Dimension = [0, 0, 0, 0, 0, 0, 0, 1];
+const FREQUENCY: Dimension = [0, 0, -1, 0, 0, 0, 0, 0];
+const SPEED: Dimension = [1, 0, -1, 0, 0, 0, 0, 0];
+const FORCE: Dimension = [1, 1, -2, 0, 0, 0, 0, 0];
+const PRESSURE: Dimension = [-1, 1, -2, 0, 0, 0, 0, 0];
+const ENERGY: Dimension = [2, 1, -2, 0, 0, 0, 0, 0];
+const POWER: Dimension = [2, 1, -3, 0, 0, 0, 0, 0];
+const CHARGE: Dimension = [0, 0, 1, 1, 0, 0, 0, 0];
+const VOLTAGE: Dimension = [2, 1, -3, -1, 0, 0, 0, 0];
+const RESISTANCE: Dimension = [2, 1, -3, -2, 0, 0, 0, 0];
+const CONDUCTANCE: Dimension = [-2, -1, 3, 2, 0, 0, 0, 0];
+const CAPACITANCE: Dimension = [-2, -1, 4, 2, 0, 0, 0, 0];
+const MAGNETIC_FLUX: Dimension = [2, 1, -2, -1, 0, 0, 0, 0];
+const FLUX_DENSITY: Dimension = [0, 1, -2, -1, 0, 0, 0, 0];
+const INDUCTANCE: Dimension = [2, 1, -2, -2, 0, 0, 0, 0];
+
+/// Which prefixes a unit accepts.
+#[derive(Copy, Clone, PartialEq)]
+enum Prefixes {
+    None,
+    /// `km`, `mA`, ...
+    Si,
+    /// SI and binary prefixes, `kB` and `KiB`.
+    Binary,
+}
+
+struct UnitDef {
+    names: &'static [&'static str],
+    /// Size in SI base units, a decimal or a quotient of two.
+    scale: &'static str,
+    dim: Dimension,
+    prefixes: Prefixes,
+}
+
+const fn unit(
+    names: &'static [&'static str],
+    scale: &'static str,
+    dim: Dimension,
+    prefixes: Prefixes,
+) -> UnitDef {
+    UnitDef {
+        names,
+        scale,
+        dim,
+        prefixes,
+    }
+}
+
+/// The built-in units. Names are matched exactly before trying prefixes, so
+/// `min` is a minute rather than a milli-inch.
+#[rustfmt::skip]
+const UNITS: &[UnitDef] = &[
+    // SI base units, with the gram standing in for the kilogram.
+    unit(&["m", "meter", "meters", "metre", "metres"], "1", LENGTH, Prefixes::Si),
+    unit(&["g", "gram", "grams"], "0.001", MASS, Prefixes::Si),
+    unit(&["s", "sec", "second", "seconds"], "1", TIME, Prefixes::Si),
+    unit(&["A", "amp", "amps", "ampere", "amperes"], "1", CURRENT, Prefixes::Si),
+    unit(&["K", "kelvin"], "1", TEMPERATURE, Prefixes::Si),
+    unit(&["mol", "mole", "moles"], "1", AMOUNT, Prefixes::Si),
+    unit(&["cd", "candela"], "1", LUMINOSITY, Prefixes::Si),
+    unit(&["bit", "bits"], "1", INFORMATION, Prefixes::Binary),
+    unit(&["B", "byte", "bytes"], "8", INFORMATION, Prefixes::Binary),
+
+    // Derived SI units.
+    unit(&["Hz", "hertz"], "1", FREQUENCY, Prefixes::Si),
+    unit(&["N", "newton", "newtons"], "1", FORCE, Prefixes::Si),
+    unit(&["Pa", "pascal", "pascals"], "1", PRESSURE, Prefixes::Si),
+    unit(&["J", "joule", "joules"], "1", ENERGY, Prefixes::Si),
+    unit(&["W", "watt", "watts"], "1", POWER, Prefixes::Si),
+    unit(&["C", "coulomb", "coulombs"], "1", CHARGE, Prefixes::Si),
+    unit(&["V", "volt", "volts"], "1", VOLTAGE, Prefixes::Si),
+    unit(&["ohm", "ohms"], "1", RESISTANCE, Prefixes::Si),
+    unit(&["S", "siemens"], "1", CONDUCTANCE, Prefixes::Si),
+    unit(&["F", "farad", "farads"], "1", CAPACITANCE, Prefixes::Si),
+    unit(&["Wb", "weber", "webers"], "1", MAGNETIC_FLUX, Prefixes::Si),
+    unit(&["T", "tesla", "teslas"], "1", FLUX_DENSITY, Prefixes::Si),
+    unit(&["H", "henry", "henries"], "1", INDUCTANCE, Prefixes::Si),
+
+    // Other metric units.
+    unit(&["L", "l", "liter", "liters", "litre", "litres"], "0.001", VOLUME, Prefixes::Si),
+    unit(&["t", "tonne", "tonnes"], "1000", MASS, Prefixes::None),
+    unit(&["ha", "hectare", "hectares"], "10000", AREA, Prefixes::None),
+    unit(&["bar"], "100000", PRESSURE, Prefixes::Si),
+    unit(&["atm"], "101325", PRESSURE, Prefixes::None),
+    unit(&["eV", "electronvolt", "electronvolts"], "1.602176634e-19", ENERGY, Prefixes::Si),
+    unit(&["cal", "calorie", "calories"], "4.184", ENERGY, Prefixes::Si),
+    unit(&["Wh"], "3600", ENERGY, Prefixes::Si),
+
+    // Time.
+    unit(&["min", "minute", "minutes"], "60", TIME, Prefixes::None),
+    unit(&["h", "hr", "hour", "hours"], "3600", TIME, Prefixes::None),
+    unit(&["day", "days"], "86400", TIME, Prefixes::None),
+    unit(&["week", "weeks"], "604800", TIME, Prefixes::None),
+    unit(&["yr", "year", "years"], "31557600", TIME, Prefixes::None),
+
+    // Imperial and US customary units.
+    unit(&["mil", "thou"], "0.0000254", LENGTH, Prefixes::None),
+    unit(&["in", "inch", "inches"], "0.0254", LENGTH, Prefixes::None),
+    unit(&["ft", "foot", "feet"], "0.3048", LENGTH, Prefixes::None),
+    unit(&["yd", "yard", "yards"], "0.9144", LENGTH, Prefixes::None),
+    unit(&["mi", "mile", "miles"], "1609.344", LENGTH, Prefixes::None),
+    unit(&["nmi"], "1852", LENGTH, Prefixes::None),
+    unit(&["acre", "acres"], "4046.8564224", AREA, Prefixes::None),
+    unit(&["gal", "gallon", "gallons"], "0.003785411784", VOLUME, Prefixes::None),
+    unit(&["oz", "ounce", "ounces"], "0.028349523125", MASS, Prefixes::None),
+    unit(&["lb", "lbs", "pound", "pounds"], "0.45359237", MASS, Prefixes::None),
+    unit(&["mph"], "0.44704", SPEED, Prefixes::None),
+    unit(&["kn", "knot", "knots"], "1852/3600", SPEED, Prefixes::None),
+    unit(&["lbf"], "4.4482216152605", FORCE, Prefixes::None),
+    unit(&["psi"], "4.4482216152605/0.00064516", PRESSURE, Prefixes::None),
+];
+
+/// Symbol, name and factor of the SI prefixes, `da` before `d` so the
+/// longer one matches first.
+const SI_PREFIXES: &[(&str, &str, &str)] = &[
+    ("Y", "yotta", "1e24"),
+    ("Z", "zetta", "1e21"),
+    ("E", "exa", "1e18"),
+    ("P", "peta", "1e15"),
+    ("T", "tera", "1e12"),
+    ("G", "giga", "1e9"),
+    ("M", "mega", "1e6"),
+    ("k", "kilo", "1e3"),
+    ("h", "hecto", "1e2"),
+    ("da", "deca", "1e1"),
+    ("d", "deci", "1e-1"),
+    ("c", "centi", "1e-2"),
+    ("m", "milli", "1e-3"),
+    ("u", "micro", "1e-6"),
+    ("n", "nano", "1e-9"),
+    ("p", "pico", "1e-12"),
+    ("f", "femto", "1e-15"),
+    ("a", "atto", "1e-18"),
+    ("z", "zepto", "1e-21"),
+    ("y", "yocto", "1e-24"),
+];
+
+const BINARY_PREFIXES: &[(&str, &str, &str)] = &[
+    ("Ki", "kibi", "1024"),
+    ("Mi", "mebi", "1048576"),
+    ("Gi", "gibi", "1073741824"),
+    ("Ti", "tebi", "1099511627776"),
+    ("Pi", "pebi", "1125899906842624"),
+    ("Ei", "exbi", "1152921504606846976"),
+];
+
+/// Parses a table scale, `a` or `a/b`.
+fn parse_scale(s: &str) -> Number {
+    let mut parts = s
+        .split('/')
+        .map(|p| Number::parse_decimal(p).expect("BUG ALERT: invalid scale in unit table"));
+    let n = parts.next().unwrap();
+    parts.fold(n, |n, d| n.div(&d))
+}
+
+/// A product of powers of named units, like `km/h` or `kg*m/s^2`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Unit {
+    /// Names as written, with their powers, in order of appearance.
+    factors: Vec<(String, i8)>,
+    /// Size in SI base units.
+    scale: Number,
+    dim: Dimension,
+}
+
+impl Unit {
+    /// The unit of plain numbers.
+    pub fn none() -> Self {
+        Unit {
+            factors: Vec::new(),
+            scale: Number::from(1),
+            dim: NONE,
+        }
+    }
+
+    /// A built-in unit, optionally with an SI or binary prefix.
+    pub fn lookup(name: &str) -> Option<Self> {
+        let (scale, dim) = find(name).or_else(|| {
+            let prefixes = SI_PREFIXES.iter().chain(BINARY_PREFIXES);
+            prefixes
+                .flat_map(|&(symbol, long, factor)| [(symbol, factor), (long, factor)])
+                .filter(|(p, _)| name.len() > p.len() && name.starts_with(p))
+                .find_map(|(p, factor)| {
+                    let def = UNITS.iter().find(|u| u.names.contains(&&name[p.len()..]))?;
+                    let binary = BINARY_PREFIXES.iter().any(|b| b.0 == p || b.1 == p);
+                    match def.prefixes {
+                        Prefixes::Binary => {}
+                        Prefixes::Si if !binary => {}
+                        _ => return None,
+                    }
+                    Some((parse_scale(factor).mul(&parse_scale(def.scale)), def.dim))
+                })
+        })?;
+        Some(Unit {
+            factors: vec![(name.to_owned(), 1)],
+            scale,
+            dim,
+        })
+    }
+
+    /// Size in SI base units, 1000 for `km`.
+    pub fn scale(&self) -> &Number {
+        &self.scale
+    }
+
+    /// What the unit measures, as powers of the base dimensions.
+    pub fn dim(&self) -> Dimension {
+        self.dim
+    }
+
+    /// Whether the unit is dimensionless, like `m/km`.
+    pub fn is_none(&self) -> bool {
+        self.dim == NONE
+    }
+
+    /// The product, `None` if an exponent gets out of range.
+    pub fn mul(&self, other: &Unit) -> Option<Unit> {
+        let mut factors = self.factors.clone();
+        for (name, p) in &other.factors {
+            match factors.iter_mut().find(|(n, _)| n == name) {
+                Some((_, q)) => *q = q.checked_add(*p)?,
+                None => factors.push((name.clone(), *p)),
+            }
+        }
+        factors.retain(|&(_, p)| p != 0);
+        let mut dim = self.dim;
+        for (d, e) in dim.iter_mut().zip(&other.dim) {
+            *d = d.checked_add(*e)?;
+        }
+        Some(Unit {
+            factors,
+            scale: self.scale.mul(&other.scale),
+            dim,
+        })
+    }
+
+    /// The quotient, `None` if an exponent gets out of range.
+    pub fn div(&self, other: &Unit) -> Option<Unit> {
+        self.mul(&other.pow(-1)?)
+    }
+
+    /// `self^n`, `None` if an exponent gets out of range.
+    pub fn pow(&self, n: i64) -> Option<Unit> {
+        let n = n.to_i8()?;
+        let mut factors = Vec::with_capacity(self.factors.len());
+        for (name, p) in &self.factors {
+            factors.push((name.clone(), p.checked_mul(n)?));
+        }
+        factors.retain(|&(_, p)| p != 0);
+        let mut dim = self.dim;
+        for d in dim.iter_mut() {
+            *d = d.checked_mul(n)?;
+        }
+        Some(Unit {
+            factors,
+            scale: self.scale.pow(&Number::from(i64::from(n)), None),
+            dim,
+        })
+    }
+
+    /// The unit for error messages, "no unit" for plain numbers.
+    pub fn describe(&self) -> String {
+        if self.factors.is_empty() {
+            "no unit".to_owned()
+        } else {
+            self.to_string()
+        }
+    }
+}
+
+/// Scale and dimension of the unit called exactly `name`.
+fn find(name: &str) -> Option<(Number, Dimension)> {
+    let def = UNITS.iter().find(|u| u.names.contains(&name))?;
+    Some((parse_scale(def.scale), def.dim))
+}
+
+/// Writes `name` or `name^p`.
+fn fmt_factor(f: &mut fmt::Formatter, name: &str, p: i16) -> fmt::Result {
+    if p == 1 {
+        write!(f, "{}", name)
+    } else {
+        write!(f, "{}^{}", name, p)
+    }
+}
+
+impl fmt::Display for Unit {
+    /// `kg*m/s^2`, or `s^-1` without a numerator.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let num: Vec<_> = self.factors.iter().filter(|(_, p)| *p > 0).collect();
+        if num.is_empty() {
+            let parts: Vec<_> = self
+                .factors
+                .iter()
+                .map(|(n, p)| format!("{}^{}", n, p))
+                .collect();
+            return write!(f, "{}", parts.join("*"));
+        }
+        for (i, (name, p)) in num.iter().enumerate() {
+            if i > 0 {
+                write!(f, "*")?;
+            }
+            fmt_factor(f, name, i16::from(*p))?;
+        }
+        for (name, p) in self.factors.iter().filter(|(_, p)| *p < 0) {
+            write!(f, "/")?;
+            fmt_factor(f, name, -i16::from(*p))?;
+        }
+        Ok(())
+    }
+}
+
+/// A number of some unit, like `5 km/h`. The value is kept in the unit it
+/// was written in and only converted when needed, so `3 ft + 2 in` is in
+/// feet.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Quantity {
+    /// The amount, in `unit`.
+    pub value: Number,
+    /// The unit the amount is in.
+    pub unit: Unit,
+}
+
+impl Quantity {
+    /// `value` in `unit`.
+    pub fn new(value: Number, unit: Unit) -> Self {
+        Quantity { value, unit }
+    }
+
+    /// The value in SI base units.
+    pub fn si_value(&self) -> Number {
+        self.value.mul(&self.unit.scale)
+    }
+
+    /// `self` in `unit`, `None` if the dimensions differ.
+    pub fn convert(&self, unit: &Unit) -> Option<Quantity> {
+        if self.unit.dim != unit.dim {
+            return None;
+        }
+        let value = self.si_value().div(&unit.scale);
+        Some(Quantity::new(value, unit.clone()))
+    }
+
+    /// A quantity, or a plain number if the units cancel out, as in
+    /// `1 km / 1 m`.
+    pub fn into_value(self) -> Value {
+        if self.unit.is_none() {
+            Value::Number(self.si_value())
+        } else {
+            Value::Quantity(Box::new(self))
+        }
+    }
+
+    /// `self` formatted with `part` formatting the number, as `5 km/h`.
+    pub fn to_string_with(&self, part: impl Fn(&Number) -> String) -> String {
+        let value = part(&self.value);
+        if self.value.is_complex() {
+            format!("({}) {}", value, self.unit)
+        } else {
+            format!("{} {}", value, self.unit)
+        }
+    }
+}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_string_with(Number::to_string))
+    }
+}
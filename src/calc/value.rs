@@ -0,0 +1,132 @@
+use crate::calc::{matrix, Matrix, Number, Quantity};
+use std::fmt;
+
+/// Result of evaluating an expression.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    /// A scalar, real or complex.
+    Number(Number),
+    /// The result of a comparison or logical operator.
+    Bool(bool),
+    /// `[1, 2, 3]`
+    Vector(Vec<Number>),
+    /// `[[1, 2], [3, 4]]`
+    Matrix(Matrix),
+    /// A number with a unit. Never dimensionless, those are plain numbers.
+    Quantity(Box<Quantity>),
+}
+
+impl Value {
+    /// Article and name of the value's type, for error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Number(Number::Complex(_)) => "a complex number",
+            Value::Number(_) => "a number",
+            Value::Bool(_) => "a boolean",
+            Value::Vector(_) => "a vector",
+            Value::Matrix(_) => "a matrix",
+            Value::Quantity(_) => "a quantity",
+        }
+    }
+
+    /// Like `type_name`, with the size of vectors and matrices, as in
+    /// "a 2x3 matrix".
+    pub fn shape_name(&self) -> String {
+        match self {
+            Value::Vector(v) => format!("a {}-vector", v.len()),
+            Value::Matrix(m) => format!("a {}x{} matrix", m.rows(), m.cols()),
+            v => v.type_name().to_owned(),
+        }
+    }
+
+    /// A number as the nearest `f64`, `None` for other values.
+    pub fn to_f64(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(n.to_f64()),
+            _ => None,
+        }
+    }
+
+    /// The numbers a value is made of.
+    pub fn numbers(&self) -> &[Number] {
+        match self {
+            Value::Number(n) => std::slice::from_ref(n),
+            Value::Bool(_) => &[],
+            Value::Vector(v) => v,
+            Value::Matrix(m) => m.data(),
+            Value::Quantity(q) => std::slice::from_ref(&q.value),
+        }
+    }
+
+    /// `f` applied to every number, `None` for booleans. Quantities keep
+    /// their unit.
+    pub fn map(&self, f: impl Fn(&Number) -> Number) -> Option<Value> {
+        match self {
+            Value::Number(n) => Some(Value::Number(f(n))),
+            Value::Bool(_) => None,
+            Value::Vector(v) => Some(Value::Vector(v.iter().map(f).collect())),
+            Value::Matrix(m) => Some(Value::Matrix(m.map(f))),
+            Value::Quantity(q) => Some(Value::Quantity(Box::new(Quantity::new(
+                f(&q.value),
+                q.unit.clone(),
+            )))),
+        }
+    }
+
+    /// `f` applied element-wise to vectors or matrices of the same shape,
+    /// or to a number and each element of the other operand. `None` for
+    /// other combinations, including quantities.
+    pub fn zip_with(&self, other: &Value, f: impl Fn(&Number, &Number) -> Number) -> Option<Value> {
+        match (self, other) {
+            (Value::Quantity(_), _) | (_, Value::Quantity(_)) => None,
+            (Value::Number(a), b) => b.map(|b| f(a, b)),
+            (a, Value::Number(b)) => a.map(|a| f(a, b)),
+            (Value::Vector(a), Value::Vector(b)) if a.len() == b.len() => Some(Value::Vector(
+                a.iter().zip(b).map(|(a, b)| f(a, b)).collect(),
+            )),
+            (Value::Matrix(a), Value::Matrix(b)) => a.zip_with(b, f).map(Value::Matrix),
+            _ => None,
+        }
+    }
+
+    /// `self` formatted with `part` formatting the numbers in it.
+    pub fn to_string_with(&self, part: impl Fn(&Number) -> String) -> String {
+        match self {
+            Value::Number(n) => part(n),
+            Value::Bool(b) => b.to_string(),
+            Value::Vector(v) => matrix::vector_string(v, part),
+            Value::Matrix(m) => m.to_string_with(part),
+            Value::Quantity(q) => q.to_string_with(part),
+        }
+    }
+}
+
+impl From<Number> for Value {
+    fn from(n: Number) -> Self {
+        Value::Number(n)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(v: f64) -> Self {
+        Value::Number(Number::Float(v))
+    }
+}
+
+impl From<i64> for Value {
+    fn from(i: i64) -> Self {
+        Value::Number(Number::from(i))
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_string_with(Number::to_string))
+    }
+}
@@ -0,0 +1,641 @@
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use termion::event::Key;
+use termion::input::TermRead;
+use termion::raw::IntoRawMode;
+use termion::{clear, cursor};
+
+/// Most lines kept in the history.
+const MAX_HISTORY: usize = 1000;
+
+/// What the editor needs to know about the language being typed.
+pub trait Helper {
+    /// Names starting with `word`, sorted, for Tab to complete. `first` if
+    /// the word starts the line, where commands may go.
+    fn complete(&self, word: &str, first: bool) -> Vec<String>;
+
+    /// A hint shown beneath the line once Tab completes `name`, such as the
+    /// signature of a function.
+    fn hint(&self, name: &str) -> Option<String>;
+
+    /// `line` as shown while it is edited, which may add escape codes but
+    /// no other text.
+    fn highlight(&self, line: &str) -> String;
+
+    /// A line shown beneath `line` while it is edited, such as its value.
+    fn preview(&mut self, line: &str) -> Option<String>;
+}
+
+/// Reads lines from the terminal with cursor movement, a history browsed
+/// with up and down and searched with Ctrl-R, saved across sessions.
+pub struct Editor {
+    history: Vec<String>,
+    /// File the history is saved to, `None` if there is nowhere to save it.
+    path: Option<PathBuf>,
+    /// Rows the cursor is below the prompt, to redraw lines that wrap.
+    row: usize,
+}
+
+/// The text being edited and the cursor in it.
+#[derive(Default)]
+struct Line {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Line {
+    fn set(&mut self, text: &str) {
+        self.chars = text.chars().collect();
+        self.pos = self.chars.len();
+    }
+
+    fn text(&self) -> String {
+        self.chars.iter().collect()
+    }
+
+    fn insert(&mut self, c: char) {
+        self.chars.insert(self.pos, c);
+        self.pos += 1;
+    }
+
+    fn backspace(&mut self) {
+        if self.pos > 0 {
+            self.pos -= 1;
+            self.chars.remove(self.pos);
+        }
+    }
+
+    fn delete(&mut self) {
+        if self.pos < self.chars.len() {
+            self.chars.remove(self.pos);
+        }
+    }
+
+    fn left(&mut self) {
+        self.pos = self.pos.saturating_sub(1);
+    }
+
+    fn right(&mut self) {
+        self.pos = (self.pos + 1).min(self.chars.len());
+    }
+
+    /// Deletes the word before the cursor and the spaces after it, like
+    /// Ctrl-W in a shell.
+    fn delete_word(&mut self) {
+        let mut start = self.pos;
+        while start > 0 && self.chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        while start > 0 && !self.chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        self.chars.drain(start..self.pos);
+        self.pos = start;
+    }
+
+    fn kill_start(&mut self) {
+        self.chars.drain(..self.pos);
+        self.pos = 0;
+    }
+
+    fn kill_end(&mut self) {
+        self.chars.truncate(self.pos);
+    }
+
+    /// Where the name before the cursor starts, including the `:` of a
+    /// command at the start of the line.
+    fn word_start(&self) -> usize {
+        let mut start = self.pos;
+        while start > 0 && (self.chars[start - 1].is_alphanumeric() || self.chars[start - 1] == '_')
+        {
+            start -= 1;
+        }
+        if start > 0
+            && self.chars[start - 1] == ':'
+            && self.chars[..start - 1].iter().all(|c| c.is_whitespace())
+        {
+            start -= 1;
+        }
+        start
+    }
+
+    /// Replaces the text from `start` to the cursor with `text`.
+    fn replace(&mut self, start: usize, text: &str) {
+        self.chars.splice(start..self.pos, text.chars());
+        self.pos = start + text.chars().count();
+    }
+}
+
+/// A Ctrl-R search through the history, newest first.
+struct Search {
+    query: String,
+    /// The history entry found, `None` if nothing matches.
+    found: Option<usize>,
+    /// The line before the search, restored if it is cancelled.
+    original: String,
+}
+
+impl Editor {
+    /// An editor with the history saved by earlier sessions.
+    pub fn new() -> Self {
+        let path = history_path();
+        let mut history: Vec<String> = path
+            .as_ref()
+            .and_then(|p| fs::File::open(p).ok())
+            .map(|f| BufReader::new(f).lines().map_while(Result::ok).collect())
+            .unwrap_or_default();
+        if history.len() > MAX_HISTORY {
+            history.drain(..history.len() - MAX_HISTORY);
+            if let Some(path) = &path {
+                let _ = fs::write(path, history.join("\n") + "\n");
+            }
+        }
+        Editor {
+            history,
+            path,
+            row: 0,
+        }
+    }
+
+    /// Shows `prompt`, which may contain escape codes, and reads a line.
+    /// `None` at the end of input or on Ctrl-D, an empty line on Ctrl-C.
+    pub fn read_line(
+        &mut self,
+        prompt: &str,
+        helper: &mut dyn Helper,
+    ) -> io::Result<Option<String>> {
+        let stdin = io::stdin();
+        let mut out = io::stdout().into_raw_mode()?;
+        let mut line = Line::default();
+        // The entry shown while browsing with up and down, the new line
+        // being `history.len()`, whose text is kept in `draft`.
+        let mut index = self.history.len();
+        let mut draft = String::new();
+        let mut search: Option<Search> = None;
+        // Lines shown beneath the input until the next key, such as the
+        // names Tab could complete.
+        let mut below = Vec::new();
+        self.row = 0;
+        self.draw(&mut out, prompt, &line, search.as_ref(), &below, helper)?;
+
+        for key in stdin.lock().keys() {
+            let key = key?;
+            below.clear();
+            if let Some(s) = &mut search {
+                let mut steered = true;
+                match key {
+                    Key::Ctrl('r') => {
+                        let before = s.found.unwrap_or(self.history.len());
+                        if let Some(i) = self.find(&s.query, before) {
+                            s.found = Some(i);
+                        }
+                    }
+                    Key::Char(c) if c != '\n' && c != '\t' => {
+                        s.query.push(c);
+                        let before = s.found.map_or(self.history.len(), |i| i + 1);
+                        s.found = self.find(&s.query, before);
+                    }
+                    Key::Backspace => {
+                        s.query.pop();
+                        s.found = self.find(&s.query, self.history.len());
+                    }
+                    Key::Ctrl('g') | Key::Esc => {
+                        line.set(&s.original);
+                        search = None;
+                    }
+                    // Anything else ends the search with the entry found,
+                    // which Enter runs and other keys edit.
+                    _ => {
+                        if let Some(i) = s.found {
+                            line.set(&self.history[i]);
+                            index = i;
+                        }
+                        search = None;
+                        steered = false;
+                    }
+                }
+                if steered {
+                    self.draw(&mut out, prompt, &line, search.as_ref(), &below, helper)?;
+                    continue;
+                }
+            }
+
+            match key {
+                Key::Char('\n') => {
+                    self.leave(&mut out, prompt, &mut line, "", helper)?;
+                    let text = line.text();
+                    self.add_history(&text);
+                    return Ok(Some(text));
+                }
+                Key::Ctrl('c') => {
+                    self.leave(&mut out, prompt, &mut line, "^C", helper)?;
+                    return Ok(Some(String::new()));
+                }
+                Key::Ctrl('d') if line.chars.is_empty() => return Ok(None),
+                Key::Ctrl('r') => {
+                    search = Some(Search {
+                        query: String::new(),
+                        found: None,
+                        original: line.text(),
+                    });
+                }
+                Key::Ctrl('l') => {
+                    write!(out, "{}{}", clear::All, cursor::Goto(1, 1))?;
+                    self.row = 0;
+                }
+                Key::Char('\t') => below = complete(&mut line, &*helper),
+                key => self.edit(&mut line, key, &mut index, &mut draft),
+            }
+            self.draw(&mut out, prompt, &line, search.as_ref(), &below, helper)?;
+        }
+
+        Ok(None)
+    }
+
+    /// Draws `line` a last time followed by `mark`, without the lines
+    /// beneath it, and moves to the next line for the output.
+    fn leave<W: Write>(
+        &mut self,
+        out: &mut W,
+        prompt: &str,
+        line: &mut Line,
+        mark: &str,
+        helper: &mut dyn Helper,
+    ) -> io::Result<()> {
+        line.pos = line.chars.len();
+        self.draw(out, prompt, line, None, &[], helper)?;
+        write!(out, "{}\r\n{}", mark, clear::AfterCursor)?;
+        out.flush()
+    }
+
+    /// Applies an editing or history key to `line`.
+    fn edit(&self, line: &mut Line, key: Key, index: &mut usize, draft: &mut String) {
+        match key {
+            Key::Char(c) => line.insert(c),
+            Key::Backspace | Key::Ctrl('h') => line.backspace(),
+            Key::Delete | Key::Ctrl('d') => line.delete(),
+            Key::Left | Key::Ctrl('b') => line.left(),
+            Key::Right | Key::Ctrl('f') => line.right(),
+            Key::Home | Key::Ctrl('a') => line.pos = 0,
+            Key::End | Key::Ctrl('e') => line.pos = line.chars.len(),
+            Key::Ctrl('w') => line.delete_word(),
+            Key::Ctrl('u') => line.kill_start(),
+            Key::Ctrl('k') => line.kill_end(),
+            Key::Up | Key::Ctrl('p') if *index > 0 => {
+                if *index == self.history.len() {
+                    *draft = line.text();
+                }
+                *index -= 1;
+                line.set(&self.history[*index]);
+            }
+            Key::Down | Key::Ctrl('n') if *index < self.history.len() => {
+                *index += 1;
+                match self.history.get(*index) {
+                    Some(entry) => line.set(entry),
+                    None => line.set(draft),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The newest history entry before `before` containing `query`.
+    fn find(&self, query: &str, before: usize) -> Option<usize> {
+        self.history[..before.min(self.history.len())]
+            .iter()
+            .rposition(|entry| entry.contains(query))
+    }
+
+    /// Redraws the prompt and line, or the search, over the previous
+    /// drawing, wrapping at the edge of the terminal. The line is shown as
+    /// `helper` highlights it, with its preview and the lines in `below`
+    /// beneath it.
+    fn draw<W: Write>(
+        &mut self,
+        out: &mut W,
+        prompt: &str,
+        line: &Line,
+        search: Option<&Search>,
+        below: &[String],
+        helper: &mut dyn Helper,
+    ) -> io::Result<()> {
+        let (prompt, text, pos) = match search {
+            Some(s) => {
+                let found = s.found.map_or("", |i| self.history[i].as_str());
+                let failing = if s.found.is_none() && !s.query.is_empty() {
+                    "failing "
+                } else {
+                    ""
+                };
+                let prompt = format!("({}reverse-i-search)`{}': ", failing, s.query);
+                (prompt, found.to_owned(), found.chars().count())
+            }
+            None => (prompt.to_owned(), line.text(), line.pos),
+        };
+        let (shown, preview) = match search {
+            Some(_) => (text.clone(), None),
+            None => (helper.highlight(&text), helper.preview(&text)),
+        };
+        let width = terminal_width();
+
+        if self.row > 0 {
+            write!(out, "{}", cursor::Up(self.row as u16))?;
+        }
+        write!(out, "\r{}{}{}", clear::AfterCursor, prompt, shown)?;
+        let start = visible_width(&prompt);
+        let end = start + text.chars().count();
+        let at = start + pos;
+        // Terminals leave the cursor on the last column after filling a
+        // row, so move it to the next one by hand.
+        if end > 0 && end % width == 0 {
+            write!(out, "\r\n")?;
+        }
+        let (row, mut end_row) = (at / width, end / width);
+        for l in preview.iter().chain(below) {
+            write!(out, "\r\n{}", l)?;
+            // A full last row leaves the cursor on it, as above.
+            end_row += visible_width(l).saturating_sub(1) / width + 1;
+        }
+        if end_row > row {
+            write!(out, "{}", cursor::Up((end_row - row) as u16))?;
+        }
+        write!(out, "\r")?;
+        let column = at % width;
+        if column > 0 {
+            write!(out, "{}", cursor::Right(column as u16))?;
+        }
+        self.row = row;
+        out.flush()
+    }
+
+    /// Remembers `line` in the history and appends it to the history file,
+    /// unless it is empty or repeats the last entry.
+    fn add_history(&mut self, line: &str) {
+        if line.trim().is_empty() || self.history.last().is_some_and(|l| l == line) {
+            return;
+        }
+        self.history.push(line.to_owned());
+        if self.history.len() > MAX_HISTORY {
+            self.history.remove(0);
+        }
+        // The history is a convenience, failing to save it is no error.
+        if let Some(path) = &self.path {
+            if let Some(dir) = path.parent() {
+                let _ = fs::create_dir_all(dir);
+            }
+            let file = OpenOptions::new().create(true).append(true).open(path);
+            if let Ok(mut file) = file {
+                let _ = writeln!(file, "{}", line);
+            }
+        }
+    }
+}
+
+/// Completes the name before the cursor as far as the names `helper` knows
+/// agree, returning the lines to show beneath: the hint for a single
+/// match, or the matches when there are several and nothing to add.
+fn complete(line: &mut Line, helper: &dyn Helper) -> Vec<String> {
+    let start = line.word_start();
+    let word: String = line.chars[start..line.pos].iter().collect();
+    if word.is_empty() {
+        return Vec::new();
+    }
+    let names = helper.complete(&word, start == 0 || word.starts_with(':'));
+    match names.as_slice() {
+        [] => Vec::new(),
+        [name] => {
+            line.replace(start, name);
+            helper.hint(name).into_iter().collect()
+        }
+        [first, rest @ ..] => {
+            let common = rest.iter().fold(first.as_str(), |common, name| {
+                let len = common
+                    .char_indices()
+                    .zip(name.chars())
+                    .find(|((_, a), b)| a != b)
+                    .map_or(common.len().min(name.len()), |((i, _), _)| i);
+                &common[..len]
+            });
+            if common.len() > word.len() {
+                line.replace(start, common);
+                Vec::new()
+            } else {
+                columns(&names, terminal_width())
+            }
+        }
+    }
+}
+
+/// `names` in as many columns as fit `width`.
+fn columns(names: &[String], width: usize) -> Vec<String> {
+    let column = names.iter().map(|n| n.chars().count()).max().unwrap_or(0) + 2;
+    let per_row = (width.saturating_sub(1) / column).max(1);
+    names
+        .chunks(per_row)
+        .map(|row| {
+            let row: Vec<String> = row.iter().map(|n| format!("{:<1$}", n, column)).collect();
+            row.concat().trim_end().to_owned()
+        })
+        .collect()
+}
+
+/// Columns of the terminal, 80 if it does not say.
+fn terminal_width() -> usize {
+    termion::terminal_size().map_or(80, |(w, _)| usize::from(w.max(1)))
+}
+
+/// `$XDG_DATA_HOME/calc/history`, by default `~/.local/share/calc/history`.
+fn history_path() -> Option<PathBuf> {
+    let data = env::var_os("XDG_DATA_HOME")
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".local/share")))?;
+    Some(data.join("calc").join("history"))
+}
+
+/// Columns `s` takes up on the terminal, not counting escape codes.
+fn visible_width(s: &str) -> usize {
+    let mut width = 0;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Skip a control sequence up to its final byte.
+            if chars.next() == Some('[') {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+        } else {
+            width += 1;
+        }
+    }
+    width
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Completes from a fixed list of names, with a hint for each.
+    struct Names(&'static [&'static str]);
+
+    impl Helper for Names {
+        fn complete(&self, word: &str, first: bool) -> Vec<String> {
+            self.0
+                .iter()
+                .filter(|n| n.starts_with(word) && (first || !n.starts_with(':')))
+                .map(|n| n.to_string())
+                .collect()
+        }
+
+        fn hint(&self, name: &str) -> Option<String> {
+            Some(format!("{}(x)", name))
+        }
+
+        fn highlight(&self, line: &str) -> String {
+            line.to_owned()
+        }
+
+        fn preview(&mut self, _: &str) -> Option<String> {
+            None
+        }
+    }
+
+    fn line(text: &str, pos: usize) -> Line {
+        Line {
+            chars: text.chars().collect(),
+            pos,
+        }
+    }
+
+    #[test]
+    fn editing() {
+        let mut l = Line::default();
+        for c in "1+3".chars() {
+            l.insert(c);
+        }
+        l.left();
+        l.backspace();
+        l.insert('*');
+        assert_eq!((l.text(), l.pos), ("1*3".to_owned(), 2));
+        l.delete();
+        l.delete();
+        assert_eq!((l.text(), l.pos), ("1*".to_owned(), 2));
+        l.right();
+        assert_eq!(l.pos, 2);
+        for _ in 0..3 {
+            l.left();
+        }
+        l.backspace();
+        assert_eq!((l.text(), l.pos), ("1*".to_owned(), 0));
+
+        let mut l = line("λ = 2π", 4);
+        l.kill_end();
+        assert_eq!((l.text(), l.pos), ("λ = ".to_owned(), 4));
+        l.set("sin(x) + 1");
+        assert_eq!(l.pos, 10);
+        l.pos = 4;
+        l.kill_start();
+        assert_eq!((l.text(), l.pos), ("x) + 1".to_owned(), 0));
+    }
+
+    #[test]
+    fn delete_word() {
+        let mut l = line("a = max(x,  y)  ", 16);
+        l.delete_word();
+        assert_eq!((l.text(), l.pos), ("a = max(x,  ".to_owned(), 12));
+        l.delete_word();
+        assert_eq!(l.text(), "a = ");
+        l.pos = 2;
+        l.delete_word();
+        assert_eq!((l.text(), l.pos), ("= ".to_owned(), 0));
+        l.delete_word();
+        assert_eq!(l.text(), "= ");
+    }
+
+    #[test]
+    fn word_start() {
+        assert_eq!(line("2 * sq", 6).word_start(), 4);
+        assert_eq!(line("f(my_var", 8).word_start(), 2);
+        assert_eq!(line("  :form", 7).word_start(), 2);
+        assert_eq!(line("x :form", 7).word_start(), 3);
+        assert_eq!(line("sin(x)", 3).word_start(), 0);
+    }
+
+    #[test]
+    fn completion() {
+        let names = Names(&["ceil", "cos", "cosh", "count", "tan", "tanh", ":exact"]);
+
+        // The one match, with its hint.
+        let mut l = line("1 + cei", 7);
+        assert_eq!(complete(&mut l, &names), vec!["ceil(x)"]);
+        assert_eq!((l.text(), l.pos), ("1 + ceil".to_owned(), 8));
+
+        // As far as the matches agree.
+        let mut l = line("cos(ta + 1)", 6);
+        assert!(complete(&mut l, &names).is_empty());
+        assert_eq!((l.text(), l.pos), ("cos(tan + 1)".to_owned(), 7));
+        let mut l = line("cos", 3);
+        assert_eq!(complete(&mut l, &names), vec!["cos   cosh"]);
+        let mut l = line("cou", 3);
+        complete(&mut l, &names);
+        assert_eq!(l.text(), "count");
+        let mut l = line("c", 1);
+        assert_eq!(complete(&mut l, &names), vec!["ceil   cos    cosh   count"]);
+        assert_eq!(l.text(), "c");
+
+        // Commands only at the start of the line.
+        let mut l = line(":ex", 3);
+        complete(&mut l, &names);
+        assert_eq!(l.text(), ":exact");
+        let mut l = line("x", 1);
+        assert!(complete(&mut l, &names).is_empty());
+        let mut l = line("1 + ", 4);
+        assert!(complete(&mut l, &names).is_empty());
+    }
+
+    #[test]
+    fn layout() {
+        let names: Vec<String> = ["a", "bb", "ccc", "dddd", "e"]
+            .iter()
+            .map(|n| n.to_string())
+            .collect();
+        assert_eq!(columns(&names, 80), vec!["a     bb    ccc   dddd  e"]);
+        assert_eq!(columns(&names, 19), vec!["a     bb    ccc", "dddd  e"]);
+        assert_eq!(columns(&names, 3), vec!["a", "bb", "ccc", "dddd", "e"]);
+
+        assert_eq!(visible_width("λ 1+2"), 5);
+        assert_eq!(visible_width("\x1b[1mrad\x1b[0m λ "), 6);
+        assert_eq!(visible_width("\x1b[38;5;1mx\x1b[39m"), 1);
+    }
+
+    #[test]
+    fn history() {
+        let mut editor = Editor {
+            history: Vec::new(),
+            path: None,
+            row: 0,
+        };
+        editor.add_history("1 + 1");
+        editor.add_history("1 + 1");
+        editor.add_history("  ");
+        editor.add_history("x = 2");
+        assert_eq!(editor.history, vec!["1 + 1", "x = 2"]);
+        assert_eq!(editor.find("1", 2), Some(0));
+        assert_eq!(editor.find("x", 1), None);
+
+        for i in 0..MAX_HISTORY {
+            editor.add_history(&i.to_string());
+        }
+        assert_eq!(editor.history.len(), MAX_HISTORY);
+        assert_eq!(editor.history[0], "0");
+        assert_eq!(
+            editor.history[MAX_HISTORY - 1],
+            (MAX_HISTORY - 1).to_string()
+        );
+    }
+}
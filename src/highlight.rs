@@ -0,0 +1,223 @@
+use calc::{Calculator, Format, Scanner, Token, TokenType};
+use std::collections::HashSet;
+use termion::color::{self, Fg};
+
+/// `line` colored by its tokens for the terminal: numbers, operators, names
+/// the calculator knows and those it does not, and brackets without a
+/// partner, so that mistakes show before the line is run. Comments are
+/// dimmed.
+pub fn highlight(calc: &Calculator, line: &str) -> String {
+    let chars: Vec<char> = line.chars().collect();
+    let tokens: Vec<Token> = Scanner::new(line).collect();
+    let unmatched = unmatched(&tokens);
+    let defined = defined(&tokens);
+
+    let mut out = String::new();
+    let mut end = 0;
+    for (i, t) in tokens.iter().enumerate() {
+        for c in &t.trivia {
+            out.extend(&chars[end..c.span.start]);
+            let text: String = chars[c.span.start..c.span.end].iter().collect();
+            out.push_str(&format!(
+                "{}{}{}",
+                Fg(color::LightBlack),
+                text,
+                Fg(color::Reset)
+            ));
+            end = c.span.end;
+        }
+        out.extend(&chars[end..t.span.start]);
+        let text: String = chars[t.span.start..t.span.end].iter().collect();
+        end = t.span.end;
+        let converting = i > 0 && matches!(tokens[i - 1].lexeme.as_str(), "to" | "in");
+        let c: Option<&dyn color::Color> = match t.token_type {
+            TokenType::Integer
+            | TokenType::Float
+            | TokenType::Exponent
+            | TokenType::Imaginary
+            | TokenType::HexInteger
+            | TokenType::OctalInteger
+            | TokenType::BinaryInteger
+            | TokenType::HexFloat => Some(&color::Cyan),
+            TokenType::Identifier => {
+                let name = t.lexeme.as_str();
+                if matches!(name, "to" | "in" | "xor") && i > 0 {
+                    Some(&color::Yellow)
+                } else if name == "if" || calc.signature(name).is_some() {
+                    Some(&color::Blue)
+                } else if calc.is_defined(name)
+                    || defined.contains(name)
+                    || converting && Format::from_name(name).is_some()
+                {
+                    Some(&color::Magenta)
+                } else {
+                    Some(&color::Red)
+                }
+            }
+            TokenType::OpenParen
+            | TokenType::CloseParen
+            | TokenType::OpenBracket
+            | TokenType::CloseBracket => {
+                if unmatched.contains(&i) {
+                    Some(&color::Red)
+                } else {
+                    None
+                }
+            }
+            TokenType::Comma | TokenType::Separator | TokenType::EOL => None,
+            TokenType::Error => Some(&color::Red),
+            _ => Some(&color::Yellow),
+        };
+        match c {
+            Some(c) => out.push_str(&format!("{}{}{}", Fg(c), text, Fg(color::Reset))),
+            None => out.push_str(&text),
+        }
+    }
+    out.extend(&chars[end.min(chars.len())..]);
+    out
+}
+
+/// Indices of the brackets in `tokens` that are not closed, or close
+/// nothing or a bracket of the other kind.
+fn unmatched(tokens: &[Token]) -> HashSet<usize> {
+    let mut unmatched = HashSet::new();
+    let mut open: Vec<usize> = Vec::new();
+    for (i, t) in tokens.iter().enumerate() {
+        let opener = match t.token_type {
+            TokenType::OpenParen | TokenType::OpenBracket => {
+                open.push(i);
+                continue;
+            }
+            TokenType::CloseParen => TokenType::OpenParen,
+            TokenType::CloseBracket => TokenType::OpenBracket,
+            _ => continue,
+        };
+        match open.last() {
+            Some(&j) if tokens[j].token_type == opener => {
+                open.pop();
+            }
+            _ => {
+                unmatched.insert(i);
+            }
+        }
+    }
+    unmatched.extend(open);
+    unmatched
+}
+
+/// The names the statements in a line define, which are not yet known: the
+/// variable in `x = ...`, or the function and its parameters in
+/// `f(x, y) = ...`.
+fn defined(tokens: &[Token]) -> HashSet<&str> {
+    tokens
+        .split(|t| t.token_type == TokenType::Separator)
+        .flat_map(|stmt| {
+            let is = |i: usize, tt| stmt.get(i).is_some_and(|t: &Token| t.token_type == tt);
+            let close = stmt
+                .iter()
+                .position(|t| t.token_type == TokenType::CloseParen);
+            let end = match close {
+                _ if is(0, TokenType::Identifier) && is(1, TokenType::Equal) => 1,
+                Some(close)
+                    if is(0, TokenType::Identifier)
+                        && is(1, TokenType::OpenParen)
+                        && is(close + 1, TokenType::Equal) =>
+                {
+                    close
+                }
+                _ => 0,
+            };
+            stmt[..end]
+                .iter()
+                .filter(|t| t.token_type == TokenType::Identifier)
+                .map(|t| t.lexeme.as_str())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn unmatched_in(line: &str) -> Vec<String> {
+        let tokens: Vec<Token> = Scanner::new(line).collect();
+        let mut found: Vec<usize> = unmatched(&tokens).into_iter().collect();
+        found.sort();
+        found
+            .iter()
+            .map(|&i| format!("{}@{}", tokens[i].lexeme, tokens[i].span.start))
+            .collect()
+    }
+
+    fn defined_in(line: &str) -> Vec<String> {
+        let tokens: Vec<Token> = Scanner::new(line).collect();
+        let mut names: Vec<String> = defined(&tokens).into_iter().map(str::to_owned).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn brackets() {
+        assert!(unmatched_in("max([1, (2)], f(g(3)))").is_empty());
+        assert_eq!(unmatched_in("(1 + [2)"), vec!["(@0", "[@5", ")@7"]);
+        assert_eq!(unmatched_in("((1 + 2)"), vec!["(@0"]);
+        assert_eq!(unmatched_in("1) + (2"), vec![")@1", "(@5"]);
+        assert_eq!(unmatched_in("[1, 2)]"), vec![")@5"]);
+        assert_eq!(unmatched_in("]["), vec!["]@0", "[@1"]);
+    }
+
+    #[test]
+    fn definitions() {
+        assert_eq!(defined_in("x = y + 1"), vec!["x"]);
+        assert_eq!(defined_in("f(a, b) = a * b + c"), vec!["a", "b", "f"]);
+        assert_eq!(defined_in("a = 1; g(t) = t; a + z"), vec!["a", "g", "t"]);
+        assert!(defined_in("f(a) + 1").is_empty());
+        assert!(defined_in("x == 1").is_empty());
+    }
+
+    #[test]
+    fn colors() {
+        let mut calc = Calculator::new();
+        calc.run("known = 1").unwrap();
+        let paint =
+            |c: &dyn color::Color, text: &str| format!("{}{}{}", Fg(c), text, Fg(color::Reset));
+
+        assert_eq!(
+            highlight(&calc, "known + y"),
+            format!(
+                "{} {} {}",
+                paint(&color::Magenta, "known"),
+                paint(&color::Yellow, "+"),
+                paint(&color::Red, "y")
+            )
+        );
+        assert_eq!(
+            highlight(&calc, "y = sin(y)"),
+            format!(
+                "{} {} {}({})",
+                paint(&color::Magenta, "y"),
+                paint(&color::Yellow, "="),
+                paint(&color::Blue, "sin"),
+                paint(&color::Magenta, "y")
+            )
+        );
+        assert_eq!(
+            highlight(&calc, "(2 # two"),
+            format!(
+                "{}{} {}",
+                paint(&color::Red, "("),
+                paint(&color::Cyan, "2"),
+                paint(&color::LightBlack, "# two")
+            )
+        );
+        assert_eq!(
+            highlight(&calc, "255 to hex"),
+            format!(
+                "{} {} {}",
+                paint(&color::Cyan, "255"),
+                paint(&color::Yellow, "to"),
+                paint(&color::Magenta, "hex")
+            )
+        );
+    }
+}
@@ -0,0 +1,24 @@
+//! A simple calculator: a scanner, a recursive-descent parser producing an
+//! expression tree, and a `Calculator` that evaluates it while keeping track
+//! of variables, constants and built-in functions.
+//!
+//! ```
+//! use calc::{Calculator, Value};
+//!
+//! let mut calc = Calculator::new();
+//! assert_eq!(calc.run("a = 2^10").unwrap(), "a = 1024");
+//!
+//! // Parse once, evaluate as often as needed.
+//! let stmt = calc.parse("a / 4").unwrap().unwrap();
+//! assert_eq!(stmt.to_string(), "a / 4");
+//! assert_eq!(calc.exec(&stmt).unwrap(), Some(Value::from(256)));
+//!
+//! calc.run("f(x) = x^2 + 1").unwrap();
+//! assert_eq!(calc.run("f(a)").unwrap(), "1048577");
+//! ```
+
+#![warn(missing_docs)]
+
+mod calc;
+
+pub use crate::calc::*;
@@ -1,41 +1,261 @@
-mod calc;
+mod editor;
+mod highlight;
 
-use calc::Calculator;
-use std::io::{self, BufRead, Write};
-use termion;
+use calc::{AngleMode, Calculator, Format};
+use editor::{Editor, Helper};
+use std::io::{self, BufRead, BufReader, Write};
+use std::num::NonZeroU32;
+use std::{env, fmt, fs, process};
 use termion::{color, style};
 
+const USAGE: &str = "usage: calc              interactive session, or lines from a pipe
+       calc EXPRESSION   print the value of EXPRESSION
+       calc -f FILE      evaluate FILE line by line
+
+       --no-color        no colors in the interactive session, also with
+                         NO_COLOR set";
+
+/// Most significant digits `:precision` accepts, more gets slow.
+const MAX_PRECISION: u32 = 1000;
+
+/// Most bits `:word` accepts.
+const MAX_WORD_SIZE: u32 = 1024;
+
+/// What Tab completes at the start of a line, besides names.
+const COMMANDS: &[&str] = &[
+    "help",
+    "reset",
+    "exit",
+    ":implicit",
+    ":exact",
+    ":precision",
+    ":polar",
+    ":angle",
+    ":format",
+    ":word",
+    ":wrap",
+];
+
 fn main() -> Result<(), io::Error> {
-    let mut input = String::new();
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let colored = !args.iter().any(|a| a == "--no-color")
+        && env::var_os("NO_COLOR").map_or(true, |v| v.is_empty());
+    args.retain(|a| a != "--no-color");
+
+    // Anything but the options is an expression, including "-2 * 3".
+    let code = match args.first().map(String::as_str) {
+        None if termion::is_tty(&io::stdin()) && termion::is_tty(&io::stdout()) => {
+            return interactive(colored)
+        }
+        None => run_lines(
+            &mut io::stdout(),
+            &mut io::stderr(),
+            io::stdin().lock().lines(),
+            "<stdin>",
+            true,
+        ),
+        Some("-h") | Some("--help") => {
+            println!("{}", USAGE);
+            0
+        }
+        Some("-f") => match &args[1..] {
+            [path] => script(path),
+            _ => {
+                eprintln!("{}", USAGE);
+                2
+            }
+        },
+        Some(_) => {
+            let mut calc = Calculator::new();
+            match run_line(&mut io::stdout(), &mut calc, &args.join(" ")) {
+                Ok(()) => 0,
+                Err(e) => {
+                    eprintln!("calc: {}", e.message(None));
+                    1
+                }
+            }
+        }
+    };
+    process::exit(code)
+}
+
+/// Evaluates the file at `path` line by line, stopping at the first error.
+/// Returns the exit code.
+fn script(path: &str) -> i32 {
+    match fs::File::open(path) {
+        Ok(file) => run_lines(
+            &mut io::stdout(),
+            &mut io::stderr(),
+            BufReader::new(file).lines(),
+            path,
+            false,
+        ),
+        Err(e) => {
+            eprintln!("calc: {}: {}", path, e);
+            1
+        }
+    }
+}
+
+/// Evaluates lines from a script, or from another program through a pipe,
+/// without prompts or colors, to `out`. A statement left open at the end of
+/// a line, inside brackets or after an operator, goes on in the next.
+/// Errors go to `err` as `name:line:column: message`. Piped input goes on after errors
+/// if `keep_going`, as the program may still be writing. Returns the exit
+/// code, 1 after any error.
+fn run_lines<W: Write, E: Write>(
+    out: &mut W,
+    err: &mut E,
+    lines: impl Iterator<Item = io::Result<String>>,
+    name: &str,
+    keep_going: bool,
+) -> i32 {
     let mut calc = Calculator::new();
+    let mut code = 0;
+    // The lines of an open statement, and the number of the first.
+    let mut block = String::new();
+    let mut first = 1;
+    let mut lines = lines.enumerate().peekable();
+    while let Some((i, line)) = lines.next() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                let _ = writeln!(err, "calc: {}: {}", name, e);
+                return 1;
+            }
+        };
+        if block.is_empty() {
+            if line.trim() == "exit" {
+                break;
+            }
+            first = i + 1;
+        } else {
+            block.push('\n');
+        }
+        block.push_str(&line);
+        if !is_command(&block) && calc.needs_more(&block) && lines.peek().is_some() {
+            continue;
+        }
+        if let Err(e) = run_line(out, &mut calc, &block) {
+            let _ = writeln!(err, "{}", e.message(Some((name, first))));
+            if !keep_going {
+                return 1;
+            }
+            code = 1;
+        }
+        block.clear();
+    }
+    code
+}
+
+/// Why text given on the command line or in a script failed.
+struct LineError {
+    message: String,
+    /// Zero-based line within the text and one-based column of the error,
+    /// if it has a place.
+    at: Option<(usize, usize)>,
+}
+
+impl LineError {
+    /// The error as `name:line:column: message`, like compilers report
+    /// them, given the name of the text and the number of its first line.
+    fn message(&self, source: Option<(&str, usize)>) -> String {
+        match (source, self.at) {
+            (Some((name, first)), Some((l, c))) => {
+                format!("{}:{}:{}: {}", name, first + l, c, self.message)
+            }
+            (Some((name, first)), None) => format!("{}:{}: {}", name, first, self.message),
+            (None, Some((0, c))) => format!("column {}: {}", c, self.message),
+            (None, Some((l, c))) => format!("line {}, column {}: {}", l + 1, c, self.message),
+            (None, None) => self.message.clone(),
+        }
+    }
+}
+
+/// Runs text outside the interactive session, writing the value of each
+/// statement that has one to `out` on a line of its own. Commands change
+/// settings silently, and `help` writes the help as plain text.
+fn run_line<W: Write>(out: &mut W, calc: &mut Calculator, text: &str) -> Result<(), LineError> {
+    let failed = |e: io::Error| LineError {
+        message: e.to_string(),
+        at: None,
+    };
+    match text.trim() {
+        "reset" => {
+            calc.reset();
+            return Ok(());
+        }
+        "help" => return writeln!(out, "{}", help_text(calc)).map_err(failed),
+        line if line.starts_with(':') => {
+            return command(calc, line)
+                .map(|_| ())
+                .map_err(|message| LineError { message, at: None })
+        }
+        _ => {}
+    }
+    for result in calc.run_all(text) {
+        match result {
+            Ok(result) if result.is_empty() => {}
+            Ok(result) => writeln!(out, "{}", result).map_err(failed)?,
+            Err(e) => {
+                return Err(LineError {
+                    message: e.to_string(),
+                    at: Some(position(text, e.span().start)),
+                })
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The zero-based line and one-based column of the character `offset` in
+/// `text`.
+fn position(text: &str, offset: usize) -> (usize, usize) {
+    let before: Vec<char> = text.chars().take(offset).collect();
+    let line = before.iter().filter(|&&c| c == '\n').count();
+    let column = before.iter().rev().take_while(|&&c| c != '\n').count();
+    (line, column + 1)
+}
 
-    let stdin = io::stdin();
-    let mut handle_in = stdin.lock();
+/// Whether `line` is a command rather than statements.
+fn is_command(line: &str) -> bool {
+    let line = line.trim();
+    line.starts_with(':') || COMMANDS.contains(&line)
+}
+
+/// The read-eval-print loop on a terminal, `colored` unless colors are
+/// turned off.
+fn interactive(colored: bool) -> Result<(), io::Error> {
+    let mut calc = Calculator::new();
+    let mut editor = Editor::new();
 
     let stdout = io::stdout();
     let mut handle_out = stdout.lock();
 
     loop {
-        prompt(&mut handle_out)?;
-
-        input.clear();
-        match handle_in.read_line(&mut input) {
-            Ok(0) => {
+        let prompt = prompt(&calc, colored);
+        let mut session = Session {
+            calc: &mut calc,
+            colored,
+        };
+        match editor.read_line(&prompt, &mut session) {
+            Ok(None) => {
                 writeln!(handle_out)?;
                 handle_out.flush()?;
                 break;
             }
-            Ok(_) => match input.trim() {
+            Ok(Some(input)) => match input.trim() {
                 "exit" => break,
                 "reset" => calc.reset(),
-                "help" => help(&mut handle_out, &calc)?,
-                input => match calc.run(&input) {
-                    Ok(result) => {
-                        if !result.is_empty() {
-                            print_result(&mut handle_out, &result)?;
-                        }
-                    }
-                    Err(e) => print_err(&mut handle_out, &e)?,
+                "help" => help(&mut handle_out, &calc, colored)?,
+                line if line.starts_with(':') => match command(&mut calc, line) {
+                    Ok(result) => print_result(&mut handle_out, &result, colored)?,
+                    Err(e) => print_err(&mut handle_out, &e, colored)?,
+                },
+                _ => match read_rest(&mut editor, &mut calc, input, colored) {
+                    Ok(Some(input)) => run_input(&mut handle_out, &mut calc, &input, colored)?,
+                    Ok(None) => writeln!(handle_out)?,
+                    Err(e) => writeln!(handle_out, "stdin error: {}", e)?,
                 },
             },
             Err(e) => {
@@ -49,43 +269,431 @@ fn main() -> Result<(), io::Error> {
     Ok(())
 }
 
-fn prompt<W: Write>(w: &mut W) -> Result<(), io::Error> {
-    write!(
-        w,
-        "{}{}λ {}",
-        style::Bold,
-        color::Fg(color::Reset),
-        style::Reset
-    )?;
-    w.flush()?;
+/// `input` followed by the lines that complete its last statement, if it
+/// is left open. `None` if the input ends first.
+fn read_rest(
+    editor: &mut Editor,
+    calc: &mut Calculator,
+    mut input: String,
+    colored: bool,
+) -> io::Result<Option<String>> {
+    let prompt = continued_prompt(calc, colored);
+    while calc.needs_more(&input) {
+        let mut session = Session { calc, colored };
+        match editor.read_line(&prompt, &mut session)? {
+            Some(line) => {
+                input.push('\n');
+                input.push_str(&line);
+            }
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(input))
+}
 
+/// Runs the statements in `input` and prints their results, pointing at
+/// an error in the last line typed if nothing was printed after it.
+fn run_input<W: Write>(
+    w: &mut W,
+    calc: &mut Calculator,
+    input: &str,
+    colored: bool,
+) -> Result<(), io::Error> {
+    for (i, result) in calc.run_all(input).into_iter().enumerate() {
+        match result {
+            Ok(result) if result.is_empty() => {}
+            Ok(result) => print_result(w, &result, colored)?,
+            Err(e) => {
+                let span = e.span();
+                let (line, column) = position(input, span.start);
+                if i == 0 && line == input.matches('\n').count() {
+                    let indent = prompt_text(calc).chars().count() + column - 1;
+                    print_caret(w, indent, span.end - span.start, colored)?;
+                    print_err(w, &e.to_string(), colored)?;
+                } else {
+                    let e = LineError {
+                        message: e.to_string(),
+                        at: Some((line, column)),
+                    };
+                    print_err(w, &e.message(None), colored)?;
+                }
+            }
+        }
+    }
     Ok(())
 }
 
-fn print_result<W: Write>(w: &mut W, result: &str) -> Result<(), io::Error> {
-    writeln!(w, "{}=> {}\n", color::Fg(color::Green), result)?;
+/// The interactive session as the line editor sees it.
+struct Session<'a> {
+    calc: &'a mut Calculator,
+    colored: bool,
+}
+
+impl Helper for Session<'_> {
+    fn complete(&self, word: &str, first: bool) -> Vec<String> {
+        let calc = &self.calc;
+        let commands = if first { COMMANDS } else { &[] };
+        let mut names: Vec<String> = calc
+            .get_functions()
+            .into_iter()
+            .chain(calc.get_constants())
+            .chain(calc.get_variables())
+            .chain(commands.iter().copied())
+            .filter(|name| name.starts_with(word))
+            .map(str::to_owned)
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    fn hint(&self, name: &str) -> Option<String> {
+        let signature = self.calc.signature(name)?;
+        Some(format!(
+            "{}{}{}",
+            esc(self.colored, style::Faint),
+            signature,
+            esc(self.colored, style::Reset)
+        ))
+    }
+
+    fn highlight(&self, line: &str) -> String {
+        if self.colored && !is_command(line) {
+            highlight::highlight(self.calc, line)
+        } else {
+            line.to_owned()
+        }
+    }
+
+    fn preview(&mut self, line: &str) -> Option<String> {
+        if is_command(line) {
+            return None;
+        }
+        let value = self.calc.preview(line)?;
+        Some(format!(
+            "{}=> {}{}",
+            esc(self.colored, style::Faint),
+            value,
+            esc(self.colored, style::Reset)
+        ))
+    }
+}
+
+/// The prompt, with the angle mode so it is always visible.
+fn prompt_text(calc: &Calculator) -> String {
+    format!("{} λ ", calc.angle_mode())
+}
+
+/// The escape code `code` if `colored`, nothing otherwise.
+fn esc(colored: bool, code: impl fmt::Display) -> String {
+    if colored {
+        code.to_string()
+    } else {
+        String::new()
+    }
+}
+
+/// `prompt_text` in bold.
+fn prompt(calc: &Calculator, colored: bool) -> String {
+    bold(&prompt_text(calc), colored)
+}
+
+/// The prompt for the lines that complete a statement, as wide as the
+/// first.
+fn continued_prompt(calc: &Calculator, colored: bool) -> String {
+    let width = prompt_text(calc).chars().count();
+    bold(&format!("{:>1$} ", "...", width.max(4) - 1), colored)
+}
+
+fn bold(text: &str, colored: bool) -> String {
+    format!(
+        "{}{}{}{}",
+        esc(colored, style::Bold),
+        esc(colored, color::Fg(color::Reset)),
+        text,
+        esc(colored, style::Reset)
+    )
+}
+
+fn print_result<W: Write>(w: &mut W, result: &str, colored: bool) -> Result<(), io::Error> {
+    writeln!(
+        w,
+        "{}=> {}\n",
+        esc(colored, color::Fg(color::Green)),
+        result
+    )?;
     w.flush()?;
     Ok(())
 }
 
-fn print_err<W: Write>(w: &mut W, err: &str) -> Result<(), io::Error> {
-    writeln!(w, "{}=> {}\n", color::Fg(color::Red), err)?;
+fn print_err<W: Write>(w: &mut W, err: &str, colored: bool) -> Result<(), io::Error> {
+    writeln!(w, "{}=> {}\n", esc(colored, color::Fg(color::Red)), err)?;
     w.flush()?;
     Ok(())
 }
 
-fn help<W: Write>(w: &mut W, calc: &Calculator) -> Result<(), io::Error> {
-    write!(
+/// Underlines `len` characters from column `at` of the echoed input,
+/// counting the prompt.
+fn print_caret<W: Write>(w: &mut W, at: usize, len: usize, colored: bool) -> Result<(), io::Error> {
+    writeln!(
+        w,
+        "{}{}{}",
+        esc(colored, color::Fg(color::Red)),
+        " ".repeat(at),
+        "^".repeat(len.max(1))
+    )
+}
+
+/// Runs a `:name [value]` command that changes or shows a setting.
+fn command(calc: &mut Calculator, line: &str) -> Result<String, String> {
+    let mut args = line[1..].split_whitespace();
+    let name = args.next().unwrap_or("");
+    let value = args.next();
+
+    match name {
+        "implicit" => {
+            if let Some(v) = value {
+                calc.set_implicit_multiplication(parse_switch(v)?);
+            }
+            Ok(format!(
+                "implicit multiplication {}",
+                if calc.implicit_multiplication() {
+                    "on"
+                } else {
+                    "off"
+                }
+            ))
+        }
+        "exact" => {
+            if let Some(v) = value {
+                calc.set_exact(parse_switch(v)?);
+            }
+            Ok(format!(
+                "exact fractions {}",
+                if calc.exact() { "on" } else { "off" }
+            ))
+        }
+        "precision" => {
+            if let Some(v) = value {
+                calc.set_precision(parse_precision(v)?);
+            }
+            Ok(match calc.precision() {
+                Some(digits) => format!("precision {} digits", digits),
+                None => "precision off".to_owned(),
+            })
+        }
+        "polar" => {
+            if let Some(v) = value {
+                calc.set_polar(parse_switch(v)?);
+            }
+            Ok(format!(
+                "polar form {}",
+                if calc.polar() { "on" } else { "off" }
+            ))
+        }
+        "angle" => {
+            if let Some(v) = value {
+                calc.set_angle_mode(parse_angle_mode(v)?);
+            }
+            Ok(format!("angle mode {}", calc.angle_mode()))
+        }
+        "format" => {
+            if let Some(v) = value {
+                calc.set_format(parse_format(v, args.next())?);
+            }
+            Ok(format!("format {}", calc.format()))
+        }
+        "word" => {
+            if let Some(v) = value {
+                calc.set_word_size(parse_word_size(v)?);
+            }
+            Ok(match calc.word_size() {
+                Some(bits) => format!("word size {} bits", bits),
+                None => "word size off".to_owned(),
+            })
+        }
+        "wrap" => {
+            if let Some(v) = value {
+                calc.set_wrapping(parse_switch(v)?);
+            }
+            Ok(format!(
+                "wrap-around {}",
+                if calc.wrapping() { "on" } else { "off" }
+            ))
+        }
+        _ => Err(format!("unknown command: {}", line)),
+    }
+}
+
+fn parse_switch(value: &str) -> Result<bool, String> {
+    match value {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        v => Err(format!("expected 'on' or 'off', found '{}'", v)),
+    }
+}
+
+fn parse_angle_mode(value: &str) -> Result<AngleMode, String> {
+    match value {
+        "rad" => Ok(AngleMode::Radians),
+        "deg" => Ok(AngleMode::Degrees),
+        "grad" => Ok(AngleMode::Gradians),
+        v => Err(format!("expected 'rad', 'deg' or 'grad', found '{}'", v)),
+    }
+}
+
+/// Significant digits for `:precision`, or `None` for `off`.
+fn parse_precision(value: &str) -> Result<Option<NonZeroU32>, String> {
+    match value {
+        "off" => Ok(None),
+        v => match v.parse::<NonZeroU32>() {
+            Ok(digits) if digits.get() <= MAX_PRECISION => Ok(Some(digits)),
+            _ => Err(format!(
+                "expected 'off' or a number of digits up to {}, found '{}'",
+                MAX_PRECISION, v
+            )),
+        },
+    }
+}
+
+/// The format for `:format`, with `places` following `fixed`.
+fn parse_format(value: &str, places: Option<&str>) -> Result<Format, String> {
+    if value == "fixed" {
+        return match places.map(str::parse::<usize>) {
+            Some(Ok(places)) if places <= MAX_PRECISION as usize => Ok(Format::Fixed(places)),
+            _ => Err(format!(
+                "expected a number of decimal places up to {} after 'fixed'",
+                MAX_PRECISION
+            )),
+        };
+    }
+    Format::from_name(value).ok_or_else(|| {
+        format!(
+            "expected 'dec', 'sci', 'eng', 'si', 'hex', 'oct', 'bin' or 'fixed', found '{}'",
+            value
+        )
+    })
+}
+
+/// Bits for `:word`, or `None` for `off`.
+fn parse_word_size(value: &str) -> Result<Option<NonZeroU32>, String> {
+    match value {
+        "off" => Ok(None),
+        v => match v.parse::<NonZeroU32>() {
+            Ok(bits) if bits.get() <= MAX_WORD_SIZE => Ok(Some(bits)),
+            _ => Err(format!(
+                "expected 'off' or a number of bits up to {}, found '{}'",
+                MAX_WORD_SIZE, v
+            )),
+        },
+    }
+}
+
+fn help<W: Write>(w: &mut W, calc: &Calculator, colored: bool) -> Result<(), io::Error> {
+    writeln!(
         w,
-        "{}=> Constants: {}",
-        color::Fg(color::Green),
-        calc.get_constants().join(", ")
+        "{}=> {}",
+        esc(colored, color::Fg(color::Green)),
+        help_text(calc)
     )?;
-    write!(w, "   Functions: {}", calc.get_functions().join(", "))?;
-    write!(w, "   Variables: ans")?;
-    writeln!(w, "   Commands:  help, reset, exit")?;
     writeln!(w)?;
     w.flush()?;
 
     Ok(())
 }
+
+/// The constants, functions and commands there are.
+fn help_text(calc: &Calculator) -> String {
+    format!(
+        "Constants: {}   Functions: {}   Variables: ans   Commands:  help, reset, exit, :implicit [on|off], :exact [on|off],\n              :precision [digits|off], :polar [on|off],\n              :angle [rad|deg|grad], :format [dec|sci|eng|si|hex|oct|bin|fixed N],\n              :word [bits|off], :wrap [on|off]",
+        calc.get_constants().join(", "),
+        calc.get_functions().join(", ")
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// What `run_lines` writes to stdout and stderr for `text`, and its
+    /// exit code.
+    fn run(text: &str, keep_going: bool) -> (String, String, i32) {
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        let lines = text.lines().map(|l| Ok(l.to_owned()));
+        let code = run_lines(&mut out, &mut err, lines, "t.calc", keep_going);
+        (
+            String::from_utf8(out).unwrap(),
+            String::from_utf8(err).unwrap(),
+            code,
+        )
+    }
+
+    #[test]
+    fn lines() {
+        let (out, err, code) = run("w = 3; h = 4\nd = sqrt(w^2 +\n  h^2)\n\nd * 2", false);
+        assert_eq!(out, "w = 3\nh = 4\nd = 5\n10\n");
+        assert_eq!((err.as_str(), code), ("", 0));
+
+        let (out, _, code) = run(
+            "f(x) = [x,\n  x^2]\nf(3) # squared\n/* 1 +\n2 */ 7 /* \n*/",
+            false,
+        );
+        assert_eq!(out, "f(x) = [x, x ^ 2]\n[3, 9]\n7\n");
+        assert_eq!(code, 0);
+
+        // Commands are silent and never continue a statement.
+        let (out, _, _) = run(":word 8\n:format hex\n127 +\n1\nreset\n:wrap\nhelp", false);
+        assert!(out.starts_with("0x80\nConstants: E, INF, PI"), "{}", out);
+
+        let (out, _, code) = run("1\nexit\n2", false);
+        assert_eq!((out.as_str(), code), ("1\n", 0));
+    }
+
+    #[test]
+    fn errors() {
+        // Scripts stop at the first error, pipes go on.
+        let text = "1\nx = 2 *\n  (y + 1)\n3\n:word 0\n1 +";
+        let (out, err, code) = run(text, false);
+        assert_eq!((out.as_str(), code), ("1\n", 1));
+        assert_eq!(err, "t.calc:3:4: unknown variable: y\n");
+
+        let (out, err, code) = run(text, true);
+        assert_eq!((out.as_str(), code), ("1\n3\n", 1));
+        assert_eq!(
+            err.lines().collect::<Vec<_>>(),
+            vec![
+                "t.calc:3:4: unknown variable: y",
+                "t.calc:5: expected 'off' or a number of bits up to 1024, found '0'",
+                "t.calc:6:4: expected a number, variable, function call, '(' or '[', found end of input",
+            ]
+        );
+
+        let lines = vec![
+            Ok("1".to_owned()),
+            Err(io::Error::new(io::ErrorKind::Other, "gone")),
+        ];
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        assert_eq!(
+            run_lines(&mut out, &mut err, lines.into_iter(), "-", true),
+            1
+        );
+        assert_eq!(String::from_utf8(err).unwrap(), "calc: -: gone\n");
+    }
+
+    #[test]
+    fn messages() {
+        assert_eq!(position("1 + x", 4), (0, 5));
+        assert_eq!(position("a = [1,\n  2,\n  z]", 15), (2, 3));
+        assert_eq!(position("λ + μ", 4), (0, 5));
+
+        let e = |at| LineError {
+            message: "oops".to_owned(),
+            at,
+        };
+        assert_eq!(e(Some((1, 3))).message(Some(("f", 10))), "f:11:3: oops");
+        assert_eq!(e(None).message(Some(("f", 10))), "f:10: oops");
+        assert_eq!(e(Some((0, 3))).message(None), "column 3: oops");
+        assert_eq!(e(Some((1, 3))).message(None), "line 2, column 3: oops");
+        assert_eq!(e(None).message(None), "oops");
+    }
+}